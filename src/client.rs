@@ -0,0 +1,595 @@
+//! A client observing resources, RFC 7641.
+//!
+//! Like the server, the client does no I/O itself. [`CoapClient::observe`] returns the
+//! registration request to be sent, received messages are passed to
+//! [`CoapClient::handle_message_from`] and [`CoapClient::poll`] returns the messages that
+//! are due later, retransmissions and renewed registrations.
+//! Notifications are passed to the [`CoapNotificationHandler`] of their observation,
+//! notifications older than the last one are dropped by the freshness rule of RFC 7641 §3.4.
+
+use crate::exchange::{self, Backoff, CoapClock, CoapRandom, DEFAULT_MESSAGE_SIZE};
+use crate::message::header::{CoapHeader, CoapHeaderCode, CoapHeaderType};
+use crate::message::option::{CoapOptionNumbers, DEFAULT_MAX_OPTIONS, DEFAULT_OPTION_SIZE};
+use crate::message::{self, CoapMessage, CoapMessageRef, DEFAULT_PAYLOAD_SIZE};
+use crate::observe;
+use crate::CoapError;
+use core::net::SocketAddr;
+use heapless::{Deque, Vec};
+
+/// Default maximum number of observations of a client
+pub const DEFAULT_MAX_OBSERVATIONS: usize = 4;
+
+/// How long a notification is fresh without a Max-Age option in seconds, RFC 7252 §5.10.5
+pub const DEFAULT_MAX_AGE: u32 = 60;
+
+/// Receives the notifications of an observation.
+/// Closures taking a notification result are notification handlers, a [`Deque`]
+/// of messages queues copies of the notifications
+pub trait CoapNotificationHandler {
+    /// Handles a notification. A response without Observe option ends the observation,
+    /// it is the last one passed to the handler. An error ends the observation as well,
+    /// [`CoapError::Timeout`] if the registration request was not acknowledged
+    fn notify(&mut self, notification: Result<&CoapMessageRef<'_>, CoapError>);
+}
+
+impl<F> CoapNotificationHandler for F
+where
+    F: FnMut(Result<&CoapMessageRef<'_>, CoapError>),
+{
+    fn notify(&mut self, notification: Result<&CoapMessageRef<'_>, CoapError>) {
+        self(notification)
+    }
+}
+
+impl<
+        const MAX_OPTIONS: usize,
+        const OPTION_SIZE: usize,
+        const PAYLOAD_SIZE: usize,
+        const N: usize,
+    > CoapNotificationHandler for Deque<CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>, N>
+{
+    /// Queues a copy of the notification, the oldest one is dropped when the queue is full.
+    /// Notifications that do not fit into the messages and errors are dropped, an
+    /// observation that ended is no longer counted by [`CoapClient::get_observation_count`]
+    fn notify(&mut self, notification: Result<&CoapMessageRef<'_>, CoapError>) {
+        let message = match notification.and_then(CoapMessage::from_ref) {
+            Ok(message) => message,
+            Err(_) => return,
+        };
+        if self.is_full() {
+            self.pop_front();
+        }
+        let _ = self.push_back(message);
+    }
+}
+
+/// A resource observed by the client
+struct Observation<'a, const MESSAGE_SIZE: usize> {
+    peer: SocketAddr,
+    /// The encoded registration request carrying Observe 0
+    request: Vec<u8, MESSAGE_SIZE>,
+    handler: &'a mut dyn CoapNotificationHandler,
+    /// The sequence number and receive time of the freshest notification
+    last: Option<(u32, u64)>,
+    /// When the registration is renewed, once the last notification is no longer fresh
+    expires: u64,
+    /// The retransmission timing of a confirmable registration request while it is
+    /// not acknowledged
+    backoff: Option<Backoff>,
+}
+
+impl<'a, const MESSAGE_SIZE: usize> Observation<'a, MESSAGE_SIZE> {
+    /// Returns true if the observation was registered with the peer and the token
+    fn is(&self, peer: SocketAddr, token: &[u8]) -> bool {
+        self.peer == peer && message::encoded_token(&self.request) == token
+    }
+
+    /// Returns true if the registration request has the message id
+    fn has_message_id(&self, message_id: u16) -> bool {
+        self.request[2..4] == message_id.to_be_bytes()
+    }
+
+    /// Gives the registration request the message id and waits for a notification
+    /// until `now` plus [`DEFAULT_MAX_AGE`]. A confirmable request is retransmitted
+    /// until it is acknowledged, see [`Backoff::new`]
+    fn register(&mut self, message_id: u16, now: u64, random: u32) {
+        self.request[2..4].copy_from_slice(&message_id.to_be_bytes());
+        self.expires = now + u64::from(DEFAULT_MAX_AGE) * 1000;
+        let confirmable = CoapHeader::decode(&self.request)
+            .is_ok_and(|header| header.get_type() == CoapHeaderType::Confirmable);
+        self.backoff = confirmable.then(|| Backoff::new(now, random));
+    }
+}
+
+/// CoAP client observing resources of servers, see [`crate::client`].
+/// Takes a buffer the outgoing messages are encoded into.
+///
+/// Keeps up to `MAX_OBSERVATIONS` observations with registration requests of up to
+/// `MESSAGE_SIZE` bytes encoded, the message capacities are used for the requests
+pub struct CoapClient<
+    'a,
+    const MAX_OPTIONS: usize = DEFAULT_MAX_OPTIONS,
+    const OPTION_SIZE: usize = DEFAULT_OPTION_SIZE,
+    const PAYLOAD_SIZE: usize = DEFAULT_PAYLOAD_SIZE,
+    const MAX_OBSERVATIONS: usize = DEFAULT_MAX_OBSERVATIONS,
+    const MESSAGE_SIZE: usize = DEFAULT_MESSAGE_SIZE,
+> {
+    buffer: &'a mut [u8],
+    clock: Option<&'a dyn CoapClock>,
+    random: Option<&'a dyn CoapRandom>,
+    message_id: u16,
+    observations: Vec<Observation<'a, MESSAGE_SIZE>, MAX_OBSERVATIONS>,
+}
+
+impl<
+        'a,
+        const MAX_OPTIONS: usize,
+        const OPTION_SIZE: usize,
+        const PAYLOAD_SIZE: usize,
+        const MAX_OBSERVATIONS: usize,
+        const MESSAGE_SIZE: usize,
+    > CoapClient<'a, MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE, MAX_OBSERVATIONS, MESSAGE_SIZE>
+{
+    /// Creates a new CoAP client
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        CoapClient {
+            buffer,
+            clock: None,
+            random: None,
+            message_id: 0,
+            observations: Vec::new(),
+        }
+    }
+
+    /// Sets the clock timing the retransmissions, the freshness of notifications
+    /// and the renewal of registrations. Without a clock the time stands still
+    pub fn set_clock(&mut self, clock: &'a dyn CoapClock) {
+        self.clock = Some(clock);
+    }
+
+    /// Sets the source of the random numbers that spread the retransmission timeouts,
+    /// RFC 7252 §4.2. Without one they are derived from the time and the message id
+    pub fn set_random(&mut self, random: &'a dyn CoapRandom) {
+        self.random = Some(random);
+    }
+
+    /// Sets the message id of the next message the client sends.
+    /// Start with a random value, RFC 7252 §4.4
+    pub fn set_message_id(&mut self, message_id: u16) {
+        self.message_id = message_id;
+    }
+
+    /// Returns the number of observations
+    pub fn get_observation_count(&self) -> usize {
+        self.observations.len()
+    }
+
+    /// Observes a resource of the peer with a GET or FETCH request, RFC 7641 §3.1,
+    /// other methods are rejected with [`CoapError::MethodNotObservable`].
+    /// The request gets the Observe option 0 and the next message id, its token
+    /// identifies the observation and replaces one with the same peer and token.
+    /// Returns the registration request to be sent to the peer, a confirmable one is
+    /// retransmitted by [`CoapClient::poll`] until it is acknowledged.
+    /// The notifications are passed to the handler
+    pub fn observe(
+        &mut self,
+        peer: SocketAddr,
+        request: CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>,
+        handler: &'a mut dyn CoapNotificationHandler,
+    ) -> Result<&[u8], CoapError> {
+        if !matches!(
+            request.header.get_code(),
+            CoapHeaderCode::GET | CoapHeaderCode::FETCH
+        ) {
+            return Err(CoapError::MethodNotObservable);
+        }
+        let mut request = request;
+        request
+            .get_options_mut()
+            .replace(CoapOptionNumbers::Observe, &[])?;
+        // The observation it replaces is kept until the request is ready
+        let existing = self
+            .observations
+            .iter()
+            .position(|observation| observation.is(peer, request.get_token()));
+        if existing.is_none() && self.observations.is_full() {
+            return Err(CoapError::CapacityExceeded {
+                capacity: MAX_OBSERVATIONS,
+            });
+        }
+        let length = request.encode_into(self.buffer)?;
+        let request =
+            Vec::from_slice(&self.buffer[..length]).map_err(|_| CoapError::CapacityExceeded {
+                capacity: MESSAGE_SIZE,
+            })?;
+        let mut observation = Observation {
+            peer,
+            request,
+            handler,
+            last: None,
+            expires: 0,
+            backoff: None,
+        };
+        let now = self.now();
+        let random = self.random(now);
+        observation.register(self.next_message_id(), now, random);
+        self.buffer[..length].copy_from_slice(&observation.request);
+        match existing {
+            Some(index) => self.observations[index] = observation,
+            // Checked to have room above
+            None => {
+                let _ = self.observations.push(observation);
+            }
+        }
+        Ok(&self.buffer[..length])
+    }
+
+    /// Cancels the observation registered with the peer and the token, RFC 7641 §3.6.
+    /// Returns the GET or FETCH request with Observe 1 to be sent to the peer.
+    /// The observation is forgotten right away, if the request is lost the next
+    /// notification is rejected with a reset
+    pub fn cancel(&mut self, peer: SocketAddr, token: &[u8]) -> Result<&[u8], CoapError> {
+        let index = self
+            .observations
+            .iter()
+            .position(|observation| observation.is(peer, token))
+            .ok_or(CoapError::UnknownObservation)?;
+        let observation = self.observations.swap_remove(index);
+        let registration = CoapMessageRef::decode(&observation.request)?;
+        let mut request: CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE> =
+            CoapMessage::from_ref(&registration)?;
+        request
+            .get_options_mut()
+            .replace(CoapOptionNumbers::Observe, &[1])?;
+        request.header = self.next_header(request.header)?;
+        let length = request.encode_into(self.buffer)?;
+        Ok(&self.buffer[..length])
+    }
+
+    /// Handles a message received from the peer.
+    /// Returns the message to be sent back, empty if there is nothing to send.
+    ///
+    /// Fresh notifications are passed to the handler of their observation, a response
+    /// without Observe option or with an error code ends the observation.
+    /// Confirmable notifications are acknowledged, those with an unknown token are
+    /// rejected with a reset to cancel the observation at the server, RFC 7641 §3.6
+    pub fn handle_message_from(
+        &mut self,
+        peer: SocketAddr,
+        msg: &[u8],
+    ) -> Result<&[u8], CoapError> {
+        let msg = CoapMessageRef::decode(msg)?;
+        let t = msg.header.get_type();
+        let code = msg.header.get_code();
+        let message_id = msg.header.get_message_id();
+        if code.is_empty() {
+            return match t {
+                CoapHeaderType::Acknowledgement | CoapHeaderType::Reset => {
+                    self.acknowledged(peer, message_id, t == CoapHeaderType::Reset);
+                    Ok(&[])
+                }
+                // CoAP ping
+                CoapHeaderType::Confirmable => self.empty(CoapHeaderType::Reset, message_id),
+                CoapHeaderType::NonConfirmable => Err(CoapError::InvalidEmptyMessage),
+            };
+        }
+        let index = self
+            .observations
+            .iter()
+            .position(|observation| observation.is(peer, msg.get_token()));
+        let index = match index {
+            Some(index) if code.is_response() => index,
+            // A piggybacked response to a request that is forgotten needs no answer
+            _ if t == CoapHeaderType::Acknowledgement => return Ok(&[]),
+            _ => return self.empty(CoapHeaderType::Reset, message_id),
+        };
+
+        let now = self.now();
+        let observe = msg
+            .get_options()
+            .find(|option| option.get_option_number() == CoapOptionNumbers::Observe)
+            .and_then(|option| option.get_uint().ok());
+        let observation = &mut self.observations[index];
+        observation.backoff = None;
+        match observe {
+            Some(sequence) if code.is_success() => {
+                let fresh = observation.last.is_none_or(|(last, received)| {
+                    observe::is_fresh(last, received, sequence, now)
+                });
+                if fresh {
+                    let max_age = msg
+                        .get_options()
+                        .find(|option| option.get_option_number() == CoapOptionNumbers::MaxAge)
+                        .and_then(|option| option.get_uint().ok())
+                        .unwrap_or(DEFAULT_MAX_AGE);
+                    observation.last = Some((sequence, now));
+                    observation.expires = now + u64::from(max_age) * 1000;
+                    observation.handler.notify(Ok(&msg));
+                }
+            }
+            _ => {
+                let observation = self.observations.swap_remove(index);
+                observation.handler.notify(Ok(&msg));
+            }
+        }
+        match t {
+            CoapHeaderType::Confirmable => self.empty(CoapHeaderType::Acknowledgement, message_id),
+            _ => Ok(&[]),
+        }
+    }
+
+    /// Returns the next message to be sent to a peer, call it regularly until it returns `None`.
+    ///
+    /// Confirmable registration requests whose acknowledgement timed out are sent again.
+    /// After [`crate::exchange::MAX_RETRANSMIT`] retransmissions the observation ends,
+    /// its handler gets [`CoapError::Timeout`].
+    /// Observations are registered again once the last notification is no longer fresh
+    /// by its Max-Age, RFC 7641 §3.3.1, or no notification arrived in [`DEFAULT_MAX_AGE`]
+    /// after registering
+    pub fn poll(&mut self) -> Option<(SocketAddr, &[u8])> {
+        let now = self.now();
+        let mut index = 0;
+        while index < self.observations.len() {
+            let observation = &mut self.observations[index];
+            let peer = observation.peer;
+            if let Some(backoff) = &mut observation.backoff {
+                if !backoff.is_due(now) {
+                    index += 1;
+                    continue;
+                }
+                if backoff.retransmit(now) {
+                    let length = observation.request.len();
+                    if let Some(buffer) = self.buffer.get_mut(..length) {
+                        buffer.copy_from_slice(&observation.request);
+                        return Some((peer, &self.buffer[..length]));
+                    }
+                }
+                let observation = self.observations.swap_remove(index);
+                observation.handler.notify(Err(CoapError::Timeout));
+                continue;
+            }
+            if now >= observation.expires {
+                let message_id = self.next_message_id();
+                let random = self.random(now);
+                let observation = &mut self.observations[index];
+                observation.register(message_id, now, random);
+                let length = observation.request.len();
+                if let Some(buffer) = self.buffer.get_mut(..length) {
+                    buffer.copy_from_slice(&observation.request);
+                    return Some((peer, &self.buffer[..length]));
+                }
+            }
+            index += 1;
+        }
+        None
+    }
+
+    /// Stops retransmitting the registration request acknowledged or reset by the message.
+    /// A reset ends the observation
+    fn acknowledged(&mut self, peer: SocketAddr, message_id: u16, reset: bool) {
+        self.observations.retain_mut(|observation| {
+            let answered = observation.backoff.is_some()
+                && observation.peer == peer
+                && observation.has_message_id(message_id);
+            if answered {
+                observation.backoff = None;
+            }
+            !(answered && reset)
+        });
+    }
+
+    /// Returns the next message id
+    fn next_message_id(&mut self) -> u16 {
+        let message_id = self.message_id;
+        self.message_id = self.message_id.wrapping_add(1);
+        message_id
+    }
+
+    /// Returns the header with the next message id
+    fn next_header(&mut self, header: CoapHeader) -> Result<CoapHeader, CoapError> {
+        CoapHeader::new(
+            header.get_type(),
+            header.get_tkl(),
+            header.get_code(),
+            self.next_message_id(),
+        )
+    }
+
+    /// Encodes an empty acknowledgement or reset of the message into the buffer
+    fn empty(&mut self, t: CoapHeaderType, message_id: u16) -> Result<&[u8], CoapError> {
+        let header = CoapHeader::new(t, 0, CoapHeaderCode::EMPTY, message_id)?;
+        let message: CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE> =
+            CoapMessage::from_header(header, &[])?;
+        let length = message.encode_into(self.buffer)?;
+        Ok(&self.buffer[..length])
+    }
+
+    /// Returns a random number for the retransmission timeout of a message sent at `now`
+    fn random(&self, now: u64) -> u32 {
+        match self.random {
+            Some(random) => random.random(),
+            None => exchange::fallback_random(now, self.message_id),
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.clock.map_or(0, |clock| clock.now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::*;
+    use crate::message::option::CoapOption;
+    use core::cell::Cell;
+
+    fn server() -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], 5683))
+    }
+
+    #[test]
+    fn observe() {
+        let mut notifications: Deque<CoapMessage, 4> = Deque::new();
+        let now = Cell::new(0);
+        let clock = || now.get();
+        let random = || 0;
+        let mut buffer: [u8; 64] = [0; 64];
+        let mut client: CoapClient = CoapClient::new(&mut buffer);
+        client.set_clock(&clock);
+        client.set_random(&random);
+        client.set_message_id(0x100);
+
+        let request = CoapMessage::get()
+            .path("temp")
+            .token(&[7])
+            .confirmable()
+            .build()
+            .unwrap();
+        let registration = [0x41, 1, 1, 0, 7, 0x60, 0x54, b't', b'e', b'm', b'p'];
+        let resp = client
+            .observe(server(), request, &mut notifications)
+            .unwrap();
+        assert_eq!(resp, &registration[..]);
+        assert_eq!(client.get_observation_count(), 1);
+
+        // Retransmitted until acknowledged
+        now.set(2000);
+        assert_eq!(client.poll(), Some((server(), &registration[..])));
+        let ack = [0x60, 0, 1, 0];
+        assert_eq!(client.handle_message_from(server(), &ack).unwrap(), &[][..]);
+        now.set(10_000);
+        assert_eq!(client.poll(), None);
+
+        // Notifications are acknowledged, stale ones are dropped
+        let notification = [0x41, 69, 0, 9, 7, 0x61, 5, 0x81, 30, 0xff, b'1'];
+        let resp = client.handle_message_from(server(), &notification).unwrap();
+        assert_eq!(resp, &[0x60, 0, 0, 9][..]);
+        let stale = [0x51, 69, 0, 10, 7, 0x61, 4, 0xff, b'0'];
+        let resp = client.handle_message_from(server(), &stale).unwrap();
+        assert_eq!(resp, &[][..]);
+        let fresh = [0x51, 69, 0, 11, 7, 0x61, 6, 0x81, 30, 0xff, b'2'];
+        client.handle_message_from(server(), &fresh).unwrap();
+        drop(client);
+        assert_eq!(notifications.len(), 2);
+        assert_eq!(notifications.pop_front().unwrap().get_payload(), b"1");
+        assert_eq!(notifications.pop_front().unwrap().get_payload(), b"2");
+    }
+
+    #[test]
+    fn renew_and_cancel() {
+        let payloads = Cell::new(0);
+        let mut count = |_: Result<&CoapMessageRef, CoapError>| payloads.set(payloads.get() + 1);
+        let ended = Cell::new(false);
+        let mut end = |response: Result<&CoapMessageRef, CoapError>| {
+            let code = response.unwrap().get_header().get_code();
+            assert_eq!(code, CoapHeaderCode::NotFound);
+            ended.set(true);
+        };
+        let now = Cell::new(0);
+        let clock = || now.get();
+        let mut buffer: [u8; 64] = [0; 64];
+        let mut client: CoapClient = CoapClient::new(&mut buffer);
+        client.set_clock(&clock);
+
+        let request = CoapMessage::get()
+            .path("temp")
+            .token(&[7])
+            .option(CoapOption::uint(CoapOptionNumbers::Observe, 1).unwrap())
+            .build()
+            .unwrap();
+        let resp = client.observe(server(), request, &mut count).unwrap();
+        assert_eq!(
+            resp,
+            &[0x51, 1, 0, 0, 7, 0x60, 0x54, b't', b'e', b'm', b'p'][..]
+        );
+
+        // Registered again when Max-Age runs out
+        let notification = [0x51, 69, 0, 9, 7, 0x61, 5, 0x81, 30];
+        client.handle_message_from(server(), &notification).unwrap();
+        now.set(29_999);
+        assert_eq!(client.poll(), None);
+        now.set(30_000);
+        let registration = [0x51, 1, 0, 1, 7, 0x60, 0x54, b't', b'e', b'm', b'p'];
+        assert_eq!(client.poll(), Some((server(), &registration[..])));
+        assert_eq!(client.poll(), None);
+
+        // An older sequence number is fresh after 128 seconds
+        now.set(200_000);
+        let notification = [0x51, 69, 0, 10, 7, 0x61, 1];
+        client.handle_message_from(server(), &notification).unwrap();
+        assert_eq!(payloads.get(), 2);
+
+        // Cancelled with Observe 1
+        let cancel = [0x51, 1, 0, 2, 7, 0x61, 1, 0x54, b't', b'e', b'm', b'p'];
+        assert_eq!(client.cancel(server(), &[7]).unwrap(), &cancel[..]);
+        assert_eq!(
+            client.cancel(server(), &[7]),
+            Err(CoapError::UnknownObservation)
+        );
+        // Notifications of unknown observations are reset
+        let notification = [0x41, 69, 0, 11, 7, 0x61, 2];
+        let resp = client.handle_message_from(server(), &notification).unwrap();
+        assert_eq!(resp, &[0x70, 0, 0, 11][..]);
+
+        // A response without Observe ends the observation
+        let request = CoapMessage::get().path("temp").token(&[8]).build().unwrap();
+        client.observe(server(), request, &mut end).unwrap();
+        let response = [0x51, 132, 0, 12, 8];
+        client.handle_message_from(server(), &response).unwrap();
+        assert_eq!(client.get_observation_count(), 0);
+        assert!(ended.get());
+    }
+
+    #[test]
+    fn observe_errors() {
+        let failure = Cell::new(None);
+        let mut fail = |notification: Result<&CoapMessageRef, CoapError>| {
+            failure.set(notification.err());
+        };
+        let now = Cell::new(0);
+        let clock = || now.get();
+        let random = || 0;
+        let mut buffer: [u8; 64] = [0; 64];
+        let mut client: CoapClient = CoapClient::new(&mut buffer);
+        client.set_clock(&clock);
+        client.set_random(&random);
+
+        // Only GET and FETCH can observe, RFC 7641 §2
+        let mut ignore = |_: Result<&CoapMessageRef, CoapError>| {};
+        let request = CoapMessage::post().path("temp").build().unwrap();
+        assert_eq!(
+            client.observe(server(), request, &mut ignore),
+            Err(CoapError::MethodNotObservable)
+        );
+
+        // The handler learns that the registration was never acknowledged
+        let request = CoapMessage::get()
+            .path("temp")
+            .token(&[7])
+            .confirmable()
+            .build()
+            .unwrap();
+        client.observe(server(), request, &mut fail).unwrap();
+        let mut retransmissions = 0;
+        for _ in 0..100 {
+            now.set(now.get() + 1000);
+            if client.poll().is_some() {
+                retransmissions += 1;
+            }
+        }
+        assert_eq!(retransmissions, 4);
+        assert_eq!(client.get_observation_count(), 0);
+
+        // A registration that does not fit keeps the observation it would replace
+        let mut keep = |_: Result<&CoapMessageRef, CoapError>| {};
+        let request = CoapMessage::get().path("hum").token(&[8]).build().unwrap();
+        client.observe(server(), request, &mut keep).unwrap();
+        let mut replace = |_: Result<&CoapMessageRef, CoapError>| {};
+        let long = "0123456789012345678901234567890123456789012345678901234567890123456789";
+        let request = CoapMessage::get().path(long).token(&[8]).build().unwrap();
+        assert!(client.observe(server(), request, &mut replace).is_err());
+        assert_eq!(client.get_observation_count(), 1);
+        drop(client);
+        assert_eq!(failure.get(), Some(CoapError::Timeout));
+    }
+}
@@ -0,0 +1,199 @@
+//! Errors returned by the message codec and the server
+
+use core::fmt;
+
+/// Errors returned by the message codec and the server.
+/// Offsets are byte offsets into the message being decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoapError {
+    /// The message is shorter than the 4 byte header
+    TruncatedHeader {
+        /// Length of the message
+        length: usize,
+    },
+    /// Unsupported CoAP version
+    WrongVersion(u8),
+    /// The token length is 9-15, which is reserved, or the token is longer than 8 bytes
+    BadTokenLength {
+        /// The token length
+        length: usize,
+    },
+    /// The message ends before the token does
+    TruncatedToken {
+        /// Offset of the first token byte
+        offset: usize,
+    },
+    /// An empty message (code 0.00) carries a token, options or a payload
+    InvalidEmptyMessage,
+    /// A request carried in an Acknowledgement or Reset message
+    InvalidMessageType,
+    /// The option delta uses the reserved value 15, its extended bytes are missing
+    /// or the resulting option number is larger than 65535
+    BadOptionDelta {
+        /// Offset of the option
+        offset: usize,
+    },
+    /// The option length uses the reserved value 15,
+    /// or the option runs past the end of the message
+    BadOptionLength {
+        /// Offset of the option
+        offset: usize,
+    },
+    /// Options have to be encoded in option number order
+    OptionsOutOfOrder,
+    /// The message carries a critical option that is not recognized
+    UnrecognizedCriticalOption(u16),
+    /// The option value length is outside of the range allowed for the option, RFC 7252 §5.10
+    OptionLengthOutOfRange {
+        /// The option number
+        number: u16,
+        /// Length of the value
+        length: usize,
+    },
+    /// The option value does not match the option format,
+    /// a string that is not UTF-8 or an integer longer than 4 bytes
+    InvalidOptionValue(u16),
+    /// A payload marker that is not followed by a payload
+    PayloadMarkerWithoutPayload {
+        /// Offset of the payload marker
+        offset: usize,
+    },
+    /// A message code outside of the c.dd range or not in c.dd notation
+    InvalidCode,
+    /// A URI that is not an absolute CoAP URI or has an invalid percent-encoding
+    InvalidUri,
+    /// A resource path template with a misplaced `**` or a malformed `{name}` segment
+    InvalidPathTemplate,
+    /// A link format document that is not UTF-8 or does not follow RFC 6690
+    InvalidLinkFormat {
+        /// Offset of the error in the document
+        offset: usize,
+    },
+    /// A separate response for an exchange that is not deferred or was answered already
+    UnknownExchange,
+    /// A cancellation of an observation that is not registered
+    UnknownObservation,
+    /// An observation requested with a method other than GET or FETCH, RFC 7641 §2
+    MethodNotObservable,
+    /// A confirmable message was not acknowledged after MAX_RETRANSMIT retransmissions
+    Timeout,
+    /// A block option with the reserved size exponent 7 or a block number longer than 20 bits
+    InvalidBlock,
+    /// The buffer is too small to hold the encoded message
+    BufferTooSmall {
+        /// Number of bytes needed
+        needed: usize,
+        /// Number of bytes available in the buffer
+        available: usize,
+    },
+    /// A fixed capacity, for example the number of options or resources, is exceeded
+    CapacityExceeded {
+        /// The capacity that was exceeded
+        capacity: usize,
+    },
+}
+
+impl CoapError {
+    /// Moves the offset of an error found in a part of a message
+    /// to be relative to the start of the message
+    pub(crate) fn at_offset(self, base: usize) -> Self {
+        match self {
+            CoapError::TruncatedToken { offset } => CoapError::TruncatedToken {
+                offset: base + offset,
+            },
+            CoapError::BadOptionDelta { offset } => CoapError::BadOptionDelta {
+                offset: base + offset,
+            },
+            CoapError::BadOptionLength { offset } => CoapError::BadOptionLength {
+                offset: base + offset,
+            },
+            CoapError::PayloadMarkerWithoutPayload { offset } => {
+                CoapError::PayloadMarkerWithoutPayload {
+                    offset: base + offset,
+                }
+            }
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for CoapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoapError::TruncatedHeader { length } => {
+                write!(f, "message of {} bytes is shorter than the header", length)
+            }
+            CoapError::WrongVersion(version) => write!(f, "unsupported CoAP version {}", version),
+            CoapError::BadTokenLength { length } => write!(f, "bad token length {}", length),
+            CoapError::TruncatedToken { offset } => {
+                write!(f, "message ends before the token at byte {}", offset)
+            }
+            CoapError::InvalidEmptyMessage => write!(f, "empty message is not empty"),
+            CoapError::InvalidMessageType => {
+                write!(f, "request in an acknowledgement or reset message")
+            }
+            CoapError::BadOptionDelta { offset } => {
+                write!(f, "bad option delta at byte {}", offset)
+            }
+            CoapError::BadOptionLength { offset } => {
+                write!(f, "bad option length at byte {}", offset)
+            }
+            CoapError::OptionsOutOfOrder => write!(f, "options are not in option number order"),
+            CoapError::UnrecognizedCriticalOption(number) => {
+                write!(f, "unrecognized critical option {}", number)
+            }
+            CoapError::OptionLengthOutOfRange { number, length } => write!(
+                f,
+                "option {} value length {} is out of range",
+                number, length
+            ),
+            CoapError::InvalidOptionValue(number) => {
+                write!(f, "invalid value for option {}", number)
+            }
+            CoapError::PayloadMarkerWithoutPayload { offset } => {
+                write!(f, "payload marker without payload at byte {}", offset)
+            }
+            CoapError::InvalidCode => write!(f, "invalid message code"),
+            CoapError::InvalidUri => write!(f, "invalid CoAP URI"),
+            CoapError::InvalidPathTemplate => write!(f, "invalid resource path template"),
+            CoapError::InvalidLinkFormat { offset } => {
+                write!(f, "invalid link format at byte {}", offset)
+            }
+            CoapError::UnknownExchange => write!(f, "unknown exchange"),
+            CoapError::UnknownObservation => write!(f, "unknown observation"),
+            CoapError::MethodNotObservable => write!(f, "method cannot observe a resource"),
+            CoapError::Timeout => write!(f, "message was not acknowledged"),
+            CoapError::InvalidBlock => write!(f, "invalid block option value"),
+            CoapError::BufferTooSmall { needed, available } => write!(
+                f,
+                "buffer too small, {} bytes needed but {} available",
+                needed, available
+            ),
+            CoapError::CapacityExceeded { capacity } => {
+                write!(f, "capacity of {} exceeded", capacity)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::*;
+    use heapless::String;
+
+    #[test]
+    fn display() {
+        let mut buf: String<64> = String::new();
+        let e = CoapError::BadOptionDelta { offset: 2 }.at_offset(4);
+        fmt::write(&mut buf, format_args!("{}", e)).unwrap();
+        assert_eq!(buf, "bad option delta at byte 6");
+
+        let mut buf: String<64> = String::new();
+        let e = CoapError::BufferTooSmall {
+            needed: 10,
+            available: 4,
+        };
+        fmt::write(&mut buf, format_args!("{}", e)).unwrap();
+        assert_eq!(buf, "buffer too small, 10 bytes needed but 4 available");
+    }
+}
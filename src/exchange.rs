@@ -0,0 +1,371 @@
+//! Message exchanges that outlive the handling of a request.
+//!
+//! A handler that cannot answer right away defers the response, RFC 7252 §5.2.2.
+//! The server acknowledges the request with an empty ACK and the application sends
+//! the separate response later with [`crate::CoapServer::respond`].
+//! Confirmable messages sent by the server are retransmitted with an exponential
+//! back-off until they are acknowledged, RFC 7252 §4.2, driven by a [`CoapClock`].
+//! The initial timeout is randomized by a [`CoapRandom`]. Without a clock the time
+//! stands still, nothing is retransmitted or given up and unacknowledged messages keep
+//! their room until they are acknowledged.
+//!
+//! Requests are processed once only, RFC 7252 §4.5. A duplicate of a confirmable
+//! request is answered with the response to the first copy, a duplicate of a
+//! non-confirmable one is ignored, as long as the first copy is remembered. A request
+//! whose response is too long to be kept is forgotten, a duplicate is handled again.
+
+use crate::message::header::{CoapHeaderCode, CoapHeaderType};
+use crate::message::{self, CoapToken};
+use crate::CoapError;
+use core::net::SocketAddr;
+use heapless::Vec;
+
+/// Default maximum number of deferred requests and of unacknowledged messages of a server
+pub const DEFAULT_MAX_EXCHANGES: usize = 4;
+
+/// Time to wait for the acknowledgement of a confirmable message before the
+/// first retransmission in milliseconds, ACK_TIMEOUT of RFC 7252 §4.8.
+/// The initial timeout is a random duration between ACK_TIMEOUT and ACK_TIMEOUT times
+/// ACK_RANDOM_FACTOR, 1.5
+pub const ACK_TIMEOUT: u64 = 2000;
+
+/// Number of retransmissions of a confirmable message, MAX_RETRANSMIT of RFC 7252 §4.8
+pub const MAX_RETRANSMIT: u8 = 4;
+
+/// Default maximum length of an encoded message kept to be sent again, room for a
+/// payload of [`crate::DEFAULT_PAYLOAD_SIZE`] bytes and a few options
+pub const DEFAULT_MESSAGE_SIZE: usize = 320;
+
+/// Default number of requests remembered to detect duplicates
+pub const DEFAULT_DEDUPLICATION_SIZE: usize = 4;
+
+/// Default maximum length of an encoded response kept to answer duplicates
+pub const DEFAULT_RESPONSE_SIZE: usize = DEFAULT_MESSAGE_SIZE;
+
+/// How long the message id of a confirmable message is remembered in milliseconds,
+/// EXCHANGE_LIFETIME of RFC 7252 §4.8.2
+pub const EXCHANGE_LIFETIME: u64 = 247_000;
+
+/// How long the message id of a non-confirmable message is remembered in milliseconds,
+/// NON_LIFETIME of RFC 7252 §4.8.2
+pub const NON_LIFETIME: u64 = 145_000;
+
+/// Monotonic time source of a server, in milliseconds.
+/// Closures returning the time can be used as clocks
+pub trait CoapClock {
+    /// Returns the current time in milliseconds
+    fn now(&self) -> u64;
+}
+
+impl<F> CoapClock for F
+where
+    F: Fn() -> u64,
+{
+    fn now(&self) -> u64 {
+        self()
+    }
+}
+
+/// Source of random numbers for the retransmission timeouts, RFC 7252 §4.2.
+/// Closures returning a random number can be used
+pub trait CoapRandom {
+    /// Returns a random number
+    fn random(&self) -> u32;
+}
+
+impl<F> CoapRandom for F
+where
+    F: Fn() -> u32,
+{
+    fn random(&self) -> u32 {
+        self()
+    }
+}
+
+/// Mixes the time and the message id into a number for the timeout of a message
+/// when there is no [`CoapRandom`]. It is only as random as the message ids
+pub(crate) fn fallback_random(now: u64, message_id: u16) -> u32 {
+    let x = (now ^ u64::from(message_id) << 48).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    (x >> 32) as u32
+}
+
+/// Identifies a deferred request, the client it came from and its token.
+/// Returned by [`crate::CoapRequest::get_exchange`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoapExchange {
+    peer: Option<SocketAddr>,
+    token: CoapToken,
+}
+
+impl CoapExchange {
+    pub(crate) fn new(peer: Option<SocketAddr>, token: CoapToken) -> Self {
+        CoapExchange { peer, token }
+    }
+
+    /// Returns the address of the client, if the server was told
+    pub fn get_peer(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    /// Returns the token of the request
+    pub fn get_token(&self) -> &[u8] {
+        self.token.as_slice()
+    }
+}
+
+/// A request whose response was deferred by its handler
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Deferred {
+    pub(crate) exchange: CoapExchange,
+    /// The type of the separate response, the type of the request
+    pub(crate) t: CoapHeaderType,
+    /// The default response code of the request method
+    pub(crate) code: CoapHeaderCode,
+}
+
+/// The retransmission timing of a confirmable message, RFC 7252 §4.2
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Backoff {
+    retransmissions: u8,
+    timeout: u64,
+    due: u64,
+}
+
+impl Backoff {
+    /// Starts waiting for the acknowledgement of a message sent at `now`.
+    /// The random number picks the initial timeout between ACK_TIMEOUT and
+    /// ACK_TIMEOUT * 1.5
+    pub(crate) fn new(now: u64, random: u32) -> Self {
+        let timeout = ACK_TIMEOUT + u64::from(random) % (ACK_TIMEOUT / 2 + 1);
+        Backoff {
+            retransmissions: 0,
+            timeout,
+            due: now + timeout,
+        }
+    }
+
+    /// Returns true if the acknowledgement timed out at `now`
+    pub(crate) fn is_due(&self, now: u64) -> bool {
+        now >= self.due
+    }
+
+    /// Schedules the next retransmission, the timeout doubles every time.
+    /// Returns false once the message has been retransmitted MAX_RETRANSMIT times
+    pub(crate) fn retransmit(&mut self, now: u64) -> bool {
+        if self.retransmissions >= MAX_RETRANSMIT {
+            return false;
+        }
+        self.retransmissions += 1;
+        self.timeout *= 2;
+        self.due = now + self.timeout;
+        true
+    }
+}
+
+/// An encoded confirmable message waiting for its acknowledgement
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Transmission<const SIZE: usize> {
+    pub(crate) peer: Option<SocketAddr>,
+    message: Vec<u8, SIZE>,
+    pub(crate) backoff: Backoff,
+}
+
+impl<const SIZE: usize> Transmission<SIZE> {
+    /// Keeps the encoded message sent to the peer, see [`Backoff::new`].
+    /// Fails if the message is longer than `SIZE`
+    pub(crate) fn new(
+        peer: Option<SocketAddr>,
+        message: &[u8],
+        backoff: Backoff,
+    ) -> Result<Self, CoapError> {
+        let message =
+            Vec::from_slice(message).map_err(|_| CoapError::CapacityExceeded { capacity: SIZE })?;
+        Ok(Transmission {
+            peer,
+            message,
+            backoff,
+        })
+    }
+
+    /// Returns the encoded message
+    pub(crate) fn get_message(&self) -> &[u8] {
+        &self.message
+    }
+
+    /// Returns the token of the message
+    pub(crate) fn get_token(&self) -> &[u8] {
+        message::encoded_token(&self.message)
+    }
+
+    /// Returns true if the message is acknowledged or reset by the message
+    pub(crate) fn is_answered_by(&self, peer: Option<SocketAddr>, message_id: u16) -> bool {
+        self.peer == peer && self.message[2..4] == message_id.to_be_bytes()
+    }
+}
+
+/// The requests received recently, to detect duplicates by peer and message id.
+/// When full the oldest request is forgotten. Responses are kept encoded, a response
+/// longer than `RESPONSE_SIZE` is not kept but its request is still remembered
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Deduplication<const SIZE: usize, const RESPONSE_SIZE: usize> {
+    entries: Vec<Received<RESPONSE_SIZE>, SIZE>,
+}
+
+/// A request received recently and the encoded response sent to it
+#[derive(Debug, Clone, PartialEq)]
+struct Received<const RESPONSE_SIZE: usize> {
+    peer: Option<SocketAddr>,
+    message_id: u16,
+    received: u64,
+    expires: u64,
+    response: Option<Vec<u8, RESPONSE_SIZE>>,
+}
+
+impl<const SIZE: usize, const RESPONSE_SIZE: usize> Deduplication<SIZE, RESPONSE_SIZE> {
+    /// Looks up a request that was received before and has not expired at `now`.
+    /// Returns the response sent to it, `None` inside if nothing was sent
+    #[allow(clippy::option_option)]
+    pub(crate) fn get(
+        &self,
+        peer: Option<SocketAddr>,
+        message_id: u16,
+        now: u64,
+    ) -> Option<Option<&[u8]>> {
+        self.entries
+            .iter()
+            .find(|entry| entry.peer == peer && entry.message_id == message_id)
+            .filter(|entry| now < entry.expires)
+            .map(|entry| entry.response.as_deref())
+    }
+
+    /// Remembers a request received at `now` and the encoded response sent to it.
+    /// Confirmable requests are kept for EXCHANGE_LIFETIME, others for NON_LIFETIME
+    pub(crate) fn insert(
+        &mut self,
+        peer: Option<SocketAddr>,
+        t: CoapHeaderType,
+        message_id: u16,
+        now: u64,
+        response: Option<&[u8]>,
+    ) {
+        let lifetime = match t {
+            CoapHeaderType::Confirmable => EXCHANGE_LIFETIME,
+            _ => NON_LIFETIME,
+        };
+        self.entries.retain(|entry| {
+            now < entry.expires && !(entry.peer == peer && entry.message_id == message_id)
+        });
+        // A response too long to keep is dropped, the request is remembered all the same
+        let response = response.and_then(|response| Vec::from_slice(response).ok());
+        if self.entries.is_full() {
+            let oldest = self
+                .entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| entry.received)
+                .map(|(index, _)| index);
+            match oldest {
+                Some(index) => {
+                    self.entries.swap_remove(index);
+                }
+                // Nothing is remembered with a size of 0
+                None => return,
+            }
+        }
+        let _ = self.entries.push(Received {
+            peer,
+            message_id,
+            received: now,
+            expires: now + lifetime,
+            response,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exchange::*;
+    use crate::message::header::CoapHeader;
+    use crate::message::CoapMessage;
+
+    #[test]
+    fn back_off() {
+        let header =
+            CoapHeader::new(CoapHeaderType::Confirmable, 0, CoapHeaderCode::Content, 9).unwrap();
+        let message: CoapMessage = CoapMessage::from_header(header, &[]).unwrap();
+        let mut buffer = [0; 16];
+        let length = message.encode_into(&mut buffer).unwrap();
+        let mut transmission: Transmission<16> =
+            Transmission::new(None, &buffer[..length], Backoff::new(1000, 0)).unwrap();
+        assert!(transmission.is_answered_by(None, 9));
+        assert!(!transmission.is_answered_by(None, 8));
+        assert!(!transmission.backoff.is_due(2999));
+        assert_eq!(
+            Transmission::<2>::new(None, &buffer[..length], Backoff::new(0, 0)),
+            Err(CoapError::CapacityExceeded { capacity: 2 })
+        );
+
+        let mut now = 3000;
+        let mut timeout = ACK_TIMEOUT;
+        for _ in 0..MAX_RETRANSMIT {
+            assert!(transmission.backoff.is_due(now));
+            assert!(transmission.backoff.retransmit(now));
+            timeout *= 2;
+            assert!(!transmission.backoff.is_due(now + timeout - 1));
+            now += timeout;
+        }
+        assert!(transmission.backoff.is_due(now));
+        assert!(!transmission.backoff.retransmit(now));
+    }
+
+    #[test]
+    fn random_timeout() {
+        // The initial timeout is between ACK_TIMEOUT and ACK_TIMEOUT * 1.5
+        assert!(Backoff::new(0, 999).is_due(2999));
+        assert!(!Backoff::new(0, 1000).is_due(2999));
+        assert!(Backoff::new(0, 1000).is_due(3000));
+        assert!(!Backoff::new(0, 1001).is_due(1999));
+        let random = || 7;
+        assert_eq!(CoapRandom::random(&random), 7);
+        assert_ne!(fallback_random(0, 1), fallback_random(0, 2));
+    }
+
+    #[test]
+    fn deduplication() {
+        let response = [0x60, 69, 0, 1];
+        let peer = Some(SocketAddr::from(([10, 0, 0, 1], 5683)));
+        let mut cache: Deduplication<2, 4> = Deduplication::default();
+        cache.insert(None, CoapHeaderType::Confirmable, 1, 0, Some(&response));
+        cache.insert(peer, CoapHeaderType::NonConfirmable, 1, 10, None);
+        assert_eq!(cache.get(None, 1, 0), Some(Some(&response[..])));
+        assert_eq!(cache.get(peer, 1, 0), Some(None));
+        assert_eq!(cache.get(None, 2, 0), None);
+        assert_eq!(cache.get(peer, 1, NON_LIFETIME + 10), None);
+        assert_eq!(
+            cache.get(None, 1, EXCHANGE_LIFETIME - 1),
+            Some(Some(&response[..]))
+        );
+        assert_eq!(cache.get(None, 1, EXCHANGE_LIFETIME), None);
+
+        // The oldest entry makes room
+        cache.insert(None, CoapHeaderType::NonConfirmable, 2, 20, None);
+        assert_eq!(cache.get(None, 1, 20), None);
+        assert_eq!(cache.get(peer, 1, 20), Some(None));
+        assert_eq!(cache.get(None, 2, 20), Some(None));
+
+        // A response that does not fit is not kept, its request is
+        cache.insert(None, CoapHeaderType::Confirmable, 3, 30, Some(&[0; 5]));
+        assert_eq!(cache.get(None, 3, 30), Some(None));
+        assert_eq!(cache.get(peer, 1, 30), None);
+
+        let mut cache: Deduplication<0, 4> = Deduplication::default();
+        cache.insert(None, CoapHeaderType::Confirmable, 1, 0, None);
+        assert_eq!(cache.get(None, 1, 0), None);
+    }
+
+    #[test]
+    fn closure_clock() {
+        let clock = || 42;
+        assert_eq!(CoapClock::now(&clock), 42);
+    }
+}
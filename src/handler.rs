@@ -0,0 +1,384 @@
+//! Resource handlers.
+//!
+//! A [`CoapHandler`] is called with a [`CoapRequest`] view of the received request
+//! and a [`CoapResponse`] writer for the response. Closures and structs keeping state
+//! can both be handlers, plain `fn() -> u8` callbacks are adapted by [`CoapCallback`].
+//! Representations larger than a message are sent in blocks with
+//! [`CoapResponse::set_block_payload`], RFC 7959.
+
+use crate::exchange::CoapExchange;
+use crate::message::block::{self, CoapBlock};
+use crate::message::header::CoapHeaderCode;
+use crate::message::option::{encode_uint, CoapOption, CoapOptionNumbers};
+use crate::message::{CoapMediaType, CoapMessage, CoapMessageRef, CoapOptionIter, CoapToken};
+use crate::router::{path_segments, CoapPathParams, CoapQueryIter};
+use crate::CoapError;
+use core::convert::TryFrom;
+use core::net::SocketAddr;
+
+/// Handles the requests to a resource
+pub trait CoapHandler {
+    /// Handles a request.
+    /// The response starts out without options or payload and with the default code
+    /// of the method, 2.01 Created for POST, 2.02 Deleted for DELETE,
+    /// 2.04 Changed for PUT, PATCH and iPATCH and 2.05 Content otherwise.
+    /// Returning an error answers the request with 5.00 Internal Server Error
+    fn handle(
+        &mut self,
+        request: &CoapRequest<'_>,
+        response: &mut CoapResponse<'_>,
+    ) -> Result<(), CoapError>;
+}
+
+impl<F> CoapHandler for F
+where
+    F: FnMut(&CoapRequest<'_>, &mut CoapResponse<'_>) -> Result<(), CoapError>,
+{
+    fn handle(
+        &mut self,
+        request: &CoapRequest<'_>,
+        response: &mut CoapResponse<'_>,
+    ) -> Result<(), CoapError> {
+        self(request, response)
+    }
+}
+
+/// Adapts a `fn() -> u8` callback to a [`CoapHandler`].
+/// GET requests are answered with the returned byte as payload,
+/// or with 4.04 Not Found if it is 0. Other methods get 4.05 Method Not Allowed
+#[derive(Debug, Clone, Copy)]
+pub struct CoapCallback(pub fn() -> u8);
+
+impl CoapHandler for CoapCallback {
+    fn handle(
+        &mut self,
+        request: &CoapRequest<'_>,
+        response: &mut CoapResponse<'_>,
+    ) -> Result<(), CoapError> {
+        if request.get_code() != CoapHeaderCode::GET {
+            response.set_code(CoapHeaderCode::MethodNotAllowed);
+            return Ok(());
+        }
+        match (self.0)() {
+            0 => response.set_code(CoapHeaderCode::NotFound),
+            payload => response.set_payload(&[payload])?,
+        }
+        Ok(())
+    }
+}
+
+/// A request passed to a [`CoapHandler`], borrowed from the receive buffer
+#[derive(Debug, Clone)]
+pub struct CoapRequest<'a> {
+    message: CoapMessageRef<'a>,
+    peer: Option<SocketAddr>,
+    params: CoapPathParams<'a>,
+}
+
+impl<'a> CoapRequest<'a> {
+    pub(crate) fn new(
+        message: CoapMessageRef<'a>,
+        peer: Option<SocketAddr>,
+        params: CoapPathParams<'a>,
+    ) -> Self {
+        CoapRequest {
+            message,
+            peer,
+            params,
+        }
+    }
+
+    /// Returns the whole request message
+    pub fn get_message(&self) -> &CoapMessageRef<'a> {
+        &self.message
+    }
+
+    /// Returns the request method
+    pub fn get_code(&self) -> CoapHeaderCode {
+        self.message.get_header().get_code()
+    }
+
+    /// Returns the request token
+    pub fn get_token(&self) -> &'a [u8] {
+        self.message.get_token()
+    }
+
+    /// Returns an iterator over the request options
+    pub fn get_options(&self) -> CoapOptionIter<'a> {
+        self.message.get_options()
+    }
+
+    /// Returns the request payload
+    pub fn get_payload(&self) -> &'a [u8] {
+        self.message.get_payload()
+    }
+
+    /// Returns the value of the `{name}` segment of the resource path template
+    pub fn get_param(&self, name: &str) -> Option<&'a str> {
+        self.params.get(name)
+    }
+
+    /// Returns the parameters captured by the resource path template
+    pub fn get_params(&self) -> &CoapPathParams<'a> {
+        &self.params
+    }
+
+    /// Returns the request path segments matched by the trailing `**` of the resource
+    /// path template, nothing if it does not end with `**`
+    pub fn get_rest(&self) -> impl Iterator<Item = &'a [u8]> {
+        let (skip, take) = match self.params.get_rest_index() {
+            Some(index) => (index, usize::MAX),
+            None => (0, 0),
+        };
+        path_segments(self.message.get_options())
+            .skip(skip)
+            .take(take)
+    }
+
+    /// Returns an iterator over the Uri-Query options as `(key, value)` pairs
+    pub fn get_query(&self) -> CoapQueryIter<'a> {
+        CoapQueryIter::new(self.message.get_options())
+    }
+
+    /// Returns the value of the first Uri-Query option with the key.
+    /// `Some(None)` means the option has no `=`, for example `verbose`
+    pub fn get_query_param(&self, key: &str) -> Option<Option<&'a str>> {
+        self.get_query()
+            .find(|(param, _)| *param == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns the Block2 option, the block of the response the client asks for
+    pub fn get_block2(&self) -> Option<CoapBlock> {
+        self.get_options()
+            .find(|option| option.get_option_number() == CoapOptionNumbers::Block2)
+            .and_then(|option| option.get_uint().ok())
+            .and_then(|value| CoapBlock::decode(value).ok())
+    }
+
+    /// Returns the address the request was received from, if the server was told
+    pub fn get_peer(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    /// Returns the exchange to answer the request with after deferring its response,
+    /// see [`CoapResponse::defer`]
+    pub fn get_exchange(&self) -> CoapExchange {
+        let token = CoapToken::from_slice(self.get_token()).unwrap_or_default();
+        CoapExchange::new(self.peer, token)
+    }
+}
+
+/// The message a response is written to, erasing the capacities of the server
+pub(crate) trait ResponseMessage {
+    fn code(&self) -> CoapHeaderCode;
+    fn set_code(&mut self, code: CoapHeaderCode);
+    fn push_option(&mut self, number: CoapOptionNumbers, value: &[u8]) -> Result<(), CoapError>;
+    fn replace_option(&mut self, number: CoapOptionNumbers, value: &[u8]) -> Result<(), CoapError>;
+    fn set_payload(&mut self, payload: &[u8]) -> Result<(), CoapError>;
+    fn clear(&mut self);
+    fn payload_capacity(&self) -> usize;
+    fn fill_payload(
+        &mut self,
+        length: usize,
+        fill: &mut dyn FnMut(&mut [u8]) -> Result<usize, CoapError>,
+    ) -> Result<usize, CoapError>;
+}
+
+impl<const MAX_OPTIONS: usize, const OPTION_SIZE: usize, const PAYLOAD_SIZE: usize> ResponseMessage
+    for CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>
+{
+    fn code(&self) -> CoapHeaderCode {
+        self.header.get_code()
+    }
+
+    fn set_code(&mut self, code: CoapHeaderCode) {
+        self.header.set_code(code);
+    }
+
+    fn push_option(&mut self, number: CoapOptionNumbers, value: &[u8]) -> Result<(), CoapError> {
+        self.add_option(CoapOption::opaque(number, value)?)
+    }
+
+    fn replace_option(&mut self, number: CoapOptionNumbers, value: &[u8]) -> Result<(), CoapError> {
+        self.options.replace(number, value)
+    }
+
+    fn set_payload(&mut self, payload: &[u8]) -> Result<(), CoapError> {
+        CoapMessage::set_payload(self, payload)
+    }
+
+    fn clear(&mut self) {
+        self.options = Default::default();
+        let _ = CoapMessage::set_payload(self, &[]);
+    }
+
+    fn payload_capacity(&self) -> usize {
+        PAYLOAD_SIZE
+    }
+
+    fn fill_payload(
+        &mut self,
+        length: usize,
+        fill: &mut dyn FnMut(&mut [u8]) -> Result<usize, CoapError>,
+    ) -> Result<usize, CoapError> {
+        CoapMessage::fill_payload(self, length, fill)
+    }
+}
+
+/// Writes the response to a request, passed to a [`CoapHandler`]
+pub struct CoapResponse<'a> {
+    message: &'a mut dyn ResponseMessage,
+    deferred: bool,
+    /// The block the client asks for
+    block2: Option<CoapBlock>,
+}
+
+impl<'a> CoapResponse<'a> {
+    pub(crate) fn new(message: &'a mut dyn ResponseMessage, block2: Option<CoapBlock>) -> Self {
+        CoapResponse {
+            message,
+            deferred: false,
+            block2,
+        }
+    }
+
+    /// Defers the response, for requests that take longer than the client waits for
+    /// an acknowledgement. The request is acknowledged right away and answered later
+    /// with [`crate::CoapServer::respond`], passing [`CoapRequest::get_exchange`].
+    /// Options and payload set before are dropped
+    pub fn defer(&mut self) {
+        self.deferred = true;
+    }
+
+    /// Returns true if the response was deferred
+    pub fn is_deferred(&self) -> bool {
+        self.deferred
+    }
+
+    /// Returns the response code
+    pub fn get_code(&self) -> CoapHeaderCode {
+        self.message.code()
+    }
+
+    /// Sets the response code
+    pub fn set_code(&mut self, code: CoapHeaderCode) {
+        self.message.set_code(code);
+    }
+
+    /// Adds an option with a raw value, options are kept in option number order
+    pub fn add_option(&mut self, number: CoapOptionNumbers, value: &[u8]) -> Result<(), CoapError> {
+        self.message.push_option(number, value)
+    }
+
+    /// Adds an option with an unsigned integer value, for example Max-Age
+    pub fn add_uint_option(
+        &mut self,
+        number: CoapOptionNumbers,
+        value: u32,
+    ) -> Result<(), CoapError> {
+        let (bytes, start) = encode_uint(value);
+        self.message.push_option(number, &bytes[start..])
+    }
+
+    /// Sets the Content-Format of the payload
+    pub fn set_content_format(&mut self, format: CoapMediaType) -> Result<(), CoapError> {
+        let (bytes, start) = encode_uint(u16::from(format) as u32);
+        self.message
+            .replace_option(CoapOptionNumbers::ContentFormat, &bytes[start..])
+    }
+
+    /// Sets the payload
+    pub fn set_payload(&mut self, payload: &[u8]) -> Result<(), CoapError> {
+        self.message.set_payload(payload)
+    }
+
+    /// Sets the payload to the block of the representation the client asks for, RFC 7959.
+    /// Without a Block2 option in the request the first block is sent, as large as
+    /// the payload capacity of the server allows. A representation that fits into
+    /// that block is sent as it is
+    pub fn set_block_payload(&mut self, representation: &[u8]) -> Result<(), CoapError> {
+        self.set_block_payload_with(Some(representation.len()), |offset, buffer| {
+            let rest = representation.get(offset..).unwrap_or_default();
+            let length = rest.len().min(buffer.len());
+            buffer[..length].copy_from_slice(&rest[..length]);
+            Ok(length)
+        })
+    }
+
+    /// Sets the payload to the block of a representation produced by `read`, see
+    /// [`CoapResponse::set_block_payload`]. `read` fills the buffer with the
+    /// representation from the offset on and returns the number of bytes written,
+    /// fewer than the buffer holds only at the end of the representation.
+    /// The size of the representation is sent as Size2 if it is known.
+    /// A block past the end is answered with 4.02 Bad Option
+    pub fn set_block_payload_with<F>(
+        &mut self,
+        size: Option<usize>,
+        mut read: F,
+    ) -> Result<(), CoapError>
+    where
+        F: FnMut(usize, &mut [u8]) -> Result<usize, CoapError>,
+    {
+        self.set_block_payload_sized(|offset, buffer| match size {
+            Some(size) if offset > 0 && offset >= size => Ok((0, Some(size))),
+            _ => Ok((read(offset, buffer)?, size)),
+        })
+    }
+
+    /// Sets the payload to the block of a representation produced by `fill`, which
+    /// returns the number of bytes written together with the size of the whole
+    /// representation if it is known. A representation rendered from the start for
+    /// every block can count its size in the same pass.
+    /// Without the size `fill` is called a second time to check for more blocks
+    pub(crate) fn set_block_payload_sized<F>(&mut self, mut fill: F) -> Result<(), CoapError>
+    where
+        F: FnMut(usize, &mut [u8]) -> Result<(usize, Option<usize>), CoapError>,
+    {
+        let capacity = self.message.payload_capacity();
+        let szx = block::szx_for(capacity).ok_or(CoapError::CapacityExceeded { capacity })?;
+        let block = match self.block2.map(|block| block.limit(szx)) {
+            Some(Some(block)) => block,
+            Some(None) => {
+                self.reject_block();
+                return Ok(());
+            }
+            None => CoapBlock::new(0, false, szx)?,
+        };
+        let offset = block.get_offset();
+        let mut size = None;
+        let length = self.message.fill_payload(block.get_size(), &mut |buffer| {
+            let (length, total) = fill(offset, buffer)?;
+            size = total;
+            Ok(length)
+        })?;
+        if offset > 0 && length == 0 {
+            self.reject_block();
+            return Ok(());
+        }
+        let more = match size {
+            Some(size) => offset + length < size,
+            None => length == block.get_size() && fill(offset + length, &mut [0])?.0 > 0,
+        };
+        if self.block2.is_none() && !more {
+            return Ok(());
+        }
+        let block = CoapBlock::new(block.get_num(), more, block.get_szx())?;
+        let (bytes, start) = encode_uint(block.encode());
+        self.message
+            .replace_option(CoapOptionNumbers::Block2, &bytes[start..])?;
+        if let Some(size) = size.and_then(|size| u32::try_from(size).ok()) {
+            let (bytes, start) = encode_uint(size);
+            self.message
+                .replace_option(CoapOptionNumbers::Size2, &bytes[start..])?;
+        }
+        Ok(())
+    }
+
+    /// Answers a request for a block past the end of the representation with
+    /// 4.02 Bad Option, dropping the options and payload set before
+    fn reject_block(&mut self) {
+        self.message.clear();
+        self.message.set_code(CoapHeaderCode::BadOption);
+    }
+}
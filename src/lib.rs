@@ -1,83 +1,265 @@
 #![no_std]
-#![feature(exclusive_range_pattern)]
 #![allow(dead_code)]
 #![deny(missing_docs)]
 
 //! XoAP - CoAP for Embedded systems w/o allocator
 //!
 
-use heapless::consts::*;
+use core::fmt::{self, Write};
+use core::net::SocketAddr;
+use exchange::{Backoff, Deduplication, Deferred, Transmission};
 use heapless::{String, Vec};
+use message::block::BlockWriter;
+use observe::{Observations, Observer};
 
-mod message;
-
-use message::header::CoapHeader;
-use message::header::{CoapHeaderCode, CoapHeaderType};
-use message::option::CoapOptionNumbers;
-
-#[derive(Debug)]
-pub(crate) enum CoapError {
-    ConfigError,
-    HeaderError,
-    MessageError,
-    // Actual errors from standard
-    MessageFormatError,
-    WrongVersion,
-    BadOption,
-    InternalServerError,
-}
+pub mod client;
+mod error;
+pub mod exchange;
+pub mod handler;
+pub mod message;
+pub mod observe;
+pub mod router;
+pub mod uri;
+
+pub use client::{CoapClient, CoapNotificationHandler, DEFAULT_MAX_OBSERVATIONS};
+pub use error::CoapError;
+pub use exchange::{
+    CoapClock, CoapExchange, CoapRandom, DEFAULT_DEDUPLICATION_SIZE, DEFAULT_MAX_EXCHANGES,
+    DEFAULT_MESSAGE_SIZE, DEFAULT_RESPONSE_SIZE,
+};
+
+pub use handler::{CoapCallback, CoapHandler, CoapRequest, CoapResponse};
+pub use message::header::{CoapHeader, CoapHeaderCode, CoapHeaderType, CoapOtherCode};
+pub use message::option::{
+    CoapOption, CoapOptionFormat, CoapOptionNumbers, CoapOptions, DEFAULT_MAX_OPTIONS,
+    DEFAULT_OPTION_SIZE,
+};
+pub use message::{
+    CoapBlock, CoapLink, CoapLinkAttributes, CoapMediaType, CoapMessage, CoapMessageBuilder,
+    CoapMessageRef, CoapMethod, CoapToken, DEFAULT_PAYLOAD_SIZE,
+};
+pub use observe::{DEFAULT_MAX_OBSERVERS, DEFAULT_MAX_RESOURCE_OBSERVERS};
+pub use router::{CoapPathParams, CoapQueryIter, MAX_PATH_PARAMS};
+
+/// Default maximum number of resources of a server
+pub const DEFAULT_MAX_RESOURCES: usize = 8;
+
+/// Path of the resource discovery document, RFC 6690 §4
+const WELL_KNOWN_CORE: &str = ".well-known/core";
 
 /// A CoAP resource, an endpoint that is being requested.
 /// For example ```house/livingroom/temperature```
 ///
-/// Takes the endpoint path and a callback function that will be executed when the enpoint is called
+/// Takes the endpoint path and a handler that will be called when the enpoint is requested,
+/// either with any method or with one method only.
+/// The path is a template that can hold `{name}` parameters and `*` and `**` wildcards,
+/// see [`router`]
+///
+/// Resources with a plain path are listed at `/.well-known/core` with their
+/// [`CoapLinkAttributes`]
 ///
-#[derive(Debug, Clone, PartialEq)]
-pub struct CoapResource {
-    callback: fn() -> u8,
-    path: String<U255>,
+pub struct CoapResource<'a> {
+    handler: ResourceHandler<'a>,
+    method: Option<CoapMethod>,
+    path: String<255>,
+    attributes: CoapLinkAttributes<'a>,
+}
+
+/// The handler of a resource, plain callbacks are kept inline
+enum ResourceHandler<'a> {
+    Callback(CoapCallback),
+    Handler(&'a mut dyn CoapHandler),
 }
 
-impl CoapResource {
+impl<'a> CoapResource<'a> {
     /// Returns the enpoint path for the particular resource
-    pub fn get_path(&self) -> String<U255> {
+    pub fn get_path(&self) -> String<255> {
         self.path.clone()
     }
 
-    /// Returns the callback function associated with the particular resource
-    pub fn callback(&self) -> fn() -> u8 {
-        self.callback
+    /// Returns the method handled by the resource, `None` if it handles all methods
+    pub fn get_method(&self) -> Option<CoapMethod> {
+        self.method
+    }
+
+    /// Returns the link attributes listed at `/.well-known/core`
+    pub fn get_attributes(&self) -> &CoapLinkAttributes<'a> {
+        &self.attributes
+    }
+
+    /// Sets the link attributes listed at `/.well-known/core`, for example
+    /// `config.add_resource(cb, "temp")?.set_attributes(CoapLinkAttributes::new().rt("temperature"))`
+    pub fn set_attributes(&mut self, attributes: CoapLinkAttributes<'a>) -> &mut Self {
+        self.attributes = attributes;
+        self
+    }
+
+    /// Returns the callback function associated with the particular resource,
+    /// if it was added with [`CoapConfig::add_resource`]
+    pub fn callback(&self) -> Option<fn() -> u8> {
+        match self.handler {
+            ResourceHandler::Callback(callback) => Some(callback.0),
+            ResourceHandler::Handler(_) => None,
+        }
+    }
+}
+
+impl<'a> ResourceHandler<'a> {
+    fn as_handler(&mut self) -> &mut dyn CoapHandler {
+        match self {
+            ResourceHandler::Callback(callback) => callback,
+            ResourceHandler::Handler(handler) => *handler,
+        }
+    }
+}
+
+impl<'a> PartialEq for CoapResource<'a> {
+    /// Resources are equal if they serve the same method on the same path,
+    /// handlers are not compared
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path && self.method == other.method
+    }
+}
+
+impl<'a> fmt::Debug for CoapResource<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoapResource")
+            .field("path", &self.path)
+            .field("method", &self.method)
+            .field("attributes", &self.attributes)
+            .finish_non_exhaustive()
     }
 }
 
 /// CoAP server/client configuration struct.
-/// Needs to be passed to the server/client during creation
+/// Needs to be passed to the server/client during creation.
+///
+/// Holds up to `MAX_RESOURCES` resources. The message capacities are used for the
+/// requests and responses handled by a server created from this configuration,
+/// which keeps up to `MAX_EXCHANGES` deferred requests and unacknowledged messages
+/// of up to `MESSAGE_SIZE` bytes encoded, remembers the last `DEDUPLICATION_SIZE` requests
+/// and their responses of up to `RESPONSE_SIZE` bytes to detect duplicates
+/// and takes up to `MAX_OBSERVERS` observers, at most `MAX_RESOURCE_OBSERVERS` of them
+/// per resource, whose registration requests take up to `MESSAGE_SIZE` bytes encoded
 #[derive(Debug, PartialEq)]
-pub struct CoapConfig {
-    resources: Vec<CoapResource, U8>,
+pub struct CoapConfig<
+    'a,
+    const MAX_RESOURCES: usize = DEFAULT_MAX_RESOURCES,
+    const MAX_OPTIONS: usize = DEFAULT_MAX_OPTIONS,
+    const OPTION_SIZE: usize = DEFAULT_OPTION_SIZE,
+    const PAYLOAD_SIZE: usize = DEFAULT_PAYLOAD_SIZE,
+    const MAX_EXCHANGES: usize = DEFAULT_MAX_EXCHANGES,
+    const DEDUPLICATION_SIZE: usize = DEFAULT_DEDUPLICATION_SIZE,
+    const MAX_OBSERVERS: usize = DEFAULT_MAX_OBSERVERS,
+    const MESSAGE_SIZE: usize = DEFAULT_MESSAGE_SIZE,
+    const RESPONSE_SIZE: usize = DEFAULT_RESPONSE_SIZE,
+    const MAX_RESOURCE_OBSERVERS: usize = DEFAULT_MAX_RESOURCE_OBSERVERS,
+> {
+    resources: Vec<CoapResource<'a>, MAX_RESOURCES>,
 }
 
-impl CoapConfig {
-    /// Creates a new vector of (empty) resources
+impl<'a> CoapConfig<'a> {
+    /// Creates a new vector of (empty) resources with the default capacities.
+    /// Use [`Default`] for other capacities
     pub fn new() -> Self {
-        CoapConfig {
-            resources: Vec::<CoapResource, U8>::new(),
-        }
+        Self::default()
     }
-    /// Adds a resource to the configuration
-    pub fn add_resource(&mut self, cb: fn() -> u8, path: &str) /*-> Result<(), CoapError>*/
-    {
-        let res = CoapResource {
-            callback: cb,
-            path: String::from(path),
+}
+
+impl<
+        'a,
+        const MAX_RESOURCES: usize,
+        const MAX_OPTIONS: usize,
+        const OPTION_SIZE: usize,
+        const PAYLOAD_SIZE: usize,
+        const MAX_EXCHANGES: usize,
+        const DEDUPLICATION_SIZE: usize,
+        const MAX_OBSERVERS: usize,
+        const MESSAGE_SIZE: usize,
+        const RESPONSE_SIZE: usize,
+        const MAX_RESOURCE_OBSERVERS: usize,
+    >
+    CoapConfig<
+        'a,
+        MAX_RESOURCES,
+        MAX_OPTIONS,
+        OPTION_SIZE,
+        PAYLOAD_SIZE,
+        MAX_EXCHANGES,
+        DEDUPLICATION_SIZE,
+        MAX_OBSERVERS,
+        MESSAGE_SIZE,
+        RESPONSE_SIZE,
+        MAX_RESOURCE_OBSERVERS,
+    >
+{
+    /// Adds a resource answering GET requests with the byte returned by the callback,
+    /// see [`CoapCallback`].
+    /// Returns the resource to set its [`CoapLinkAttributes`]
+    pub fn add_resource(
+        &mut self,
+        cb: fn() -> u8,
+        path: &str,
+    ) -> Result<&mut CoapResource<'a>, CoapError> {
+        self.push_resource(
+            ResourceHandler::Callback(CoapCallback(cb)),
+            Some(CoapMethod::GET),
+            path,
+        )
+    }
+
+    /// Adds a resource whose requests are passed to the handler, whatever their method.
+    /// The handler is borrowed for as long as the configuration and its server live
+    pub fn add_handler(
+        &mut self,
+        path: &str,
+        handler: &'a mut dyn CoapHandler,
+    ) -> Result<&mut CoapResource<'a>, CoapError> {
+        self.push_resource(ResourceHandler::Handler(handler), None, path)
+    }
+
+    /// Adds a handler for the requests with one method to the path.
+    /// Several methods can be registered for the same path, a request with a method
+    /// that is not registered is answered with 4.05 Method Not Allowed
+    pub fn add_method_handler(
+        &mut self,
+        method: CoapMethod,
+        path: &str,
+        handler: &'a mut dyn CoapHandler,
+    ) -> Result<&mut CoapResource<'a>, CoapError> {
+        self.push_resource(ResourceHandler::Handler(handler), Some(method), path)
+    }
+
+    fn push_resource(
+        &mut self,
+        handler: ResourceHandler<'a>,
+        method: Option<CoapMethod>,
+        path: &str,
+    ) -> Result<&mut CoapResource<'a>, CoapError> {
+        router::validate(path)?;
+        let mut res = CoapResource {
+            handler,
+            method,
+            path: String::new(),
+            attributes: CoapLinkAttributes::new(),
         };
-        self.resources.push(res).unwrap();
-        //Ok(())
+        res.path
+            .push_str(path)
+            .map_err(|_| CoapError::CapacityExceeded {
+                capacity: res.path.capacity(),
+            })?;
+        let index = self.resources.len();
+        self.resources
+            .push(res)
+            .map_err(|_| CoapError::CapacityExceeded {
+                capacity: self.resources.capacity(),
+            })?;
+        Ok(&mut self.resources[index])
     }
 
     /// Removes the first resource with the supplied endpoint string from the resource pool
     pub fn remove_resource(&mut self, resource: &str) {
-        for index in 0..self.resources.capacity() {
+        for index in 0..self.resources.len() {
             if self.resources[index].get_path() == resource {
                 self.resources.swap_remove(index);
                 break;
@@ -86,158 +268,816 @@ impl CoapConfig {
     }
 }
 
+impl<
+        'a,
+        const MAX_RESOURCES: usize,
+        const MAX_OPTIONS: usize,
+        const OPTION_SIZE: usize,
+        const PAYLOAD_SIZE: usize,
+        const MAX_EXCHANGES: usize,
+        const DEDUPLICATION_SIZE: usize,
+        const MAX_OBSERVERS: usize,
+        const MESSAGE_SIZE: usize,
+        const RESPONSE_SIZE: usize,
+        const MAX_RESOURCE_OBSERVERS: usize,
+    > Default
+    for CoapConfig<
+        'a,
+        MAX_RESOURCES,
+        MAX_OPTIONS,
+        OPTION_SIZE,
+        PAYLOAD_SIZE,
+        MAX_EXCHANGES,
+        DEDUPLICATION_SIZE,
+        MAX_OBSERVERS,
+        MESSAGE_SIZE,
+        RESPONSE_SIZE,
+        MAX_RESOURCE_OBSERVERS,
+    >
+{
+    fn default() -> Self {
+        CoapConfig {
+            resources: Vec::new(),
+        }
+    }
+}
+
 /// CoAP server.
 /// Creates a CoAP server acting behavoir.
 /// Takes a CoAP config struct and a buffer for message storage.
-pub struct CoapServer<'a> {
-    config: CoapConfig,
-    buffer: &'a [u8],
+/// Responses are encoded into the buffer.
+/// The capacities are taken from the [`CoapConfig`].
+///
+/// Responses are piggybacked on the acknowledgement of the request, unless the handler
+/// defers them. Separate responses to confirmable requests are retransmitted by
+/// [`CoapServer::poll`] until they are acknowledged, see [`exchange`].
+/// Duplicates of recent requests are not passed to the handlers again.
+/// Clients can observe resources marked `obs`, see [`observe`]. The server takes up to
+/// `MAX_OBSERVERS` observers in all and up to `MAX_RESOURCE_OBSERVERS` of one resource,
+/// so a busy resource cannot take the room of the others.
+pub struct CoapServer<
+    'a,
+    const MAX_RESOURCES: usize = DEFAULT_MAX_RESOURCES,
+    const MAX_OPTIONS: usize = DEFAULT_MAX_OPTIONS,
+    const OPTION_SIZE: usize = DEFAULT_OPTION_SIZE,
+    const PAYLOAD_SIZE: usize = DEFAULT_PAYLOAD_SIZE,
+    const MAX_EXCHANGES: usize = DEFAULT_MAX_EXCHANGES,
+    const DEDUPLICATION_SIZE: usize = DEFAULT_DEDUPLICATION_SIZE,
+    const MAX_OBSERVERS: usize = DEFAULT_MAX_OBSERVERS,
+    const MESSAGE_SIZE: usize = DEFAULT_MESSAGE_SIZE,
+    const RESPONSE_SIZE: usize = DEFAULT_RESPONSE_SIZE,
+    const MAX_RESOURCE_OBSERVERS: usize = DEFAULT_MAX_RESOURCE_OBSERVERS,
+> {
+    config: CoapConfig<
+        'a,
+        MAX_RESOURCES,
+        MAX_OPTIONS,
+        OPTION_SIZE,
+        PAYLOAD_SIZE,
+        MAX_EXCHANGES,
+        DEDUPLICATION_SIZE,
+        MAX_OBSERVERS,
+        MESSAGE_SIZE,
+        RESPONSE_SIZE,
+        MAX_RESOURCE_OBSERVERS,
+    >,
+    buffer: &'a mut [u8],
+    clock: Option<&'a dyn CoapClock>,
+    random: Option<&'a dyn CoapRandom>,
+    message_id: u16,
+    deferred: Vec<Deferred, MAX_EXCHANGES>,
+    transmissions: Vec<Transmission<MESSAGE_SIZE>, MAX_EXCHANGES>,
+    received: Deduplication<DEDUPLICATION_SIZE, RESPONSE_SIZE>,
+    observations: Observations<MESSAGE_SIZE, MAX_OBSERVERS, MAX_RESOURCE_OBSERVERS>,
+    sequence: u32,
 }
 
-impl<'a> CoapServer<'a> {
+/// The outcome of passing a request to the resources of a server
+struct Handled<const MAX_OPTIONS: usize, const OPTION_SIZE: usize, const PAYLOAD_SIZE: usize> {
+    response: CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>,
+    /// The index of the resource whose handler was called
+    resource: Option<usize>,
+    /// The default code of the method, if the handler deferred the response
+    deferred: Option<CoapHeaderCode>,
+}
+
+impl<const MAX_OPTIONS: usize, const OPTION_SIZE: usize, const PAYLOAD_SIZE: usize>
+    From<CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>>
+    for Handled<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>
+{
+    /// A response given without calling a handler
+    fn from(response: CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>) -> Self {
+        Handled {
+            response,
+            resource: None,
+            deferred: None,
+        }
+    }
+}
+
+impl<
+        'a,
+        const MAX_RESOURCES: usize,
+        const MAX_OPTIONS: usize,
+        const OPTION_SIZE: usize,
+        const PAYLOAD_SIZE: usize,
+        const MAX_EXCHANGES: usize,
+        const DEDUPLICATION_SIZE: usize,
+        const MAX_OBSERVERS: usize,
+        const MESSAGE_SIZE: usize,
+        const RESPONSE_SIZE: usize,
+        const MAX_RESOURCE_OBSERVERS: usize,
+    >
+    CoapServer<
+        'a,
+        MAX_RESOURCES,
+        MAX_OPTIONS,
+        OPTION_SIZE,
+        PAYLOAD_SIZE,
+        MAX_EXCHANGES,
+        DEDUPLICATION_SIZE,
+        MAX_OBSERVERS,
+        MESSAGE_SIZE,
+        RESPONSE_SIZE,
+        MAX_RESOURCE_OBSERVERS,
+    >
+{
     /// Creates a new CoAP server
-    pub fn new(config: CoapConfig, buffer: &'a mut [u8]) -> Self {
-        CoapServer { config, buffer }
-    }
-    /// Handels a message and returns the response to be sent of to the request owner
-    pub fn handle_message(self, msg: &mut [u8]) -> Vec<u8, U255> {
-        let request = match message::CoapMessage::decode(msg) {
-            Ok(msg) => msg,
-            Err(_e) => panic!(),
+    pub fn new(
+        config: CoapConfig<
+            'a,
+            MAX_RESOURCES,
+            MAX_OPTIONS,
+            OPTION_SIZE,
+            PAYLOAD_SIZE,
+            MAX_EXCHANGES,
+            DEDUPLICATION_SIZE,
+            MAX_OBSERVERS,
+            MESSAGE_SIZE,
+            RESPONSE_SIZE,
+            MAX_RESOURCE_OBSERVERS,
+        >,
+        buffer: &'a mut [u8],
+    ) -> Self {
+        CoapServer {
+            config,
+            buffer,
+            clock: None,
+            random: None,
+            message_id: 0,
+            deferred: Vec::new(),
+            transmissions: Vec::new(),
+            received: Deduplication::default(),
+            observations: Observations::default(),
+            sequence: 0,
+        }
+    }
+
+    /// Sets the clock timing the retransmissions and the expiry of duplicate detection.
+    /// Without a clock the time stands still, nothing is retransmitted and
+    /// requests are remembered until newer ones take their place. Confirmable separate
+    /// responses and notifications are then never given up, each one keeps its room among
+    /// the `MAX_EXCHANGES` unacknowledged messages until it is acknowledged
+    pub fn set_clock(&mut self, clock: &'a dyn CoapClock) {
+        self.clock = Some(clock);
+    }
+
+    /// Sets the source of the random numbers that spread the retransmission timeouts,
+    /// RFC 7252 §4.2. Without one they are derived from the time and the message id
+    pub fn set_random(&mut self, random: &'a dyn CoapRandom) {
+        self.random = Some(random);
+    }
+
+    /// Sets the message id of the next message the server sends on its own,
+    /// for example a separate response. Start with a random value, RFC 7252 §4.4
+    pub fn set_message_id(&mut self, message_id: u16) {
+        self.message_id = message_id;
+    }
+
+    /// Returns the configuration
+    pub fn get_config(
+        &self,
+    ) -> &CoapConfig<
+        'a,
+        MAX_RESOURCES,
+        MAX_OPTIONS,
+        OPTION_SIZE,
+        PAYLOAD_SIZE,
+        MAX_EXCHANGES,
+        DEDUPLICATION_SIZE,
+        MAX_OBSERVERS,
+        MESSAGE_SIZE,
+        RESPONSE_SIZE,
+        MAX_RESOURCE_OBSERVERS,
+    > {
+        &self.config
+    }
+
+    /// Returns the configuration, to add or remove resources while the server runs
+    pub fn get_config_mut(
+        &mut self,
+    ) -> &mut CoapConfig<
+        'a,
+        MAX_RESOURCES,
+        MAX_OPTIONS,
+        OPTION_SIZE,
+        PAYLOAD_SIZE,
+        MAX_EXCHANGES,
+        DEDUPLICATION_SIZE,
+        MAX_OBSERVERS,
+        MESSAGE_SIZE,
+        RESPONSE_SIZE,
+        MAX_RESOURCE_OBSERVERS,
+    > {
+        &mut self.config
+    }
+
+    /// Handels a message and returns the response to be sent of to the request owner.
+    /// The response is encoded into the buffer passed to [`CoapServer::new`]
+    /// and stays valid until the next message is handled.
+    /// An empty response means there is nothing to send back.
+    /// The server is kept between messages, call it from the receive loop or task.
+    ///
+    /// Malformed confirmable messages are answered with a reset, RFC 7252 §4.2.
+    /// Other malformed messages are silently ignored by returning the decode error
+    pub fn handle_message(&mut self, msg: &[u8]) -> Result<&[u8], CoapError> {
+        self.handle(msg, None)
+    }
+
+    /// Handles a message received from the peer, see [`CoapServer::handle_message`].
+    /// The peer address is passed on to the handlers
+    pub fn handle_message_from(
+        &mut self,
+        peer: SocketAddr,
+        msg: &[u8],
+    ) -> Result<&[u8], CoapError> {
+        self.handle(msg, Some(peer))
+    }
+
+    fn handle(&mut self, msg: &[u8], peer: Option<SocketAddr>) -> Result<&[u8], CoapError> {
+        let request = match CoapMessageRef::decode(msg) {
+            Ok(request) => request,
+            Err(e) => return self.reject_malformed(msg, e),
         };
+        let code = request.header.get_code();
+        let t = request.header.get_type();
+        let bad_option = request.get_unrecognized_critical_option().is_some();
+        if t == CoapHeaderType::Acknowledgement || t == CoapHeaderType::Reset {
+            if code.is_request() {
+                return Err(CoapError::InvalidMessageType);
+            }
+            // Acknowledgements and resets are never answered, a rejected one is ignored
+            if bad_option {
+                return Ok(&[]);
+            }
+            // They end a retransmission
+            let message_id = request.header.get_message_id();
+            self.transmissions
+                .retain(|transmission| !transmission.is_answered_by(peer, message_id));
+            // A reset notification ends the observation, RFC 7641 §3.6
+            if t == CoapHeaderType::Reset {
+                self.observations.remove_reset(peer, message_id);
+            }
+            return Ok(&[]);
+        }
+        if bad_option {
+            return self.reject_bad_option(request);
+        }
 
-        let response = match request.header.get_code() {
-            CoapHeaderCode::EMPTY => {
-                let header = CoapHeader::new(
-                    CoapHeaderType::Reset,
-                    request.header.get_tkl(),
-                    CoapHeaderCode::EMPTY,
-                    request.header.get_message_id(),
-                )
-                .unwrap();
-                let message = message::CoapMessage::new(header, &[]);
-                Some(message)
+        match code {
+            // CoAP ping
+            CoapHeaderCode::EMPTY if t == CoapHeaderType::Confirmable => {
+                self.reset(request.header.get_message_id())
+            }
+            CoapHeaderCode::EMPTY => Err(CoapError::InvalidEmptyMessage),
+            code if code.is_request() => self.handle_once(request, peer),
+            // The server sends no requests, a response or reserved code is rejected,
+            // RFC 7252 §4.2 and §4.3
+            _ if t == CoapHeaderType::Confirmable => self.reset(request.header.get_message_id()),
+            _ => Ok(&[]),
+        }
+    }
+
+    /// Handles a request unless it is a duplicate, RFC 7252 §4.5.
+    /// A duplicate gets the response to the first copy
+    fn handle_once(
+        &mut self,
+        request: CoapMessageRef,
+        peer: Option<SocketAddr>,
+    ) -> Result<&[u8], CoapError> {
+        let t = request.header.get_type();
+        let message_id = request.header.get_message_id();
+        let now = self.now();
+        if let Some(response) = self.received.get(peer, message_id, now) {
+            let response = match response {
+                Some(response) => response,
+                // The response was too long to keep, the request is only acknowledged
+                None if t == CoapHeaderType::Confirmable => return self.acknowledge(message_id),
+                None => &[],
+            };
+            let capacity = self.buffer.len();
+            let buffer = self
+                .buffer
+                .get_mut(..response.len())
+                .ok_or(CoapError::CapacityExceeded { capacity })?;
+            buffer.copy_from_slice(response);
+            return Ok(buffer);
+        }
+        let length = match self.handle_request(request, peer)? {
+            Some(response) => response.encode_into(self.buffer)?,
+            None => 0,
+        };
+        let replay = match t {
+            CoapHeaderType::Confirmable if length > 0 => Some(&self.buffer[..length]),
+            _ => None,
+        };
+        self.received.insert(peer, t, message_id, now, replay);
+        Ok(&self.buffer[..length])
+    }
+
+    /// Encodes the response into the server buffer
+    fn send(
+        &mut self,
+        response: CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>,
+    ) -> Result<&[u8], CoapError> {
+        let length = response.encode_into(self.buffer)?;
+        Ok(&self.buffer[..length])
+    }
+
+    /// Sends the separate response to a request deferred by its handler, RFC 7252 §5.2.2.
+    /// The response has the type of the request and starts out with the default code of
+    /// the method, the closure sets it up like a handler does.
+    /// Returns the response to be sent to the peer of the exchange, confirmable responses
+    /// are retransmitted by [`CoapServer::poll`] until they are acknowledged
+    pub fn respond<F>(&mut self, exchange: &CoapExchange, f: F) -> Result<&[u8], CoapError>
+    where
+        F: FnOnce(&mut CoapResponse<'_>) -> Result<(), CoapError>,
+    {
+        let index = self
+            .deferred
+            .iter()
+            .position(|deferred| deferred.exchange == *exchange)
+            .ok_or(CoapError::UnknownExchange)?;
+        let deferred = &self.deferred[index];
+        let t = deferred.t;
+        if t == CoapHeaderType::Confirmable && self.transmissions.is_full() {
+            return Err(CoapError::CapacityExceeded {
+                capacity: MAX_EXCHANGES,
+            });
+        }
+        let header = CoapHeader::new(t, 0, deferred.code, self.message_id)?;
+        let mut response: CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE> =
+            CoapMessage::from_header(header, &[])?;
+        response.set_token(exchange.get_token())?;
+        let mut writer = CoapResponse::new(&mut response, None);
+        if f(&mut writer).is_err() || writer.is_deferred() {
+            response = CoapMessage::from_header(header, &[])?;
+            response.set_token(exchange.get_token())?;
+            response
+                .header
+                .set_code(CoapHeaderCode::InternalServerError);
+        }
+        let length = response.encode_into(self.buffer)?;
+        if t == CoapHeaderType::Confirmable {
+            let backoff = self.backoff();
+            let transmission =
+                Transmission::new(exchange.get_peer(), &self.buffer[..length], backoff)?;
+            // Checked to have room above
+            let _ = self.transmissions.push(transmission);
+        }
+        self.deferred.swap_remove(index);
+        self.message_id = self.message_id.wrapping_add(1);
+        Ok(&self.buffer[..length])
+    }
+
+    /// Notifies the observers of the resource with the path that its state changed.
+    /// The path is matched against the request path of every observer like a path
+    /// template, see [`router`]. A concrete path such as `relays/3/state` notifies the
+    /// observers of one resource, the template `relays/{id}/state` those of all of them.
+    /// The notifications are returned by [`CoapServer::poll`]. Returns the number of observers
+    pub fn notify(&mut self, path: &str) -> usize {
+        self.observations.notify(path)
+    }
+
+    /// Returns the next message to be sent to a peer, call it regularly until it returns `None`.
+    ///
+    /// Confirmable messages whose acknowledgement timed out are sent again, they are
+    /// given up after [`exchange::MAX_RETRANSMIT`] retransmissions.
+    /// Then the notifications of the observers of changed resources are returned,
+    /// with the type of the registration request. A confirmable notification is only
+    /// sent once the one before is acknowledged, the observer is removed if it is not
+    pub fn poll(&mut self) -> Option<(Option<SocketAddr>, &[u8])> {
+        let now = self.now();
+        let mut index = 0;
+        while index < self.transmissions.len() {
+            let transmission = &mut self.transmissions[index];
+            if !transmission.backoff.is_due(now) {
+                index += 1;
+                continue;
             }
-            CoapHeaderCode::GET => self.handle_get(request),
-            CoapHeaderCode::POST => self.handle_post(request),
-            CoapHeaderCode::PUT => self.handle_put(request),
-            CoapHeaderCode::DELETE => self.handle_delete(request),
-            _ => match request.header.get_type() {
-                CoapHeaderType::Confirmable
-                | CoapHeaderType::Reset
-                | CoapHeaderType::Acknowledgement => {
-                    let header = CoapHeader::new(
-                        CoapHeaderType::Acknowledgement,
-                        request.header.get_tkl(),
-                        CoapHeaderCode::MethodNotAllowed,
-                        request.header.get_message_id(),
-                    )
-                    .unwrap();
-                    let message = message::CoapMessage::new(header, &[]);
-                    Some(message)
+            if transmission.backoff.retransmit(now) {
+                let peer = transmission.peer;
+                let message = transmission.get_message();
+                let length = message.len();
+                if let Some(buffer) = self.buffer.get_mut(..length) {
+                    buffer.copy_from_slice(message);
+                    return Some((peer, &self.buffer[..length]));
                 }
-                CoapHeaderType::NonConfirmable => {
-                    let header = CoapHeader::new(
-                        CoapHeaderType::NonConfirmable,
-                        request.header.get_tkl(),
-                        CoapHeaderCode::MethodNotAllowed,
-                        request.header.get_message_id(),
-                    )
-                    .unwrap();
-                    let message = message::CoapMessage::new(header, &[]);
-                    Some(message)
+            }
+            // Given up, or too long for the buffer
+            let transmission = self.transmissions.swap_remove(index);
+            self.observations
+                .remove(transmission.peer, transmission.get_token());
+        }
+        while let Some((peer, length)) = self.next_notification() {
+            if let Some(length) = length {
+                return Some((peer, &self.buffer[..length]));
+            }
+        }
+        None
+    }
+
+    /// Takes the next observer to be notified and runs its registration request through
+    /// the handler again, RFC 7641 §4.2. Returns the length of the notification encoded
+    /// into the buffer, `None` if the handler defers it or it cannot be created.
+    /// A notification that is not a success ends the observation
+    fn next_notification(&mut self) -> Option<(Option<SocketAddr>, Option<usize>)> {
+        let transmissions = &self.transmissions;
+        let observer = self.observations.next_pending(|observer| {
+            let confirmable = observer.t == CoapHeaderType::Confirmable;
+            let in_flight = transmissions
+                .iter()
+                .any(|transmission| observer.is(transmission.peer, transmission.get_token()));
+            !(in_flight || (confirmable && transmissions.is_full()))
+        })?;
+        observer.pending = false;
+        let peer = observer.peer;
+        let t = observer.t;
+        let token = CoapToken::from_slice(observer.get_token());
+        let request = observer.get_request();
+        let length = request.len();
+        match self.buffer.get_mut(..length) {
+            Some(buffer) => buffer.copy_from_slice(request),
+            None => return Some((peer, None)),
+        }
+
+        let (token, mut notification) = match (token, self.notify_observer(length, peer)) {
+            (Ok(token), Ok(Some(notification))) => (token, notification),
+            _ => return Some((peer, None)),
+        };
+        let token = token.as_slice();
+        let code = notification.header.get_code();
+        let message_id = self.message_id;
+        let header = CoapHeader::new(t, notification.header.get_tkl(), code, message_id);
+        match header {
+            Ok(header) => notification.header = header,
+            Err(_) => return Some((peer, None)),
+        }
+        self.message_id = self.message_id.wrapping_add(1);
+        if code.is_success() {
+            let sequence = self.next_sequence();
+            let option = CoapOption::uint(CoapOptionNumbers::Observe, sequence);
+            if option
+                .and_then(|option| notification.add_option(option))
+                .is_err()
+            {
+                return Some((peer, None));
+            }
+            if let Some(observer) = self.observations.find_mut(peer, token) {
+                observer.message_id = Some(message_id);
+            }
+        } else {
+            self.observations.remove(peer, token);
+        }
+        let length = match notification.encode_into(self.buffer) {
+            Ok(length) => length,
+            Err(_) => return Some((peer, None)),
+        };
+        if t == CoapHeaderType::Confirmable {
+            let backoff = self.backoff();
+            match Transmission::new(peer, &self.buffer[..length], backoff) {
+                // Checked to have room when the observer was taken
+                Ok(transmission) => {
+                    let _ = self.transmissions.push(transmission);
                 }
-            },
+                Err(_) => return Some((peer, None)),
+            }
+        }
+        Some((peer, Some(length)))
+    }
+
+    /// Passes the registration request of an observer, copied to the start of the server
+    /// buffer, to the handler again. Returns `None` if the handler defers the response
+    fn notify_observer(
+        &mut self,
+        length: usize,
+        peer: Option<SocketAddr>,
+    ) -> Result<Option<CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>>, CoapError> {
+        let buffer = core::mem::take(&mut self.buffer);
+        let handled =
+            CoapMessageRef::decode(&buffer[..length]).and_then(|msg| self.call_handler(msg, peer));
+        self.buffer = buffer;
+        let handled = handled?;
+        Ok(handled.deferred.is_none().then_some(handled.response))
+    }
+
+    fn now(&self) -> u64 {
+        self.clock.map_or(0, |clock| clock.now())
+    }
+
+    /// Starts the retransmission timing of a confirmable message sent now
+    fn backoff(&self) -> Backoff {
+        let now = self.now();
+        let random = match self.random {
+            Some(random) => random.random(),
+            None => exchange::fallback_random(now, self.message_id),
         };
+        Backoff::new(now, random)
+    }
+
+    /// Handles a request and registers or deregisters its client as an observer.
+    /// Returns `None` if there is nothing to send, for a deferred non-confirmable request
+    fn handle_request(
+        &mut self,
+        msg: CoapMessageRef,
+        peer: Option<SocketAddr>,
+    ) -> Result<Option<CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>>, CoapError> {
+        let handled = self.call_handler(msg, peer)?;
+        if let Some(code) = handled.deferred {
+            let exchange = CoapExchange::new(peer, CoapToken::from_slice(msg.get_token())?);
+            return self.defer(msg, exchange, code);
+        }
+        let mut response = handled.response;
+        self.observe(msg, peer, handled.resource, &mut response)?;
+        Ok(Some(response))
+    }
 
-        let encoded_response = response.unwrap().encode().unwrap();
-
-        let mut msg_resp = Vec::<u8, U255>::from_slice(&encoded_response.0).unwrap();
-        msg_resp.truncate(encoded_response.1);
-        msg_resp
-    }
-
-    fn handle_get(self, msg: message::CoapMessage) -> Option<message::CoapMessage> {
-        let mut payload: u8 = 0;
-        let mut uri_path: String<U255> = String::new();
-        let mut amount_of_uri_path_options: usize = 0;
-        for opt in msg.options.options.iter() {
-            match opt.get_option_number() {
-                CoapOptionNumbers::UriPath => {
-                    if amount_of_uri_path_options != 0 {
-                        uri_path.push_str("/").unwrap();
-                    }
-                    amount_of_uri_path_options += 1;
-                    uri_path
-                        .push_str(String::from_utf8(opt.get_option_data()).unwrap().as_str())
-                        .unwrap();
+    /// Passes the request to the handler of the resource whose path template matches
+    /// its path best and that handles its method, see [`router`].
+    /// The response starts out with the default code of the method.
+    /// `/.well-known/core` is served before any template is matched
+    fn call_handler(
+        &mut self,
+        msg: CoapMessageRef,
+        peer: Option<SocketAddr>,
+    ) -> Result<Handled<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>, CoapError> {
+        let block2 = match Self::check_blocks(msg) {
+            Ok(block2) => block2,
+            Err(code) => return msg.response(code).map(Handled::from),
+        };
+        let method = CoapMethod::from_code(msg.header.get_code());
+        if router::match_path(WELL_KNOWN_CORE, &msg).is_some() {
+            return self.well_known_core(msg, method, block2).map(Handled::from);
+        }
+        let mut path_found = false;
+        let mut best = None;
+        for (index, res) in self.config.resources.iter().enumerate() {
+            if let Some((specificity, _)) = router::match_path(&res.path, &msg) {
+                path_found = true;
+                let method_matches =
+                    method.is_some() && (res.method.is_none() || res.method == method);
+                if method_matches && best.is_none_or(|(_, best)| specificity > best) {
+                    best = Some((index, specificity));
                 }
-                _ => panic!(),
             }
         }
-        if amount_of_uri_path_options > 0 {
-            for res in self.config.resources.iter() {
-                if uri_path == res.get_path() {
-                    payload = res.callback()();
-                }
+        let (index, method) = match (best, method) {
+            (Some((index, _)), Some(method)) => (index, method),
+            _ if path_found => return Self::method_not_allowed(msg).map(Handled::from),
+            _ => return msg.response(CoapHeaderCode::NotFound).map(Handled::from),
+        };
+
+        let resource = &mut self.config.resources[index];
+        let params = match router::match_path(&resource.path, &msg) {
+            Some((_, params)) => params,
+            None => return msg.response(CoapHeaderCode::NotFound).map(Handled::from),
+        };
+        let request = CoapRequest::new(msg, peer, params);
+        let code = Self::default_code(method);
+        let mut response = msg.response(code)?;
+        let mut writer = CoapResponse::new(&mut response, block2);
+        let result = resource.handler.as_handler().handle(&request, &mut writer);
+        let deferred = (result.is_ok() && writer.is_deferred()).then_some(code);
+        if result.is_err() {
+            response = msg.response(CoapHeaderCode::InternalServerError)?;
+        }
+        Ok(Handled {
+            response,
+            resource: Some(index),
+            deferred,
+        })
+    }
+
+    /// Registers the client as an observer of the resource if the request is a GET or FETCH
+    /// carrying Observe 0 and it is answered with success, RFC 7641 §4.1.
+    /// The response then carries the Observe option, unless there is no room for another
+    /// observer of the server or of the resource, or its request is longer than
+    /// `MESSAGE_SIZE`. Any other request with the token of an observer ends the observation
+    fn observe(
+        &mut self,
+        msg: CoapMessageRef,
+        peer: Option<SocketAddr>,
+        resource: Option<usize>,
+        response: &mut CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>,
+    ) -> Result<(), CoapError> {
+        let register = msg
+            .get_options()
+            .find(|option| option.get_option_number() == CoapOptionNumbers::Observe)
+            .is_some_and(|option| option.get_uint() == Ok(0));
+        let observable = matches!(
+            CoapMethod::from_code(msg.header.get_code()),
+            Some(CoapMethod::GET | CoapMethod::FETCH)
+        ) && response.header.get_code().is_success();
+        let observed = resource.is_some_and(|index| {
+            register && observable && self.config.resources[index].attributes.is_obs()
+        });
+        let request = match msg.to_vec() {
+            Ok(request) if observed => request,
+            _ => {
+                self.observations.remove(peer, msg.get_token());
+                return Ok(());
             }
+        };
+        let observer = Observer::new(peer, msg.header.get_type(), request);
+        let resources = &self.config.resources;
+        let path = resource.map(|index| resources[index].path.as_str());
+        if self
+            .observations
+            .register(observer, path.unwrap_or_default())
+        {
+            let sequence = self.next_sequence();
+            response.add_option(CoapOption::uint(CoapOptionNumbers::Observe, sequence)?)?;
         }
+        Ok(())
+    }
 
-        if payload == 0 {
-            let header_type = CoapHeaderType::Acknowledgement;
-            let header_code = CoapHeaderCode::NotFound;
-            let header = CoapHeader::new(
-                header_type,
-                msg.header.get_tkl(),
-                header_code,
-                msg.header.get_message_id(),
-            )
-            .unwrap();
-            let response = message::CoapMessage::new(header, &[]);
-            return Some(response);
+    /// Returns the Observe sequence number of the next notification, RFC 7641 §4.4
+    fn next_sequence(&mut self) -> u32 {
+        let sequence = self.sequence & observe::SEQUENCE_MASK;
+        self.sequence = self.sequence.wrapping_add(1);
+        sequence
+    }
+
+    /// Keeps a request deferred by its handler until [`CoapServer::respond`] is called.
+    /// Confirmable requests are acknowledged with an empty ACK right away,
+    /// 5.03 Service Unavailable is returned if too many requests are deferred
+    fn defer(
+        &mut self,
+        msg: CoapMessageRef,
+        exchange: CoapExchange,
+        code: CoapHeaderCode,
+    ) -> Result<Option<CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>>, CoapError> {
+        let t = msg.header.get_type();
+        // A retransmitted request replaces the one deferred before
+        self.deferred
+            .retain(|deferred| deferred.exchange != exchange);
+        let deferred = Deferred { exchange, t, code };
+        if self.deferred.push(deferred).is_err() {
+            return msg.response(CoapHeaderCode::ServiceUnavailable).map(Some);
         }
+        if t != CoapHeaderType::Confirmable {
+            return Ok(None);
+        }
+        let header = CoapHeader::new(
+            CoapHeaderType::Acknowledgement,
+            0,
+            CoapHeaderCode::EMPTY,
+            msg.header.get_message_id(),
+        )?;
+        CoapMessage::from_header(header, &[]).map(Some)
+    }
 
-        if msg.header.get_type() == CoapHeaderType::Confirmable {
-            let header_type = CoapHeaderType::Acknowledgement;
-            let header_code = CoapHeaderCode::Content;
-            let header = CoapHeader::new(
-                header_type,
-                msg.header.get_tkl(),
-                header_code,
-                msg.header.get_message_id(),
-            )
-            .unwrap();
-            let response = message::CoapMessage::new(header, &[payload]);
-            return Some(response);
-        } else if msg.header.get_type() == CoapHeaderType::NonConfirmable {
-            let header_type = CoapHeaderType::NonConfirmable;
-            let header_code = CoapHeaderCode::Content;
-            let header = CoapHeader::new(
-                header_type,
-                msg.header.get_tkl(),
-                header_code,
-                msg.header.get_message_id(),
-            )
-            .unwrap();
-            let response = message::CoapMessage::new(header, &[payload]);
-            return Some(response);
-        } else {
-            return None;
+    /// Checks the block options of a request, RFC 7959 §2.
+    /// Returns the Block2 option, or the code of the error response. Request payloads
+    /// are not reassembled, Block1 is only accepted for a payload in a single block
+    fn check_blocks(msg: CoapMessageRef) -> Result<Option<CoapBlock>, CoapHeaderCode> {
+        let mut block2 = None;
+        for option in msg.get_options() {
+            let number = option.get_option_number();
+            if number != CoapOptionNumbers::Block1 && number != CoapOptionNumbers::Block2 {
+                continue;
+            }
+            let block = option
+                .get_uint()
+                .and_then(CoapBlock::decode)
+                .map_err(|_| CoapHeaderCode::BadRequest)?;
+            match number {
+                CoapOptionNumbers::Block2 => block2 = Some(block),
+                _ if block.get_num() > 0 => return Err(CoapHeaderCode::RequestEntityIncomplete),
+                _ if block.is_more() => return Err(CoapHeaderCode::RequestEntityTooLarge),
+                _ => {}
+            }
         }
+        Ok(block2)
     }
 
-    fn handle_post(self, _msg: message::CoapMessage) -> Option<message::CoapMessage> {
-        None
+    /// Answers GET `/.well-known/core` with the links to the resources, RFC 6690 §4.
+    /// Only resources with a plain path are listed, once per path with the attributes
+    /// of the first of them. Uri-Query options filter the links, RFC 6690 §4.1.
+    /// Documents larger than a message are sent in blocks, written again for every block
+    /// while counting the size of the whole document
+    fn well_known_core(
+        &self,
+        msg: CoapMessageRef,
+        method: Option<CoapMethod>,
+        block2: Option<CoapBlock>,
+    ) -> Result<CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>, CoapError> {
+        if method != Some(CoapMethod::GET) {
+            return Self::method_not_allowed(msg);
+        }
+        let mut response = msg.response(CoapHeaderCode::Content)?;
+        response.set_content_format(CoapMediaType::ApplicationLinkFormat)?;
+        let mut writer = CoapResponse::new(&mut response, block2);
+        writer.set_block_payload_sized(|offset, buffer| {
+            // Writing to a block writer can not fail
+            let mut window = BlockWriter::new(offset, buffer);
+            let _ = self.write_links(msg, &mut window);
+            Ok((window.get_written(), Some(window.get_position())))
+        })?;
+        Ok(response)
     }
 
-    fn handle_put(self, _msg: message::CoapMessage) -> Option<message::CoapMessage> {
-        None
+    /// Writes the links of `/.well-known/core` selected by the query of the request
+    fn write_links<W: Write>(&self, msg: CoapMessageRef, w: &mut W) -> fmt::Result {
+        let resources = &self.config.resources;
+        let mut first = true;
+        for (index, res) in resources.iter().enumerate() {
+            if !router::is_plain(&res.path) || resources[..index].iter().any(|r| r.path == res.path)
+            {
+                continue;
+            }
+            let selected = CoapQueryIter::new(msg.get_options()).all(|(key, value)| {
+                res.attributes
+                    .matches_filter(&res.path, key, value.unwrap_or(""))
+            });
+            if !selected {
+                continue;
+            }
+            if !first {
+                w.write_str(",")?;
+            }
+            first = false;
+            res.attributes.write_link(w, &res.path)?;
+        }
+        Ok(())
     }
 
-    fn handle_delete(self, _msg: message::CoapMessage) -> Option<message::CoapMessage> {
-        None
+    /// Returns the code a successful request with the method is answered with by default
+    fn default_code(method: CoapMethod) -> CoapHeaderCode {
+        match method {
+            CoapMethod::GET | CoapMethod::FETCH => CoapHeaderCode::Content,
+            CoapMethod::POST => CoapHeaderCode::Created,
+            CoapMethod::DELETE => CoapHeaderCode::Deleted,
+            CoapMethod::PUT | CoapMethod::PATCH | CoapMethod::IPATCH => CoapHeaderCode::Changed,
+        }
+    }
+
+    /// Rejects a message that could not be decoded, RFC 7252 §4.2 and §4.3.
+    /// A confirmable message is answered with a reset if its header can be read,
+    /// anything else is silently ignored by returning the error
+    fn reject_malformed(&mut self, msg: &[u8], e: CoapError) -> Result<&[u8], CoapError> {
+        match CoapHeader::decode(msg) {
+            Ok(header) if header.get_type() == CoapHeaderType::Confirmable => {
+                self.reset(header.get_message_id())
+            }
+            _ => Err(e),
+        }
+    }
+
+    /// Answers a message carrying an unrecognized critical option, RFC 7252 §5.4.1.
+    /// Confirmable requests get a 4.02 Bad Option response, anything else is rejected with a reset
+    fn reject_bad_option(&mut self, msg: CoapMessageRef) -> Result<&[u8], CoapError> {
+        if msg.header.get_type() == CoapHeaderType::Confirmable
+            && msg.header.get_code().is_request()
+        {
+            return self.send(msg.response(CoapHeaderCode::BadOption)?);
+        }
+        self.reset(msg.header.get_message_id())
+    }
+
+    /// Sends a reset matching the message id
+    fn reset(&mut self, message_id: u16) -> Result<&[u8], CoapError> {
+        let header = CoapHeader::new(CoapHeaderType::Reset, 0, CoapHeaderCode::EMPTY, message_id)?;
+        self.send(CoapMessage::from_header(header, &[])?)
+    }
+
+    /// Acknowledges a confirmable message with an empty ACK
+    fn acknowledge(&mut self, message_id: u16) -> Result<&[u8], CoapError> {
+        let header = CoapHeader::new(
+            CoapHeaderType::Acknowledgement,
+            0,
+            CoapHeaderCode::EMPTY,
+            message_id,
+        )?;
+        self.send(CoapMessage::from_header(header, &[])?)
+    }
+
+    /// Answers requests with methods that have no handler with 4.05 Method Not Allowed
+    fn method_not_allowed(
+        msg: CoapMessageRef,
+    ) -> Result<CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>, CoapError> {
+        msg.response(CoapHeaderCode::MethodNotAllowed)
     }
-    
 }
 
 #[cfg(test)]
@@ -247,9 +1087,9 @@ mod tests {
     #[test]
     fn resource_calling() {
         let mut config = CoapConfig::new();
-        config.add_resource(test, "test");
+        config.add_resource(test, "test").unwrap();
         let mut buffer: [u8; 1024] = [0; 1024];
-        let server = CoapServer::new(config, &mut buffer);
+        let mut server = CoapServer::new(config, &mut buffer);
 
         let header =
             CoapHeader::new(CoapHeaderType::Confirmable, 2, CoapHeaderCode::GET, 123).unwrap();
@@ -257,18 +1097,14 @@ mod tests {
         let option = CoapOption::new(CoapOptionNumbers::UriPath, "test".as_bytes());
         msg.add_option(option).unwrap();
         msg.set_token(&[100, 101]).unwrap();
-        let mut raw_msg = msg.encode().unwrap();
-        let resp = server.handle_message(&mut raw_msg.0);
+        let raw_msg = msg.encode().unwrap();
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
 
-        let expected_response = [98, 69, 0, 123, 255, test()];
-        let mut ex_resp = Vec::<u8, U255>::from_slice(&expected_response).unwrap();
-        ex_resp.truncate(expected_response.len());
-
-        assert_eq!(ex_resp, resp);
+        let expected_response = [98, 69, 0, 123, 100, 101, 255, test()];
+        assert_eq!(resp, &expected_response[..]);
     }
 
     fn test() -> u8 {
-        assert_eq!("foo", "foo");
         1
     }
 
@@ -283,12 +1119,14 @@ mod tests {
     #[test]
     fn multiple_uri_path() {
         let mut config = CoapConfig::new();
-        config.add_resource(test, "test");
-        config.add_resource(test_level, "test/level");
-        config.add_resource(test_level_cheese, "test/level/cheese");
+        config.add_resource(test, "test").unwrap();
+        config.add_resource(test_level, "test/level").unwrap();
+        config
+            .add_resource(test_level_cheese, "test/level/cheese")
+            .unwrap();
 
         let mut buffer: [u8; 1024] = [0; 1024];
-        let server = CoapServer::new(config, &mut buffer);
+        let mut server = CoapServer::new(config, &mut buffer);
 
         let header =
             CoapHeader::new(CoapHeaderType::Confirmable, 2, CoapHeaderCode::GET, 123).unwrap();
@@ -300,25 +1138,24 @@ mod tests {
         msg.add_option(option_2).unwrap();
         msg.add_option(option_3).unwrap();
         msg.set_token(&[100, 101]).unwrap();
-        let mut raw_msg = msg.encode().unwrap();
-        let resp = server.handle_message(&mut raw_msg.0);
-
-        let expected_response = [98, 69, 0, 123, 255, test_level_cheese()];
-        let mut ex_resp = Vec::<u8, U255>::from_slice(&expected_response).unwrap();
-        ex_resp.truncate(expected_response.len());
+        let raw_msg = msg.encode().unwrap();
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
 
-        assert_eq!(ex_resp, resp);
+        let expected_response = [98, 69, 0, 123, 100, 101, 255, test_level_cheese()];
+        assert_eq!(resp, &expected_response[..]);
     }
 
     #[test]
     fn endpoint_not_found() {
         let mut config = CoapConfig::new();
-        config.add_resource(test, "test");
-        config.add_resource(test_level, "test/level");
-        config.add_resource(test_level_cheese, "test/level/cheese");
+        config.add_resource(test, "test").unwrap();
+        config.add_resource(test_level, "test/level").unwrap();
+        config
+            .add_resource(test_level_cheese, "test/level/cheese")
+            .unwrap();
 
         let mut buffer: [u8; 1024] = [0; 1024];
-        let server = CoapServer::new(config, &mut buffer);
+        let mut server = CoapServer::new(config, &mut buffer);
 
         let header =
             CoapHeader::new(CoapHeaderType::Confirmable, 2, CoapHeaderCode::GET, 123).unwrap();
@@ -330,29 +1167,1144 @@ mod tests {
         msg.add_option(option_2).unwrap();
         msg.add_option(option_3).unwrap();
         msg.set_token(&[100, 101]).unwrap();
-        let mut raw_msg = msg.encode().unwrap();
-        let resp = server.handle_message(&mut raw_msg.0);
-
-        let expected_response = [98, 132, 0, 123];
-        let mut ex_resp = Vec::<u8, U255>::from_slice(&expected_response).unwrap();
-        ex_resp.truncate(expected_response.len());
+        let raw_msg = msg.encode().unwrap();
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
 
-        assert_eq!(ex_resp, resp);
+        let expected_response = [98, 132, 0, 123, 100, 101];
+        assert_eq!(resp, &expected_response[..]);
     }
 
     #[test]
     fn remove_resource() {
         let mut config = CoapConfig::new();
-        config.add_resource(test, "test");
-        config.add_resource(test_level, "test/level");
-        config.add_resource(test_level_cheese, "test/level/cheese");
+        config.add_resource(test, "test").unwrap();
+        config.add_resource(test_level, "test/level").unwrap();
+        config
+            .add_resource(test_level_cheese, "test/level/cheese")
+            .unwrap();
 
-        config.remove_resource(&"test/level/cheese");
+        config.remove_resource("test/level/cheese");
 
         let mut config_2 = CoapConfig::new();
-        config_2.add_resource(test, "test");
-        config_2.add_resource(test_level, "test/level");
+        config_2.add_resource(test, "test").unwrap();
+        config_2.add_resource(test_level, "test/level").unwrap();
+
+        assert_eq!(config, config_2);
+    }
+
+    #[test]
+    fn response_buffer_too_small() {
+        let mut config = CoapConfig::new();
+        config.add_resource(test, "test").unwrap();
+        let mut buffer: [u8; 4] = [0; 4];
+        let mut server = CoapServer::new(config, &mut buffer);
+
+        let header =
+            CoapHeader::new(CoapHeaderType::Confirmable, 0, CoapHeaderCode::GET, 123).unwrap();
+        let mut msg = message::CoapMessage::new(header, &[]);
+        let option = CoapOption::new(CoapOptionNumbers::UriPath, "test".as_bytes());
+        msg.add_option(option).unwrap();
+        let raw_msg = msg.encode().unwrap();
+
+        match server.handle_message(&raw_msg.0[..raw_msg.1]) {
+            Err(CoapError::BufferTooSmall { needed, available }) => {
+                assert_eq!(needed, 6);
+                assert_eq!(available, 4);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn unrecognized_critical_option() {
+        let config = CoapConfig::new();
+        let mut buffer: [u8; 1024] = [0; 1024];
+        let mut server = CoapServer::new(config, &mut buffer);
+
+        // CON GET with token [100] and the unknown critical option 1001
+        let request = [0x41, 1, 0, 123, 100, 0xe1, 0x02, 0xdc, b'a'];
+        let resp = server.handle_message(&request).unwrap();
+        assert_eq!(resp, &[0x61, 130, 0, 123, 100][..]);
+
+        let mut buffer: [u8; 1024] = [0; 1024];
+        let mut server = CoapServer::new(CoapConfig::new(), &mut buffer);
+        // The same request as NON is rejected with a reset
+        let request = [0x51, 1, 0, 123, 100, 0xe1, 0x02, 0xdc, b'a'];
+        let resp = server.handle_message(&request).unwrap();
+        assert_eq!(resp, &[0x70, 0, 0, 123][..]);
+
+        // A CON 2.05 response is rejected with a reset rather than answered with 4.02
+        let request = [0x41, 69, 0, 124, 100, 0xe1, 0x02, 0xdc, b'a'];
+        let resp = server.handle_message(&request).unwrap();
+        assert_eq!(resp, &[0x70, 0, 0, 124][..]);
+
+        // An acknowledgement is silently ignored
+        let request = [0x61, 69, 0, 125, 100, 0xe1, 0x02, 0xdc, b'a'];
+        assert_eq!(server.handle_message(&request).unwrap(), &[][..]);
+    }
+
+    #[test]
+    fn stray_responses() {
+        let mut buffer: [u8; 1024] = [0; 1024];
+        let mut server = CoapServer::new(CoapConfig::new(), &mut buffer);
+        // A CON 2.05 is rejected with a reset
+        let resp = server.handle_message(&[0x40, 69, 0, 123]).unwrap();
+        assert_eq!(resp, &[0x70, 0, 0, 123][..]);
+        // A NON 2.05 is ignored
+        let resp = server.handle_message(&[0x50, 69, 0, 124]).unwrap();
+        assert_eq!(resp, &[][..]);
+    }
+
+    #[test]
+    fn unknown_elective_option_is_ignored() {
+        let mut config = CoapConfig::new();
+        config.add_resource(test, "test").unwrap();
+        let mut buffer: [u8; 1024] = [0; 1024];
+        let mut server = CoapServer::new(config, &mut buffer);
+
+        // CON GET test with the unknown elective option 1000 after Uri-Path
+        let request = [
+            0x40, 1, 0, 123, 0xb4, b't', b'e', b's', b't', 0xe1, 0x02, 0xd0, b'a',
+        ];
+        let resp = server.handle_message(&request).unwrap();
+        assert_eq!(resp, &[0x60, 69, 0, 123, 255, test()][..]);
+    }
+
+    #[test]
+    fn ping() {
+        let mut buffer: [u8; 1024] = [0; 1024];
+        let mut server = CoapServer::new(CoapConfig::new(), &mut buffer);
+        let resp = server.handle_message(&[0x40, 0, 0, 123]).unwrap();
+        assert_eq!(resp, &[0x70, 0, 0, 123][..]);
+    }
+
+    #[test]
+    fn malformed_messages() {
+        // Confirmable with a payload marker but no payload is rejected with a reset
+        let mut buffer: [u8; 1024] = [0; 1024];
+        let mut server = CoapServer::new(CoapConfig::new(), &mut buffer);
+        let resp = server.handle_message(&[0x40, 1, 0, 123, 0xff]).unwrap();
+        assert_eq!(resp, &[0x70, 0, 0, 123][..]);
+
+        // Non-confirmable messages are silently ignored
+        let mut buffer: [u8; 1024] = [0; 1024];
+        let mut server = CoapServer::new(CoapConfig::new(), &mut buffer);
+        let resp = server.handle_message(&[0x50, 1, 0, 123, 0xff]);
+        assert_eq!(
+            resp,
+            Err(CoapError::PayloadMarkerWithoutPayload { offset: 4 })
+        );
+
+        // Truncated header
+        let mut buffer: [u8; 1024] = [0; 1024];
+        let mut server = CoapServer::new(CoapConfig::new(), &mut buffer);
+        let resp = server.handle_message(&[0x40, 1]);
+        assert_eq!(resp, Err(CoapError::TruncatedHeader { length: 2 }));
+
+        // A request in an acknowledgement
+        let mut buffer: [u8; 1024] = [0; 1024];
+        let mut server = CoapServer::new(CoapConfig::new(), &mut buffer);
+        let resp = server.handle_message(&[0x60, 1, 0, 123]);
+        assert_eq!(resp, Err(CoapError::InvalidMessageType));
+
+        // Reserved option delta in a confirmable request
+        let mut buffer: [u8; 1024] = [0; 1024];
+        let mut server = CoapServer::new(CoapConfig::new(), &mut buffer);
+        let resp = server.handle_message(&[0x41, 1, 0, 123, 7, 0xb1, b'a', 0xf1, 0]);
+        assert_eq!(resp.unwrap(), &[0x70, 0, 0, 123][..]);
+
+        // Confirmable empty message with a token
+        let resp = server.handle_message(&[0x41, 0, 0, 1, 7]);
+        assert_eq!(resp.unwrap(), &[0x70, 0, 0, 1][..]);
+        let resp = server.handle_message(&[0x51, 0, 0, 1, 7]);
+        assert_eq!(resp, Err(CoapError::InvalidEmptyMessage));
+    }
+
+    #[test]
+    fn unhandled_methods() {
+        let mut config = CoapConfig::new();
+        config.add_resource(test, "test").unwrap();
+        let mut buffer: [u8; 1024] = [0; 1024];
+        let mut server = CoapServer::new(config, &mut buffer);
+        // NON POST test
+        let request = [0x50, 2, 0, 123, 0xb4, b't', b'e', b's', b't'];
+        let resp = server.handle_message(&request).unwrap();
+        assert_eq!(resp, &[0x50, 133, 0, 123][..]);
+    }
+
+    #[test]
+    fn resource_capacity() {
+        let mut config = CoapConfig::new();
+        for _ in 0..8 {
+            config.add_resource(test, "test").unwrap();
+        }
+        assert_eq!(
+            config.add_resource(test, "test"),
+            Err(CoapError::CapacityExceeded { capacity: 8 })
+        );
+    }
+
+    #[test]
+    fn custom_capacities() {
+        let mut config = CoapConfig::<16, 2, 8, 16>::default();
+        for _ in 0..16 {
+            config.add_resource(test, "test").unwrap();
+        }
+        assert_eq!(
+            config.add_resource(test, "test"),
+            Err(CoapError::CapacityExceeded { capacity: 16 })
+        );
+        let mut buffer: [u8; 64] = [0; 64];
+        let mut server = CoapServer::new(config, &mut buffer);
+
+        let header =
+            CoapHeader::new(CoapHeaderType::NonConfirmable, 0, CoapHeaderCode::GET, 9).unwrap();
+        let mut msg = CoapMessage::new(header, &[]);
+        msg.add_option(CoapOption::new(CoapOptionNumbers::UriPath, b"test"))
+            .unwrap();
+        let raw_msg = msg.encode().unwrap();
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+        assert_eq!(resp, &[80, 69, 0, 9, 255, test()][..]);
+    }
+
+    fn request(code: CoapHeaderCode, path: &str, payload: &[u8]) -> ([u8; 255], usize) {
+        CoapMessage::builder(code)
+            .path(path)
+            .token(&[7])
+            .message_id(5)
+            .confirmable()
+            .payload(payload)
+            .build()
+            .unwrap()
+            .encode()
+            .unwrap()
+    }
+
+    /// A client address, requests from different clients are never duplicates
+    fn client(n: u8) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, n], 42))
+    }
+
+    #[test]
+    fn closure_handler() {
+        let mut calls = 0;
+        let mut handler = |request: &CoapRequest, response: &mut CoapResponse| {
+            calls += 1;
+            assert_eq!(request.get_code(), CoapHeaderCode::POST);
+            assert_eq!(request.get_token(), &[7]);
+            assert_eq!(request.get_payload(), b"on");
+            response.set_code(CoapHeaderCode::Changed);
+            response.set_content_format(CoapMediaType::TextPlain)?;
+            response.set_payload(b"switched on")
+        };
+        let mut config = CoapConfig::new();
+        config.add_handler("lamp", &mut handler).unwrap();
+        let mut buffer: [u8; 64] = [0; 64];
+        let mut server = CoapServer::new(config, &mut buffer);
+
+        let raw_msg = request(CoapHeaderCode::POST, "lamp", b"on");
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+        let mut expected = [0u8; 18];
+        expected[..7].copy_from_slice(&[0x61, 68, 0, 5, 7, 0xc0, 0xff]);
+        expected[7..].copy_from_slice(b"switched on");
+        assert_eq!(resp, &expected[..]);
+        drop(server);
+        assert_eq!(calls, 1);
+    }
+
+    struct Counter {
+        count: u8,
+    }
+
+    impl CoapHandler for Counter {
+        fn handle(
+            &mut self,
+            request: &CoapRequest,
+            response: &mut CoapResponse,
+        ) -> Result<(), CoapError> {
+            if request.get_payload() == b"fail" {
+                return Err(CoapError::InvalidCode);
+            }
+            self.count += 1;
+            response.add_uint_option(CoapOptionNumbers::MaxAge, 60)?;
+            response.set_payload(&[self.count, request.get_peer().unwrap().port() as u8])
+        }
+    }
+
+    #[test]
+    fn stateful_handler() {
+        let mut counter = Counter { count: 0 };
+        let mut config = CoapConfig::new();
+        config.add_handler("count", &mut counter).unwrap();
+        let mut buffer: [u8; 64] = [0; 64];
+        let mut server = CoapServer::new(config, &mut buffer);
+        for expected_count in 1..=2 {
+            let raw_msg = request(CoapHeaderCode::GET, "count", &[]);
+            let resp = server
+                .handle_message_from(client(expected_count), &raw_msg.0[..raw_msg.1])
+                .unwrap();
+            assert_eq!(
+                resp,
+                &[0x61, 69, 0, 5, 7, 0xd1, 1, 60, 0xff, expected_count, 42][..]
+            );
+        }
+
+        // A failing handler is answered with 5.00
+        let raw_msg = request(CoapHeaderCode::GET, "count", b"fail");
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+        assert_eq!(resp, &[0x61, 160, 0, 5, 7][..]);
+        drop(server);
+        assert_eq!(counter.count, 2);
+    }
+
+    #[test]
+    fn method_handlers() {
+        let mut get = |_: &CoapRequest, response: &mut CoapResponse| response.set_payload(b"g");
+        let mut fetch = |_: &CoapRequest, response: &mut CoapResponse| response.set_payload(b"f");
+        let mut put = |_: &CoapRequest, _: &mut CoapResponse| Ok(());
+        let mut post = |_: &CoapRequest, _: &mut CoapResponse| Ok(());
+        let mut delete = |_: &CoapRequest, _: &mut CoapResponse| Ok(());
+
+        let cases: [(CoapHeaderCode, &str, &[u8]); 8] = [
+            (
+                CoapHeaderCode::GET,
+                "item",
+                &[0x61, 69, 0, 5, 7, 0xff, b'g'],
+            ),
+            (
+                CoapHeaderCode::FETCH,
+                "item",
+                &[0x61, 69, 0, 5, 7, 0xff, b'f'],
+            ),
+            (CoapHeaderCode::PUT, "item", &[0x61, 68, 0, 5, 7]),
+            (CoapHeaderCode::POST, "item", &[0x61, 65, 0, 5, 7]),
+            (CoapHeaderCode::DELETE, "item", &[0x61, 66, 0, 5, 7]),
+            // Not registered
+            (CoapHeaderCode::PATCH, "item", &[0x61, 133, 0, 5, 7]),
+            (CoapHeaderCode::from(8), "item", &[0x61, 133, 0, 5, 7]),
+            (CoapHeaderCode::GET, "other", &[0x61, 132, 0, 5, 7]),
+        ];
+        let mut config = CoapConfig::new();
+        config
+            .add_method_handler(CoapMethod::GET, "item", &mut get)
+            .unwrap();
+        config
+            .add_method_handler(CoapMethod::FETCH, "item", &mut fetch)
+            .unwrap();
+        config
+            .add_method_handler(CoapMethod::PUT, "item", &mut put)
+            .unwrap();
+        config
+            .add_method_handler(CoapMethod::POST, "item", &mut post)
+            .unwrap();
+        config
+            .add_method_handler(CoapMethod::DELETE, "item", &mut delete)
+            .unwrap();
+        let mut buffer: [u8; 64] = [0; 64];
+        let mut server = CoapServer::new(config, &mut buffer);
+        for (n, (code, path, expected)) in cases.iter().enumerate() {
+            let raw_msg = request(*code, path, &[]);
+            let resp = server
+                .handle_message_from(client(n as u8), &raw_msg.0[..raw_msg.1])
+                .unwrap();
+            assert_eq!(resp, *expected, "{}", code);
+        }
+    }
+
+    #[test]
+    fn path_templates() {
+        let mut relay = |request: &CoapRequest, response: &mut CoapResponse| {
+            let id = request.get_param("id").ok_or(CoapError::InvalidCode)?;
+            response.set_payload(id.as_bytes())
+        };
+        let mut master = |_: &CoapRequest, response: &mut CoapResponse| response.set_payload(b"m");
+        let mut rest = |request: &CoapRequest, response: &mut CoapResponse| {
+            let segments = request.get_rest().count() as u8;
+            response.set_payload(&[segments])
+        };
+
+        let cases: [(&str, &[u8]); 6] = [
+            ("actuators/12/state", &[0x61, 69, 0, 5, 7, 0xff, b'1', b'2']),
+            ("actuators/0/state", &[0x61, 69, 0, 5, 7, 0xff, b'm']),
+            ("actuators/12", &[0x61, 69, 0, 5, 7, 0xff, 1]),
+            ("actuators/12/state/x", &[0x61, 69, 0, 5, 7, 0xff, 3]),
+            ("actuators", &[0x61, 69, 0, 5, 7, 0xff, 0]),
+            ("sensors/12/state", &[0x61, 132, 0, 5, 7]),
+        ];
+        let mut config = CoapConfig::new();
+        config.add_handler("actuators/**", &mut rest).unwrap();
+        config
+            .add_handler("actuators/{id}/state", &mut relay)
+            .unwrap();
+        config
+            .add_handler("actuators/0/state", &mut master)
+            .unwrap();
+        let mut buffer: [u8; 64] = [0; 64];
+        let mut server = CoapServer::new(config, &mut buffer);
+        for (n, (path, expected)) in cases.iter().enumerate() {
+            let raw_msg = request(CoapHeaderCode::GET, path, &[]);
+            let resp = server
+                .handle_message_from(client(n as u8), &raw_msg.0[..raw_msg.1])
+                .unwrap();
+            assert_eq!(resp, *expected, "{}", path);
+        }
+
+        let mut config = CoapConfig::new();
+        assert_eq!(
+            config.add_resource(test, "a/**/b"),
+            Err(CoapError::InvalidPathTemplate)
+        );
+    }
+
+    #[test]
+    fn query_routing() {
+        let mut json = |_: &CoapRequest, response: &mut CoapResponse| response.set_payload(b"j");
+        let mut plain = |request: &CoapRequest, response: &mut CoapResponse| match request
+            .get_query_param("unit")
+        {
+            Some(Some(unit)) => response.set_payload(unit.as_bytes()),
+            _ => response.set_payload(b"p"),
+        };
+
+        let cases: [(&[&str], &[u8]); 3] = [
+            (&["format=json", "unit=c"], &[0x61, 69, 0, 5, 7, 0xff, b'j']),
+            (&["format=text", "unit=c"], &[0x61, 69, 0, 5, 7, 0xff, b'c']),
+            (&[], &[0x61, 69, 0, 5, 7, 0xff, b'p']),
+        ];
+        let mut config = CoapConfig::new();
+        config.add_handler("temp", &mut plain).unwrap();
+        config.add_handler("temp?format=json", &mut json).unwrap();
+        let mut buffer: [u8; 64] = [0; 64];
+        let mut server = CoapServer::new(config, &mut buffer);
+        for (n, (query, expected)) in cases.iter().enumerate() {
+            let mut builder = CoapMessage::get()
+                .path("temp")
+                .token(&[7])
+                .message_id(5)
+                .confirmable();
+            for item in query.iter() {
+                builder = builder.query(item);
+            }
+            let raw_msg = builder.build().unwrap().encode().unwrap();
+            let resp = server
+                .handle_message_from(client(n as u8), &raw_msg.0[..raw_msg.1])
+                .unwrap();
+            assert_eq!(resp, *expected);
+        }
+    }
+
+    #[test]
+    fn well_known_core() {
+        let mut temp = |_: &CoapRequest, _: &mut CoapResponse| Ok(());
+        let mut config = CoapConfig::new();
+        config.add_resource(test, "test").unwrap().set_attributes(
+            CoapLinkAttributes::new()
+                .rt("temperature-c")
+                .ct(CoapMediaType::TextPlain),
+        );
+        config.add_resource(test_level, "test/level").unwrap();
+        config
+            .add_method_handler(CoapMethod::PUT, "test/level", &mut temp)
+            .unwrap()
+            .set_attributes(CoapLinkAttributes::new().rt("ignored"));
+        config.add_resource(test, "test/{id}").unwrap();
+        config
+            .add_resource(test_level_cheese, "cheese")
+            .unwrap()
+            .set_attributes(CoapLinkAttributes::new().rt("food").obs());
+        let mut buffer: [u8; 128] = [0; 128];
+        let mut server = CoapServer::new(config, &mut buffer);
+        let raw_msg = request(CoapHeaderCode::GET, ".well-known/core", &[]);
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+        let document = b"</test>;rt=\"temperature-c\";ct=0,</test/level>,</cheese>;rt=\"food\";obs";
+        assert_eq!(resp[..8], [0x61, 69, 0, 5, 7, 0xc1, 40, 0xff]);
+        assert_eq!(resp[8..], document[..]);
+
+        let filtered: [(&str, &[u8]); 5] = [
+            ("rt=temp*", b"</test>;rt=\"temperature-c\";ct=0"),
+            (
+                "href=/test*",
+                b"</test>;rt=\"temperature-c\";ct=0,</test/level>",
+            ),
+            ("obs", b"</cheese>;rt=\"food\";obs"),
+            ("rt=ignored", b""),
+            ("rt=none", b""),
+        ];
+        for (n, (query, expected)) in filtered.iter().enumerate() {
+            let raw_msg = CoapMessage::get()
+                .path(".well-known/core")
+                .query(query)
+                .message_id(n as u16)
+                .build()
+                .unwrap()
+                .encode()
+                .unwrap();
+            let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+            let payload = CoapMessageRef::decode(resp).unwrap().get_payload();
+            assert_eq!(payload, *expected, "{}", query);
+        }
+
+        let raw_msg = request(CoapHeaderCode::POST, ".well-known/core", &[]);
+        let resp = server
+            .handle_message_from(client(1), &raw_msg.0[..raw_msg.1])
+            .unwrap();
+        assert_eq!(resp, [0x61, 133, 0, 5, 7]);
+    }
+
+    #[test]
+    fn well_known_core_templates() {
+        let mut put = |_: &CoapRequest, _: &mut CoapResponse| Ok(());
+        let mut config = CoapConfig::new();
+        config.add_resource(test, "test").unwrap();
+        config.add_resource(test, "{a}/{b}").unwrap();
+        config
+            .add_method_handler(CoapMethod::PUT, "**", &mut put)
+            .unwrap();
+        let mut buffer: [u8; 128] = [0; 128];
+        let mut server = CoapServer::new(config, &mut buffer);
+
+        // Templates matching any path do not capture the discovery document
+        let raw_msg = request(CoapHeaderCode::GET, ".well-known/core", &[]);
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+        assert_eq!(resp[..8], [0x61, 69, 0, 5, 7, 0xc1, 40, 0xff]);
+        assert_eq!(resp[8..], b"</test>"[..]);
+        let raw_msg = request(CoapHeaderCode::GET, "other/path", &[]);
+        let resp = server
+            .handle_message_from(client(1), &raw_msg.0[..raw_msg.1])
+            .unwrap();
+        assert_eq!(resp, &[0x61, 69, 0, 5, 7, 0xff, test()][..]);
+    }
+
+    #[test]
+    fn root_resource() {
+        let mut config = CoapConfig::new();
+        config.add_resource(test, "").unwrap();
+        let mut buffer: [u8; 64] = [0; 64];
+        let mut server = CoapServer::new(config, &mut buffer);
+        let raw_msg = request(CoapHeaderCode::GET, "", &[]);
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+        assert_eq!(resp, &[0x61, 69, 0, 5, 7, 0xff, test()][..]);
+        let raw_msg = request(CoapHeaderCode::GET, ".well-known/core", &[]);
+        let resp = server
+            .handle_message_from(client(1), &raw_msg.0[..raw_msg.1])
+            .unwrap();
+        assert_eq!(resp[8..], b"</>"[..]);
+    }
+
+    #[test]
+    fn reuse_server() {
+        let mut config = CoapConfig::new();
+        config.add_resource(test, "test").unwrap();
+        let mut buffer: [u8; 64] = [0; 64];
+        let mut server = CoapServer::new(config, &mut buffer);
+
+        let raw_msg = request(CoapHeaderCode::GET, "test", &[]);
+        for n in 0..3 {
+            let resp = server
+                .handle_message_from(client(n), &raw_msg.0[..raw_msg.1])
+                .unwrap();
+            assert_eq!(resp, &[0x61, 69, 0, 5, 7, 0xff, test()][..]);
+        }
+
+        // Resources can be changed between messages
+        server.get_config_mut().remove_resource("test");
+        let resp = server
+            .handle_message_from(client(3), &raw_msg.0[..raw_msg.1])
+            .unwrap();
+        assert_eq!(resp, &[0x61, 132, 0, 5, 7][..]);
+        server
+            .get_config_mut()
+            .add_resource(test_level, "test")
+            .unwrap();
+        let resp = server
+            .handle_message_from(client(4), &raw_msg.0[..raw_msg.1])
+            .unwrap();
+        assert_eq!(resp, &[0x61, 69, 0, 5, 7, 0xff, test_level()][..]);
+        assert_eq!(server.get_config().resources.len(), 1);
+    }
+
+    #[test]
+    fn separate_response() {
+        let exchange = core::cell::RefCell::new(None);
+        let mut slow = |request: &CoapRequest, response: &mut CoapResponse| {
+            response.defer();
+            *exchange.borrow_mut() = Some(request.get_exchange());
+            Ok(())
+        };
+        let now = core::cell::Cell::new(0);
+        let clock = || now.get();
+        let random = || 500;
+        let mut config = CoapConfig::new();
+        config.add_handler("slow", &mut slow).unwrap();
+        let mut buffer: [u8; 64] = [0; 64];
+        let mut server = CoapServer::new(config, &mut buffer);
+        server.set_clock(&clock);
+        server.set_random(&random);
+        server.set_message_id(0x100);
+
+        // A confirmable request is acknowledged right away
+        let raw_msg = request(CoapHeaderCode::GET, "slow", &[]);
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+        assert_eq!(resp, &[0x60, 0, 0, 5][..]);
+        let deferred = exchange.borrow_mut().take().unwrap();
+        assert_eq!(deferred.get_token(), &[7]);
+
+        now.set(300);
+        let separate = [0x41, 69, 1, 0, 7, 0xff, b'2', b'1'];
+        let resp = server
+            .respond(&deferred, |response| response.set_payload(b"21"))
+            .unwrap();
+        assert_eq!(resp, &separate[..]);
+        assert_eq!(
+            server.respond(&deferred, |_| Ok(())),
+            Err(CoapError::UnknownExchange)
+        );
+
+        // Retransmitted until acknowledged, first after 2.5 s with this random number
+        now.set(2799);
+        assert_eq!(server.poll(), None);
+        now.set(2800);
+        assert_eq!(server.poll(), Some((None, &separate[..])));
+        assert_eq!(server.poll(), None);
+        now.set(7799);
+        assert_eq!(server.poll(), None);
+        now.set(7800);
+        assert_eq!(server.poll(), Some((None, &separate[..])));
+        let ack = [0x60, 0, 1, 0];
+        assert_eq!(server.handle_message(&ack).unwrap(), &[][..]);
+        now.set(100_000);
+        assert_eq!(server.poll(), None);
+
+        // Given up after 4 retransmissions
+        server
+            .handle_message_from(client(1), &raw_msg.0[..raw_msg.1])
+            .unwrap();
+        let deferred = exchange.borrow_mut().take().unwrap();
+        server.respond(&deferred, |_| Ok(())).unwrap();
+        let mut retransmissions = 0;
+        for _ in 0..100 {
+            now.set(now.get() + 1000);
+            if server.poll().is_some() {
+                retransmissions += 1;
+            }
+        }
+        assert_eq!(retransmissions, 4);
+
+        // Non-confirmable requests get a non-confirmable response
+        let raw_msg = CoapMessage::get()
+            .path("slow")
+            .token(&[8])
+            .message_id(6)
+            .build()
+            .unwrap()
+            .encode()
+            .unwrap();
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+        assert_eq!(resp, &[][..]);
+        let deferred = exchange.borrow_mut().take().unwrap();
+        let resp = server
+            .respond(&deferred, |response| {
+                response.set_code(CoapHeaderCode::ServiceUnavailable);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(resp, &[0x51, 163, 1, 2, 8][..]);
+        now.set(now.get() + 100_000);
+        assert_eq!(server.poll(), None);
+
+        // Too many deferred requests
+        for token in 0..5u8 {
+            let raw_msg = CoapMessage::get()
+                .path("slow")
+                .token(&[token])
+                .message_id(token as u16)
+                .confirmable()
+                .build()
+                .unwrap()
+                .encode()
+                .unwrap();
+            let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+            if token < 4 {
+                assert_eq!(resp, &[0x60, 0, 0, token][..]);
+            } else {
+                assert_eq!(resp, &[0x61, 163, 0, 4, 4][..]);
+            }
+        }
+    }
+
+    #[test]
+    fn duplicates() {
+        let toggles = core::cell::Cell::new(0u8);
+        let mut toggle = |_: &CoapRequest, response: &mut CoapResponse| {
+            toggles.set(toggles.get() + 1);
+            response.set_payload(&[toggles.get()])
+        };
+        let now = core::cell::Cell::new(0);
+        let clock = || now.get();
+        let mut config = CoapConfig::<8, 10, 255, 255, 4, 2>::default();
+        config.add_handler("relay", &mut toggle).unwrap();
+        let mut buffer: [u8; 64] = [0; 64];
+        let mut server = CoapServer::new(config, &mut buffer);
+        server.set_clock(&clock);
+
+        // The response to a confirmable request is sent again
+        let raw_msg = request(CoapHeaderCode::POST, "relay", &[]);
+        let expected = [0x61, 65, 0, 5, 7, 0xff, 1];
+        for _ in 0..2 {
+            let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+            assert_eq!(resp, &expected[..]);
+        }
+        // The same message id from another client is no duplicate
+        let resp = server
+            .handle_message_from(client(1), &raw_msg.0[..raw_msg.1])
+            .unwrap();
+        assert_eq!(resp, &[0x61, 65, 0, 5, 7, 0xff, 2][..]);
+        assert_eq!(toggles.get(), 2);
+
+        // Expired after EXCHANGE_LIFETIME
+        now.set(exchange::EXCHANGE_LIFETIME);
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+        assert_eq!(resp, &[0x61, 65, 0, 5, 7, 0xff, 3][..]);
+
+        // Duplicates of non-confirmable requests are ignored
+        let raw_msg = CoapMessage::post()
+            .path("relay")
+            .message_id(6)
+            .build()
+            .unwrap()
+            .encode()
+            .unwrap();
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+        assert_eq!(resp, &[0x50, 65, 0, 6, 0xff, 4][..]);
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+        assert_eq!(resp, &[][..]);
+        assert_eq!(toggles.get(), 4);
+
+        // Only the last 2 requests are remembered
+        let raw_msg = request(CoapHeaderCode::POST, "relay", &[]);
+        server
+            .handle_message_from(client(2), &raw_msg.0[..raw_msg.1])
+            .unwrap();
+        server
+            .handle_message_from(client(3), &raw_msg.0[..raw_msg.1])
+            .unwrap();
+        assert_eq!(toggles.get(), 6);
+        server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+        assert_eq!(toggles.get(), 7);
+        drop(server);
+
+        // A response too long to keep is not replayed, the duplicate is only acknowledged
+        let mut config = CoapConfig::<8, 10, 255, 255, 4, 2, 4, 320, 6>::default();
+        config.add_handler("relay", &mut toggle).unwrap();
+        let mut buffer: [u8; 64] = [0; 64];
+        let mut server = CoapServer::new(config, &mut buffer);
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+        assert_eq!(resp, &[0x61, 65, 0, 5, 7, 0xff, 8][..]);
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+        assert_eq!(resp, &[0x60, 0, 0, 5][..]);
+        drop(server);
+        assert_eq!(toggles.get(), 8);
+    }
+
+    #[test]
+    fn observe() {
+        let temperature = core::cell::Cell::new(b'0');
+        let mut temp = |_: &CoapRequest, response: &mut CoapResponse| {
+            response.set_payload(&[temperature.get()])
+        };
+        let mut hum = |_: &CoapRequest, response: &mut CoapResponse| response.set_payload(b"h");
+        let now = core::cell::Cell::new(0);
+        let clock = || now.get();
+        let mut config = CoapConfig::new();
+        config
+            .add_handler("sensors/temp", &mut temp)
+            .unwrap()
+            .set_attributes(CoapLinkAttributes::new().obs());
+        config.add_handler("sensors/hum", &mut hum).unwrap();
+        let mut buffer: [u8; 64] = [0; 64];
+        let mut server = CoapServer::new(config, &mut buffer);
+        server.set_clock(&clock);
+        server.set_message_id(0x100);
+        let observe = |path: &str, token: u8, message_id: u16, observe: u32| {
+            CoapMessage::get()
+                .path(path)
+                .token(&[token])
+                .message_id(message_id)
+                .option(CoapOption::uint(CoapOptionNumbers::Observe, observe).unwrap())
+        };
+
+        // Registrations are answered with a sequence number
+        let raw_msg = observe("sensors/temp", 1, 1, 0)
+            .confirmable()
+            .build()
+            .unwrap()
+            .encode()
+            .unwrap();
+        let resp = server
+            .handle_message_from(client(1), &raw_msg.0[..raw_msg.1])
+            .unwrap();
+        assert_eq!(resp, &[0x61, 69, 0, 1, 1, 0x60, 0xff, b'0'][..]);
+        let raw_msg = observe("sensors/temp", 2, 2, 0)
+            .build()
+            .unwrap()
+            .encode()
+            .unwrap();
+        let resp = server
+            .handle_message_from(client(2), &raw_msg.0[..raw_msg.1])
+            .unwrap();
+        assert_eq!(resp, &[0x51, 69, 0, 2, 2, 0x61, 1, 0xff, b'0'][..]);
+        // Resources not marked obs cannot be observed
+        let raw_msg = observe("sensors/hum", 3, 3, 0)
+            .confirmable()
+            .build()
+            .unwrap()
+            .encode()
+            .unwrap();
+        let resp = server
+            .handle_message_from(client(3), &raw_msg.0[..raw_msg.1])
+            .unwrap();
+        assert_eq!(resp, &[0x61, 69, 0, 3, 3, 0xff, b'h'][..]);
+        assert_eq!(server.notify("sensors/hum"), 0);
+        // Up to MAX_RESOURCE_OBSERVERS observers of one resource
+        let raw_msg = observe("sensors/temp", 7, 7, 0)
+            .build()
+            .unwrap()
+            .encode()
+            .unwrap();
+        let resp = server
+            .handle_message_from(client(3), &raw_msg.0[..raw_msg.1])
+            .unwrap();
+        assert_eq!(resp, &[0x51, 69, 0, 7, 7, 0xff, b'0'][..]);
+        assert_eq!(server.poll(), None);
+
+        // Notifications have the type of the registration
+        temperature.set(b'1');
+        assert_eq!(server.notify("sensors/temp"), 2);
+        let notification = [0x41, 69, 1, 0, 1, 0x61, 2, 0xff, b'1'];
+        assert_eq!(server.poll(), Some((Some(client(1)), &notification[..])));
+        let notification = [0x51, 69, 1, 1, 2, 0x61, 3, 0xff, b'1'];
+        assert_eq!(server.poll(), Some((Some(client(2)), &notification[..])));
+        assert_eq!(server.poll(), None);
+
+        // The next confirmable notification waits for the acknowledgement
+        temperature.set(b'2');
+        assert_eq!(server.notify("sensors/temp"), 2);
+        let notification = [0x51, 69, 1, 2, 2, 0x61, 4, 0xff, b'2'];
+        assert_eq!(server.poll(), Some((Some(client(2)), &notification[..])));
+        assert_eq!(server.poll(), None);
+        let ack = [0x60, 0, 1, 0];
+        assert_eq!(
+            server.handle_message_from(client(1), &ack).unwrap(),
+            &[][..]
+        );
+        let notification = [0x41, 69, 1, 3, 1, 0x61, 5, 0xff, b'2'];
+        assert_eq!(server.poll(), Some((Some(client(1)), &notification[..])));
+        let ack = [0x60, 0, 1, 3];
+        server.handle_message_from(client(1), &ack).unwrap();
+
+        // A reset notification ends the observation
+        let reset = [0x70, 0, 1, 1];
+        server.handle_message_from(client(2), &reset).unwrap();
+        assert_eq!(server.notify("sensors/temp"), 2);
+        assert_eq!(server.poll().unwrap().0, Some(client(1)));
+        assert_eq!(server.poll().unwrap().0, Some(client(2)));
+        server
+            .handle_message_from(client(1), &[0x60, 0, 1, 4])
+            .unwrap();
+        let reset = [0x70, 0, 1, 5];
+        server.handle_message_from(client(2), &reset).unwrap();
+        assert_eq!(server.notify("sensors/temp"), 1);
+        server.poll().unwrap();
+        server
+            .handle_message_from(client(1), &[0x60, 0, 1, 6])
+            .unwrap();
+
+        // Deregistration
+        let raw_msg = observe("sensors/temp", 1, 4, 1)
+            .confirmable()
+            .build()
+            .unwrap()
+            .encode()
+            .unwrap();
+        let resp = server
+            .handle_message_from(client(1), &raw_msg.0[..raw_msg.1])
+            .unwrap();
+        assert_eq!(resp, &[0x61, 69, 0, 4, 1, 0xff, b'2'][..]);
+        assert_eq!(server.notify("sensors/temp"), 0);
+
+        // A confirmable notification that is not acknowledged ends the observation
+        let raw_msg = observe("sensors/temp", 5, 5, 0)
+            .confirmable()
+            .build()
+            .unwrap()
+            .encode()
+            .unwrap();
+        server
+            .handle_message_from(client(1), &raw_msg.0[..raw_msg.1])
+            .unwrap();
+        assert_eq!(server.notify("sensors/temp"), 1);
+        let mut sent = 0;
+        for _ in 0..100 {
+            now.set(now.get() + 1000);
+            if server.poll().is_some() {
+                sent += 1;
+            }
+        }
+        assert_eq!(sent, 5);
+        assert_eq!(server.notify("sensors/temp"), 0);
+
+        // An error notification ends the observation
+        let raw_msg = observe("sensors/temp", 6, 6, 0)
+            .build()
+            .unwrap()
+            .encode()
+            .unwrap();
+        server
+            .handle_message_from(client(3), &raw_msg.0[..raw_msg.1])
+            .unwrap();
+        server.get_config_mut().remove_resource("sensors/temp");
+        assert_eq!(server.notify("sensors/temp"), 1);
+        let notification = [0x51, 132, 1, 8, 6];
+        assert_eq!(server.poll(), Some((Some(client(3)), &notification[..])));
+        assert_eq!(server.notify("sensors/temp"), 0);
+    }
+
+    /// Returns the value of the first option with the number
+    fn uint_option(msg: &CoapMessage, number: CoapOptionNumbers) -> Option<u32> {
+        msg.get_options()
+            .iter()
+            .find(|option| option.get_option_number() == number)
+            .map(|option| option.get_uint().unwrap())
+    }
+
+    fn get(path: &str, message_id: u16, option: Option<(CoapOptionNumbers, u32)>) -> CoapMessage {
+        let mut builder = CoapMessage::get()
+            .path(path)
+            .message_id(message_id)
+            .confirmable();
+        if let Some((number, value)) = option {
+            builder = builder.option(CoapOption::uint(number, value).unwrap());
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn block2() {
+        let mut data = [0; 300];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut dump =
+            |_: &CoapRequest, response: &mut CoapResponse| response.set_block_payload(&data);
+        let mut small =
+            |_: &CoapRequest, response: &mut CoapResponse| response.set_block_payload(b"small");
+        // 200 bytes produced from the offset, without knowing the size up front
+        let mut stream = |_: &CoapRequest, response: &mut CoapResponse| {
+            response.set_block_payload_with(None, |offset, buffer| {
+                let length = buffer.len().min(200usize.saturating_sub(offset));
+                for (i, byte) in buffer[..length].iter_mut().enumerate() {
+                    *byte = (offset + i) as u8;
+                }
+                Ok(length)
+            })
+        };
+        let mut config = CoapConfig::new();
+        config.add_handler("dump", &mut dump).unwrap();
+        config.add_handler("small", &mut small).unwrap();
+        config.add_handler("stream", &mut stream).unwrap();
+        let mut buffer: [u8; 512] = [0; 512];
+        let mut server = CoapServer::new(config, &mut buffer);
+        let mut message_id = 0;
+        let mut handle = |request: CoapMessage| {
+            message_id += 1;
+            let mut request = request;
+            request.header = CoapHeader::new(
+                CoapHeaderType::Confirmable,
+                0,
+                request.header.get_code(),
+                message_id,
+            )
+            .unwrap();
+            let raw_msg = request.encode().unwrap();
+            let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+            CoapMessage::decode(resp).unwrap()
+        };
+        let block2 = |msg: &CoapMessage| {
+            uint_option(msg, CoapOptionNumbers::Block2)
+                .map(|value| CoapBlock::decode(value).unwrap())
+        };
+
+        // The first block is as large as the payload capacity allows
+        let resp = handle(get("dump", 0, None));
+        assert_eq!(resp.get_header().get_code(), CoapHeaderCode::Content);
+        assert_eq!(block2(&resp), Some(CoapBlock::new(0, true, 3).unwrap()));
+        assert_eq!(uint_option(&resp, CoapOptionNumbers::Size2), Some(300));
+        assert_eq!(resp.get_payload(), &data[..128]);
+        let block = CoapBlock::new(2, false, 3).unwrap();
+        let resp = handle(get(
+            "dump",
+            0,
+            Some((CoapOptionNumbers::Block2, block.encode())),
+        ));
+        assert_eq!(block2(&resp), Some(block));
+        assert_eq!(resp.get_payload(), &data[256..]);
+        // Smaller blocks are served as asked for, larger ones are split
+        let block = CoapBlock::new(1, false, 0).unwrap();
+        let resp = handle(get(
+            "dump",
+            0,
+            Some((CoapOptionNumbers::Block2, block.encode())),
+        ));
+        assert_eq!(block2(&resp), Some(CoapBlock::new(1, true, 0).unwrap()));
+        assert_eq!(resp.get_payload(), &data[16..32]);
+        let block = CoapBlock::new(0, false, 6).unwrap();
+        let resp = handle(get(
+            "dump",
+            0,
+            Some((CoapOptionNumbers::Block2, block.encode())),
+        ));
+        assert_eq!(block2(&resp), Some(CoapBlock::new(0, true, 3).unwrap()));
+
+        // Blocks past the end and invalid block options
+        let block = CoapBlock::new(1, false, 6).unwrap();
+        let resp = handle(get(
+            "dump",
+            0,
+            Some((CoapOptionNumbers::Block2, block.encode())),
+        ));
+        assert_eq!(resp.get_header().get_code(), CoapHeaderCode::BadOption);
+        assert_eq!(resp.get_payload(), &[][..]);
+        assert_eq!(block2(&resp), None);
+        // The last block number does not fit into 20 bits once the block is split
+        let block = CoapBlock::new((1 << 20) - 1, false, 6).unwrap();
+        let resp = handle(get(
+            "dump",
+            0,
+            Some((CoapOptionNumbers::Block2, block.encode())),
+        ));
+        assert_eq!(resp.get_header().get_code(), CoapHeaderCode::BadOption);
+        let resp = handle(get("dump", 0, Some((CoapOptionNumbers::Block2, 0x07))));
+        assert_eq!(resp.get_header().get_code(), CoapHeaderCode::BadRequest);
+        let block = CoapBlock::new(1, false, 0).unwrap();
+        let resp = handle(get(
+            "dump",
+            0,
+            Some((CoapOptionNumbers::Block1, block.encode())),
+        ));
+        assert_eq!(
+            resp.get_header().get_code(),
+            CoapHeaderCode::RequestEntityIncomplete
+        );
+        let block = CoapBlock::new(0, true, 0).unwrap();
+        let resp = handle(get(
+            "dump",
+            0,
+            Some((CoapOptionNumbers::Block1, block.encode())),
+        ));
+        assert_eq!(
+            resp.get_header().get_code(),
+            CoapHeaderCode::RequestEntityTooLarge
+        );
+
+        // A representation that fits is sent as it is, unless a block is asked for
+        let resp = handle(get("small", 0, None));
+        assert_eq!(block2(&resp), None);
+        assert_eq!(resp.get_payload(), b"small");
+        let block = CoapBlock::new(0, false, 3).unwrap();
+        let resp = handle(get(
+            "small",
+            0,
+            Some((CoapOptionNumbers::Block2, block.encode())),
+        ));
+        assert_eq!(block2(&resp), Some(block));
+
+        // Produced from the offset
+        let block = CoapBlock::new(1, false, 2).unwrap();
+        let resp = handle(get(
+            "stream",
+            0,
+            Some((CoapOptionNumbers::Block2, block.encode())),
+        ));
+        assert_eq!(block2(&resp), Some(CoapBlock::new(1, true, 2).unwrap()));
+        assert_eq!(uint_option(&resp, CoapOptionNumbers::Size2), None);
+        assert_eq!(resp.get_payload()[0], 64);
+        let block = CoapBlock::new(3, false, 2).unwrap();
+        let resp = handle(get(
+            "stream",
+            0,
+            Some((CoapOptionNumbers::Block2, block.encode())),
+        ));
+        assert_eq!(block2(&resp), Some(block));
+        assert_eq!(resp.get_payload().len(), 8);
+        let block = CoapBlock::new(4, false, 2).unwrap();
+        let resp = handle(get(
+            "stream",
+            0,
+            Some((CoapOptionNumbers::Block2, block.encode())),
+        ));
+        assert_eq!(resp.get_header().get_code(), CoapHeaderCode::BadOption);
+    }
+
+    #[test]
+    fn well_known_core_blocks() {
+        let paths = [
+            "sensors/temperature/livingroom",
+            "sensors/temperature/kitchen",
+            "sensors/humidity/livingroom",
+            "sensors/humidity/kitchen",
+            "actuators/heating/livingroom",
+        ];
+        let mut config = CoapConfig::new();
+        for path in paths.iter() {
+            config.add_resource(test, path).unwrap();
+        }
+        let mut buffer: [u8; 512] = [0; 512];
+        let mut server = CoapServer::new(config, &mut buffer);
+
+        let mut document: heapless::Vec<u8, 512> = heapless::Vec::new();
+        for num in 0.. {
+            let block = CoapBlock::new(num, false, 1).unwrap();
+            let request = get(
+                ".well-known/core",
+                num as u16,
+                Some((CoapOptionNumbers::Block2, block.encode())),
+            );
+            let raw_msg = request.encode().unwrap();
+            let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+            let resp: CoapMessage = CoapMessage::decode(resp).unwrap();
+            assert_eq!(
+                resp.content_format(),
+                Some(CoapMediaType::ApplicationLinkFormat)
+            );
+            document.extend_from_slice(resp.get_payload()).unwrap();
+            let block = CoapBlock::decode(uint_option(&resp, CoapOptionNumbers::Block2).unwrap());
+            if !block.unwrap().is_more() {
+                assert_eq!(
+                    uint_option(&resp, CoapOptionNumbers::Size2),
+                    Some(document.len() as u32)
+                );
+                break;
+            }
+        }
+        let mut expected: heapless::String<512> = heapless::String::new();
+        for (i, path) in paths.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(expected, "{}</{}>", separator, path).unwrap();
+        }
+        assert_eq!(&document[..], expected.as_bytes());
 
-        assert_eq!(config,config_2);
+        // Past the end the error carries no Content-Format
+        let block = CoapBlock::new(16, false, 1).unwrap();
+        let request = get(
+            ".well-known/core",
+            100,
+            Some((CoapOptionNumbers::Block2, block.encode())),
+        );
+        let raw_msg = request.encode().unwrap();
+        let resp = server.handle_message(&raw_msg.0[..raw_msg.1]).unwrap();
+        let resp: CoapMessage = CoapMessage::decode(resp).unwrap();
+        assert_eq!(resp.get_header().get_code(), CoapHeaderCode::BadOption);
+        assert_eq!(resp.content_format(), None);
+        assert_eq!(resp.get_options().len(), 0);
     }
 }
@@ -0,0 +1,198 @@
+//! Block-wise transfers, RFC 7959.
+//!
+//! The Block1 and Block2 options carry a [`CoapBlock`], the number of a block, whether
+//! more blocks follow and the block size. Blocks are 16 to 1024 bytes long, the size is
+//! encoded as the exponent SZX, 2^(SZX + 4) bytes.
+
+use crate::CoapError;
+use core::fmt;
+
+/// Largest block size exponent, for blocks of 1024 bytes. 7 is reserved
+pub const MAX_SZX: u8 = 6;
+
+/// Block numbers are up to 20 bits long
+const MAX_NUM: u32 = (1 << 20) - 1;
+
+/// The value of a Block1 or Block2 option, RFC 7959 §2.2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoapBlock {
+    num: u32,
+    more: bool,
+    szx: u8,
+}
+
+impl CoapBlock {
+    /// Creates a block value, blocks are 2^(`szx` + 4) bytes long.
+    /// Fails if `szx` is larger than [`MAX_SZX`] or `num` is longer than 20 bits
+    pub fn new(num: u32, more: bool, szx: u8) -> Result<Self, CoapError> {
+        if szx > MAX_SZX || num > MAX_NUM {
+            return Err(CoapError::InvalidBlock);
+        }
+        Ok(CoapBlock { num, more, szx })
+    }
+
+    /// Decodes the unsigned integer value of a block option
+    pub fn decode(value: u32) -> Result<Self, CoapError> {
+        Self::new(value >> 4, value & 0x8 != 0, (value & 0x7) as u8)
+    }
+
+    /// Encodes the block as the unsigned integer value of a block option
+    pub fn encode(&self) -> u32 {
+        self.num << 4 | (self.more as u32) << 3 | self.szx as u32
+    }
+
+    /// Returns the block number
+    pub fn get_num(&self) -> u32 {
+        self.num
+    }
+
+    /// Returns true if more blocks follow
+    pub fn is_more(&self) -> bool {
+        self.more
+    }
+
+    /// Returns the block size exponent
+    pub fn get_szx(&self) -> u8 {
+        self.szx
+    }
+
+    /// Returns the block size in bytes
+    pub fn get_size(&self) -> usize {
+        16 << self.szx
+    }
+
+    /// Returns the offset of the block in the whole body
+    pub fn get_offset(&self) -> usize {
+        self.num as usize * self.get_size()
+    }
+
+    /// Returns the same part of the body in blocks no larger than 2^(`szx` + 4) bytes,
+    /// the first of them if the block is split, RFC 7959 §2.4.
+    /// Returns `None` if the number of the first smaller block is longer than 20 bits
+    pub(crate) fn limit(self, szx: u8) -> Option<Self> {
+        if self.szx <= szx {
+            return Some(self);
+        }
+        let num = self.num << (self.szx - szx);
+        if num > MAX_NUM {
+            return None;
+        }
+        Some(CoapBlock {
+            num,
+            more: self.more,
+            szx,
+        })
+    }
+}
+
+/// Returns the exponent of the largest block that fits into the capacity,
+/// `None` if it is smaller than 16 bytes
+pub(crate) fn szx_for(capacity: usize) -> Option<u8> {
+    (0..=MAX_SZX).rev().find(|szx| 16 << szx <= capacity)
+}
+
+/// Writes the part of a body that falls into a buffer starting at an offset,
+/// counting the length of the whole body
+pub(crate) struct BlockWriter<'b> {
+    offset: usize,
+    buffer: &'b mut [u8],
+    position: usize,
+}
+
+impl<'b> BlockWriter<'b> {
+    pub(crate) fn new(offset: usize, buffer: &'b mut [u8]) -> Self {
+        BlockWriter {
+            offset,
+            buffer,
+            position: 0,
+        }
+    }
+
+    /// Returns the length of the body written so far
+    pub(crate) fn get_position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns the number of bytes written into the buffer
+    pub(crate) fn get_written(&self) -> usize {
+        self.position
+            .saturating_sub(self.offset)
+            .min(self.buffer.len())
+    }
+}
+
+impl fmt::Write for BlockWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let start = self.offset.max(self.position);
+        let end = (self.offset + self.buffer.len()).min(self.position + s.len());
+        if start < end {
+            self.buffer[start - self.offset..end - self.offset]
+                .copy_from_slice(&s.as_bytes()[start - self.position..end - self.position]);
+        }
+        self.position += s.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::block::*;
+    use core::fmt::Write;
+
+    #[test]
+    fn block_value() {
+        let block = CoapBlock::decode(0x2a).unwrap();
+        assert_eq!(block.get_num(), 2);
+        assert!(block.is_more());
+        assert_eq!(block.get_szx(), 2);
+        assert_eq!(block.get_size(), 64);
+        assert_eq!(block.get_offset(), 128);
+        assert_eq!(block.encode(), 0x2a);
+
+        assert_eq!(CoapBlock::decode(0x07), Err(CoapError::InvalidBlock));
+        assert_eq!(
+            CoapBlock::new(1 << 20, false, 0),
+            Err(CoapError::InvalidBlock)
+        );
+        assert_eq!(
+            CoapBlock::new(MAX_NUM, false, MAX_SZX).unwrap().encode(),
+            0xff_fff6
+        );
+    }
+
+    #[test]
+    fn block_size() {
+        let block = CoapBlock::new(3, false, 6).unwrap();
+        let limited = block.limit(4).unwrap();
+        assert_eq!(limited.get_num(), 12);
+        assert_eq!(limited.get_offset(), block.get_offset());
+        assert_eq!(block.limit(6), Some(block));
+        // Split blocks are numbered with 20 bits too
+        let last = CoapBlock::new(MAX_NUM, false, 6).unwrap();
+        assert_eq!(last.limit(3), None);
+        let block = CoapBlock::new(MAX_NUM >> 3, false, 6).unwrap();
+        assert_eq!(block.limit(3).map(|b| b.get_num()), Some(MAX_NUM - 7));
+
+        assert_eq!(szx_for(15), None);
+        assert_eq!(szx_for(16), Some(0));
+        assert_eq!(szx_for(255), Some(3));
+        assert_eq!(szx_for(4096), Some(MAX_SZX));
+    }
+
+    #[test]
+    fn block_writer() {
+        let mut buffer = [0; 4];
+        let mut writer = BlockWriter::new(3, &mut buffer);
+        write!(writer, "ab").unwrap();
+        assert_eq!(writer.get_written(), 0);
+        write!(writer, "cdefghi").unwrap();
+        assert_eq!(writer.get_written(), 4);
+        assert_eq!(writer.get_position(), 9);
+        assert_eq!(&buffer, b"defg");
+
+        let mut writer = BlockWriter::new(0, &mut []);
+        write!(writer, "abc").unwrap();
+        assert_eq!(writer.get_position(), 3);
+        assert_eq!(writer.get_written(), 0);
+    }
+}
@@ -0,0 +1,286 @@
+//! Fluent construction of requests
+
+use super::header::{CoapHeader, CoapHeaderCode, CoapHeaderType};
+use super::option::{CoapOption, CoapOptionNumbers, CoapOptions};
+use super::{CoapMediaType, CoapMessage, CoapToken};
+use crate::uri::CoapUri;
+use crate::CoapError;
+use heapless::Vec;
+
+/// Builds a [`CoapMessage`] step by step.
+/// Created with [`CoapMessage::get`] and friends or [`CoapMessageBuilder::new`].
+/// The first error is kept and returned by [`CoapMessageBuilder::build`]
+#[derive(Debug, Clone)]
+pub struct CoapMessageBuilder<
+    const MAX_OPTIONS: usize,
+    const OPTION_SIZE: usize,
+    const PAYLOAD_SIZE: usize,
+> {
+    t: CoapHeaderType,
+    code: CoapHeaderCode,
+    message_id: u16,
+    token: CoapToken,
+    options: CoapOptions<MAX_OPTIONS, OPTION_SIZE>,
+    payload: Vec<u8, PAYLOAD_SIZE>,
+    error: Option<CoapError>,
+}
+
+impl<const MAX_OPTIONS: usize, const OPTION_SIZE: usize, const PAYLOAD_SIZE: usize>
+    CoapMessageBuilder<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>
+{
+    /// Starts a non-confirmable message with the code, message id 0 and no token
+    pub fn new(code: CoapHeaderCode) -> Self {
+        CoapMessageBuilder {
+            t: CoapHeaderType::NonConfirmable,
+            code,
+            message_id: 0,
+            token: CoapToken::new(),
+            options: CoapOptions::default(),
+            payload: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Makes the message confirmable
+    pub fn confirmable(mut self) -> Self {
+        self.t = CoapHeaderType::Confirmable;
+        self
+    }
+
+    /// Makes the message non-confirmable
+    pub fn non_confirmable(mut self) -> Self {
+        self.t = CoapHeaderType::NonConfirmable;
+        self
+    }
+
+    /// Sets the message id
+    pub fn message_id(mut self, message_id: u16) -> Self {
+        self.message_id = message_id;
+        self
+    }
+
+    /// Sets the token, max 8 bytes. The token length in the header follows the token
+    pub fn token(mut self, token: &[u8]) -> Self {
+        match CoapToken::from_slice(token) {
+            Ok(token) => self.token = token,
+            Err(e) => self.fail(e),
+        }
+        self
+    }
+
+    /// Adds a Uri-Path option for every segment of the path, for example `sensors/temp`.
+    /// A leading `/` is ignored
+    pub fn path(mut self, path: &str) -> Self {
+        let path = path.strip_prefix('/').unwrap_or(path);
+        if !path.is_empty() {
+            for segment in path.split('/') {
+                self = self.string_option(CoapOptionNumbers::UriPath, segment);
+            }
+        }
+        self
+    }
+
+    /// Adds the Uri-Host, Uri-Path and Uri-Query options of a URI for a request sent to the
+    /// port of the URI, see [`CoapUri::to_options`]
+    pub fn uri(mut self, uri: &str) -> Self {
+        let options = &mut self.options;
+        if let Err(e) = CoapUri::parse(uri).and_then(|uri| uri.to_options(uri.get_port(), options))
+        {
+            self.fail(e);
+        }
+        self
+    }
+
+    /// Adds a Uri-Query option, for example `unit=c`
+    pub fn query(self, query: &str) -> Self {
+        self.string_option(CoapOptionNumbers::UriQuery, query)
+    }
+
+    /// Sets the Accept option
+    pub fn accept(self, format: CoapMediaType) -> Self {
+        self.uint_option(CoapOptionNumbers::Accept, u16::from(format) as u32)
+    }
+
+    /// Sets the Content-Format option
+    pub fn content_format(self, format: CoapMediaType) -> Self {
+        self.uint_option(CoapOptionNumbers::ContentFormat, u16::from(format) as u32)
+    }
+
+    /// Adds an option
+    pub fn option(mut self, option: CoapOption<OPTION_SIZE>) -> Self {
+        if let Err(e) = self.options.push(option) {
+            self.fail(e);
+        }
+        self
+    }
+
+    /// Sets the payload, max `PAYLOAD_SIZE` bytes
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        match Vec::from_slice(payload) {
+            Ok(payload) => self.payload = payload,
+            Err(_) => self.fail(CoapError::CapacityExceeded {
+                capacity: PAYLOAD_SIZE,
+            }),
+        }
+        self
+    }
+
+    /// Builds the message, or returns the first error hit while building it
+    pub fn build(self) -> Result<CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>, CoapError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let header = CoapHeader::new(self.t, self.token.len() as u8, self.code, self.message_id)?;
+        let mut message = CoapMessage::from_header(header, &self.payload)?;
+        message.token = self.token;
+        message.options = self.options;
+        Ok(message)
+    }
+
+    fn string_option(self, number: CoapOptionNumbers, value: &str) -> Self {
+        match CoapOption::string(number, value) {
+            Ok(option) => self.option(option),
+            Err(e) => self.failed(e),
+        }
+    }
+
+    fn uint_option(mut self, number: CoapOptionNumbers, value: u32) -> Self {
+        match CoapOption::uint(number.clone(), value) {
+            Ok(option) => {
+                self.options.remove_all(number);
+                self.option(option)
+            }
+            Err(e) => self.failed(e),
+        }
+    }
+
+    fn failed(mut self, e: CoapError) -> Self {
+        self.fail(e);
+        self
+    }
+
+    fn fail(&mut self, e: CoapError) {
+        if self.error.is_none() {
+            self.error = Some(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::builder::*;
+    use crate::message::header::*;
+    use crate::message::CoapMessageRef;
+
+    #[test]
+    fn build_request() {
+        let msg = CoapMessage::get()
+            .path("sensors/temp")
+            .query("unit=c")
+            .accept(CoapMediaType::ApplicationJson)
+            .token(&[1, 2])
+            .message_id(7)
+            .confirmable()
+            .build()
+            .unwrap();
+        assert_eq!(msg.get_header().get_type(), CoapHeaderType::Confirmable);
+        assert_eq!(msg.get_header().get_code(), CoapHeaderCode::GET);
+        assert_eq!(msg.get_header().get_tkl(), 2);
+        assert_eq!(msg.get_header().get_message_id(), 7);
+        assert_eq!(msg.get_token(), &[1, 2]);
+        let en_msg = msg.encode().unwrap();
+        assert_eq!(
+            &en_msg.0[..en_msg.1],
+            &[
+                0x42, 1, 0, 7, 1, 2, 0xb7, b's', b'e', b'n', b's', b'o', b'r', b's', 0x04, b't',
+                b'e', b'm', b'p', 0x46, b'u', b'n', b'i', b't', b'=', b'c', 0x21, 50
+            ][..]
+        );
+    }
+
+    #[test]
+    fn build_from_uri() {
+        let msg = CoapMessage::get()
+            .uri("coap://example.com/sensors/temp?unit=c")
+            .build()
+            .unwrap();
+        let reference = CoapMessage::get()
+            .option(CoapOption::string(CoapOptionNumbers::UriHost, "example.com").unwrap())
+            .path("sensors/temp")
+            .query("unit=c")
+            .build()
+            .unwrap();
+        assert_eq!(msg, reference);
+        assert_eq!(
+            CoapMessage::get().uri("http://example.com").build(),
+            Err(CoapError::InvalidUri)
+        );
+    }
+
+    #[test]
+    fn build_errors() {
+        assert_eq!(
+            CoapMessage::get().token(&[0; 9]).build(),
+            Err(CoapError::BadTokenLength { length: 9 })
+        );
+        // The first error is kept
+        assert_eq!(
+            CoapMessage::post()
+                .path("")
+                .payload(&[0; 256])
+                .token(&[0; 9])
+                .build(),
+            Err(CoapError::CapacityExceeded { capacity: 255 })
+        );
+        let msg: CoapMessage<1, 255, 255> = CoapMessageBuilder::new(CoapHeaderCode::PUT)
+            .path("/a")
+            .build()
+            .unwrap();
+        assert_eq!(msg.get_options().len(), 1);
+        let result: Result<CoapMessage<1, 255, 255>, _> =
+            CoapMessageBuilder::new(CoapHeaderCode::PUT)
+                .path("a/b")
+                .build();
+        assert_eq!(result, Err(CoapError::CapacityExceeded { capacity: 1 }));
+    }
+
+    #[test]
+    fn response() {
+        let request = CoapMessage::get()
+            .path("a")
+            .token(&[9, 8, 7])
+            .message_id(42)
+            .confirmable()
+            .build()
+            .unwrap();
+        let response = request.response(CoapHeaderCode::Content).unwrap();
+        assert_eq!(
+            response.get_header().get_type(),
+            CoapHeaderType::Acknowledgement
+        );
+        assert_eq!(response.get_header().get_message_id(), 42);
+        assert_eq!(response.get_token(), &[9, 8, 7]);
+        assert!(response.get_options().is_empty());
+
+        let request = CoapMessage::delete().message_id(3).build().unwrap();
+        let response = request.response(CoapHeaderCode::Deleted).unwrap();
+        assert_eq!(
+            response.get_header().get_type(),
+            CoapHeaderType::NonConfirmable
+        );
+        assert_eq!(response.get_header().get_message_id(), 3);
+
+        let en_msg = CoapMessage::put()
+            .token(&[5])
+            .message_id(1)
+            .confirmable()
+            .build()
+            .unwrap()
+            .encode()
+            .unwrap();
+        let view = CoapMessageRef::decode(&en_msg.0[..en_msg.1]).unwrap();
+        let response: CoapMessage = view.response(CoapHeaderCode::Changed).unwrap();
+        let en_msg = response.encode().unwrap();
+        assert_eq!(&en_msg.0[..en_msg.1], &[0x61, 68, 0, 1, 5]);
+    }
+}
@@ -1,37 +1,178 @@
+//! The fixed 4 byte CoAP message header
+
 use crate::CoapError;
+use core::convert::TryFrom;
+use core::fmt;
+use core::str::FromStr;
 
-#[derive(Debug, Clone, PartialEq)]
+/// CoAP message code, written as c.dd where c is the 3 bit class and dd the 5 bit detail.
+/// Codes without a named variant are kept as [`CoapHeaderCode::Other`]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CoapHeaderCode {
+    /// 0.00 Empty message
     EMPTY,
     // Coap Methods
+    /// 0.01 GET
     GET,
+    /// 0.02 POST
     POST,
+    /// 0.03 PUT
     PUT,
+    /// 0.04 DELETE
     DELETE,
+    /// 0.05 FETCH (RFC 8132)
+    FETCH,
+    /// 0.06 PATCH (RFC 8132)
+    PATCH,
+    /// 0.07 iPATCH (RFC 8132)
+    IPATCH,
     // Coap Response codes
+    /// 2.01 Created
     Created,
+    /// 2.02 Deleted
     Deleted,
+    /// 2.03 Valid
     Valid,
+    /// 2.04 Changed
     Changed,
+    /// 2.05 Content
     Content,
+    /// 2.31 Continue (RFC 7959)
+    Continue,
+    /// 4.00 Bad Request
     BadRequest,
+    /// 4.01 Unauthorized
     Unauthorized,
+    /// 4.02 Bad Option
     BadOption,
+    /// 4.03 Forbidden
     Forbidden,
+    /// 4.04 Not Found
     NotFound,
+    /// 4.05 Method Not Allowed
     MethodNotAllowed,
+    /// 4.06 Not Acceptable
     NotAcceptable,
+    /// 4.08 Request Entity Incomplete (RFC 7959)
+    RequestEntityIncomplete,
+    /// 4.09 Conflict (RFC 8132)
+    Conflict,
+    /// 4.12 Precondition Failed
     PreconditionFailed,
+    /// 4.13 Request Entity Too Large
     RequestEntityTooLarge,
+    /// 4.15 Unsupported Content-Format
     UnsupportedContentFormat,
+    /// 4.22 Unprocessable Entity (RFC 8132)
+    UnprocessableEntity,
+    /// 4.29 Too Many Requests (RFC 8516)
+    TooManyRequests,
+    /// 5.00 Internal Server Error
     InternalServerError,
+    /// 5.01 Not Implemented
     NotImplemented,
+    /// 5.02 Bad Gateway
     BadGateway,
+    /// 5.03 Service Unavailable
     ServiceUnavailable,
+    /// 5.04 Gateway Timeout
     GatewayTimeout,
+    /// 5.05 Proxying Not Supported
     ProxyingNotSupported,
+    /// 5.08 Hop Limit Reached (RFC 8768)
+    HopLimitReached,
+    /// Any other code, for example 7.xx signaling codes.
+    /// Only built by `CoapHeaderCode::from(u8)` for codes that do not have a named
+    /// variant, so every code has exactly one value
+    Other(CoapOtherCode),
+}
+
+/// A code without a named [`CoapHeaderCode`] variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoapOtherCode(u8);
+
+impl From<CoapOtherCode> for u8 {
+    fn from(code: CoapOtherCode) -> u8 {
+        code.0
+    }
+}
+
+impl CoapHeaderCode {
+    /// Creates a code from its class and detail, c.dd
+    pub fn from_class_detail(class: u8, detail: u8) -> Result<Self, CoapError> {
+        if class > 7 || detail > 31 {
+            return Err(CoapError::InvalidCode);
+        }
+        Ok((class << 5 | detail).into())
+    }
+
+    /// Returns the class, the c in c.dd
+    pub fn class(&self) -> u8 {
+        u8::from(*self) >> 5
+    }
+
+    /// Returns the detail, the dd in c.dd
+    pub fn detail(&self) -> u8 {
+        u8::from(*self) & 0x1f
+    }
+
+    /// Returns true for the 0.00 empty message code
+    pub fn is_empty(&self) -> bool {
+        u8::from(*self) == 0
+    }
+
+    /// Returns true for request methods, 0.01 to 0.31
+    pub fn is_request(&self) -> bool {
+        self.class() == 0 && !self.is_empty()
+    }
+
+    /// Returns true for any response code, class 2 to 5
+    pub fn is_response(&self) -> bool {
+        (2..=5).contains(&self.class())
+    }
+
+    /// Returns true for success responses, class 2
+    pub fn is_success(&self) -> bool {
+        self.class() == 2
+    }
+
+    /// Returns true for client error responses, class 4
+    pub fn is_client_error(&self) -> bool {
+        self.class() == 4
+    }
+
+    /// Returns true for server error responses, class 5
+    pub fn is_server_error(&self) -> bool {
+        self.class() == 5
+    }
+}
+
+impl fmt::Display for CoapHeaderCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:02}", self.class(), self.detail())
+    }
+}
+
+impl FromStr for CoapHeaderCode {
+    type Err = CoapError;
+
+    /// Parses the c.dd notation, for example "2.05"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            [c, b'.', d1, d2]
+                if c.is_ascii_digit() && d1.is_ascii_digit() && d2.is_ascii_digit() =>
+            {
+                let class = c - b'0';
+                let detail = (d1 - b'0') * 10 + (d2 - b'0');
+                CoapHeaderCode::from_class_detail(class, detail)
+            }
+            _ => Err(CoapError::InvalidCode),
+        }
+    }
 }
 
+/// CoAP message header
 #[derive(Debug, Clone, PartialEq)]
 pub struct CoapHeader {
     version: u8,          // u2
@@ -41,23 +182,34 @@ pub struct CoapHeader {
     message_id: u16,      // u16
 }
 
+/// CoAP message type
 #[derive(Debug, Clone, PartialEq)]
 pub enum CoapHeaderType {
+    /// Confirmable (CON), requires an acknowledgement
     Confirmable,
+    /// Non-confirmable (NON)
     NonConfirmable,
+    /// Acknowledgement (ACK)
     Acknowledgement,
+    /// Reset (RST)
     Reset,
 }
 
 impl CoapHeader {
-    pub(crate) fn new(
+    /// Creates a new header. The version is always set to 1
+    pub fn new(
         t: CoapHeaderType,
         tkl: u8,
         code: CoapHeaderCode,
         message_id: u16,
     ) -> Result<Self, CoapError> {
+        if tkl > 8 {
+            return Err(CoapError::BadTokenLength {
+                length: tkl as usize,
+            });
+        }
         if code == CoapHeaderCode::EMPTY && tkl > 0 {
-            return Err(CoapError::MessageFormatError);
+            return Err(CoapError::InvalidEmptyMessage);
         }
         Ok(CoapHeader {
             version: 0x1,
@@ -67,34 +219,37 @@ impl CoapHeader {
             message_id,
         })
     }
-    pub(crate) fn encode(&self) -> Result<[u8; 4], CoapError> {
+    /// Encodes the header into its 4 byte wire format
+    pub fn encode(&self) -> Result<[u8; 4], CoapError> {
         if self.version != 1 {
-            return Err(CoapError::WrongVersion);
+            return Err(CoapError::WrongVersion(self.version));
         }
         let t: u8 = self.t.into();
         let vtt: u8 = (self.version << 6) | (t << 4) | self.tkl;
         let code: u8 = self.code.into();
 
         if self.code == CoapHeaderCode::EMPTY && self.tkl > 0 {
-            return Err(CoapError::MessageFormatError);
+            return Err(CoapError::InvalidEmptyMessage);
         }
         let msg_2: u8 = (self.message_id & 255) as u8;
         let msg_1: u8 = (self.message_id >> 8) as u8;
 
         Ok([vtt, code, msg_1, msg_2])
     }
-    pub(crate) fn decode(buf: &[u8]) -> Result<CoapHeader, CoapError> {
+    /// Decodes a header from the first 4 bytes of the buffer.
+    /// Only the version and the type are checked, so the message id of any message
+    /// with a readable header is available to reject it
+    pub fn decode(buf: &[u8]) -> Result<CoapHeader, CoapError> {
+        if buf.len() < 4 {
+            return Err(CoapError::TruncatedHeader { length: buf.len() });
+        }
         let version: u8 = buf[0] >> 6;
         if version != 1 {
-            return Err(CoapError::WrongVersion);
+            return Err(CoapError::WrongVersion(version));
         }
-        let t: CoapHeaderType = ((buf[0] << 2) >> 6).into();
+        let t = CoapHeaderType::try_from((buf[0] >> 4) & 0x3)?;
         let tkl: u8 = buf[0] & 15;
         let code: CoapHeaderCode = buf[1].into();
-
-        if code == CoapHeaderCode::EMPTY && tkl > 0 {
-            return Err(CoapError::MessageFormatError);
-        }
         let message_id: u16 = (buf[2] as u16) << 8 | buf[3] as u16;
 
         Ok(CoapHeader {
@@ -105,35 +260,53 @@ impl CoapHeader {
             message_id,
         })
     }
+    /// Returns the CoAP version
     pub fn get_version(&self) -> u8 {
         self.version
     }
+    /// Returns the token length
     pub fn get_tkl(&self) -> u8 {
         self.tkl
     }
+    /// Returns the message type
     pub fn get_type(&self) -> CoapHeaderType {
         self.t
     }
+    /// Returns the message code
     pub fn get_code(&self) -> CoapHeaderCode {
         self.code
     }
+    /// Returns the message id
     pub fn get_message_id(&self) -> u16 {
         self.message_id
     }
+
+    /// Sets the token length, kept in sync with the token of a message
+    pub(crate) fn set_tkl(&mut self, tkl: u8) {
+        self.tkl = tkl;
+    }
+
+    /// Sets the message code
+    pub(crate) fn set_code(&mut self, code: CoapHeaderCode) {
+        self.code = code;
+    }
 }
 
 impl Copy for CoapHeader {}
 
 impl Copy for CoapHeaderType {}
 
-impl From<u8> for CoapHeaderType {
-    fn from(item: u8) -> Self {
+impl TryFrom<u8> for CoapHeaderType {
+    type Error = CoapError;
+
+    /// Converts the 2 bit message type
+    fn try_from(item: u8) -> Result<Self, Self::Error> {
         match item {
-            0 => CoapHeaderType::Confirmable,
-            1 => CoapHeaderType::NonConfirmable,
-            2 => CoapHeaderType::Acknowledgement,
-            3 => CoapHeaderType::Reset,
-            _ => unreachable!(),
+            0 => Ok(CoapHeaderType::Confirmable),
+            1 => Ok(CoapHeaderType::NonConfirmable),
+            2 => Ok(CoapHeaderType::Acknowledgement),
+            3 => Ok(CoapHeaderType::Reset),
+            _ => Err(CoapError::InvalidMessageType),
         }
     }
 }
@@ -160,12 +333,16 @@ impl From<u8> for CoapHeaderCode {
             2 => CoapHeaderCode::POST,
             3 => CoapHeaderCode::PUT,
             4 => CoapHeaderCode::DELETE,
+            5 => CoapHeaderCode::FETCH,
+            6 => CoapHeaderCode::PATCH,
+            7 => CoapHeaderCode::IPATCH,
             // Coap Response codes
             65 => CoapHeaderCode::Created,
             66 => CoapHeaderCode::Deleted,
             67 => CoapHeaderCode::Valid,
             68 => CoapHeaderCode::Changed,
             69 => CoapHeaderCode::Content,
+            95 => CoapHeaderCode::Continue,
             128 => CoapHeaderCode::BadRequest,
             129 => CoapHeaderCode::Unauthorized,
             130 => CoapHeaderCode::BadOption,
@@ -173,16 +350,21 @@ impl From<u8> for CoapHeaderCode {
             132 => CoapHeaderCode::NotFound,
             133 => CoapHeaderCode::MethodNotAllowed,
             134 => CoapHeaderCode::NotAcceptable,
+            136 => CoapHeaderCode::RequestEntityIncomplete,
+            137 => CoapHeaderCode::Conflict,
             140 => CoapHeaderCode::PreconditionFailed,
             141 => CoapHeaderCode::RequestEntityTooLarge,
             143 => CoapHeaderCode::UnsupportedContentFormat,
+            150 => CoapHeaderCode::UnprocessableEntity,
+            157 => CoapHeaderCode::TooManyRequests,
             160 => CoapHeaderCode::InternalServerError,
             161 => CoapHeaderCode::NotImplemented,
             162 => CoapHeaderCode::BadGateway,
             163 => CoapHeaderCode::ServiceUnavailable,
             164 => CoapHeaderCode::GatewayTimeout,
             165 => CoapHeaderCode::ProxyingNotSupported,
-            _ => unreachable!(),
+            168 => CoapHeaderCode::HopLimitReached,
+            _ => CoapHeaderCode::Other(CoapOtherCode(item)),
         }
     }
 }
@@ -195,12 +377,16 @@ impl From<CoapHeaderCode> for u8 {
             CoapHeaderCode::POST => 2,
             CoapHeaderCode::PUT => 3,
             CoapHeaderCode::DELETE => 4,
+            CoapHeaderCode::FETCH => 5,
+            CoapHeaderCode::PATCH => 6,
+            CoapHeaderCode::IPATCH => 7,
             // Coap Response codes
             CoapHeaderCode::Created => 65,
             CoapHeaderCode::Deleted => 66,
             CoapHeaderCode::Valid => 67,
             CoapHeaderCode::Changed => 68,
             CoapHeaderCode::Content => 69,
+            CoapHeaderCode::Continue => 95,
             CoapHeaderCode::BadRequest => 128,
             CoapHeaderCode::Unauthorized => 129,
             CoapHeaderCode::BadOption => 130,
@@ -208,15 +394,21 @@ impl From<CoapHeaderCode> for u8 {
             CoapHeaderCode::NotFound => 132,
             CoapHeaderCode::MethodNotAllowed => 133,
             CoapHeaderCode::NotAcceptable => 134,
+            CoapHeaderCode::RequestEntityIncomplete => 136,
+            CoapHeaderCode::Conflict => 137,
             CoapHeaderCode::PreconditionFailed => 140,
             CoapHeaderCode::RequestEntityTooLarge => 141,
             CoapHeaderCode::UnsupportedContentFormat => 143,
+            CoapHeaderCode::UnprocessableEntity => 150,
+            CoapHeaderCode::TooManyRequests => 157,
             CoapHeaderCode::InternalServerError => 160,
             CoapHeaderCode::NotImplemented => 161,
             CoapHeaderCode::BadGateway => 162,
             CoapHeaderCode::ServiceUnavailable => 163,
             CoapHeaderCode::GatewayTimeout => 164,
             CoapHeaderCode::ProxyingNotSupported => 165,
+            CoapHeaderCode::HopLimitReached => 168,
+            CoapHeaderCode::Other(code) => code.0,
         }
     }
 }
@@ -242,4 +434,88 @@ mod tests {
         assert_eq!(de_header.code, CoapHeaderCode::Changed);
         assert_eq!(de_header.message_id, 123);
     }
+
+    #[test]
+    fn code_conversion() {
+        for code in 0..=u8::MAX {
+            assert_eq!(u8::from(CoapHeaderCode::from(code)), code);
+        }
+        assert_eq!(CoapHeaderCode::from(69), CoapHeaderCode::Content);
+        assert_eq!(CoapHeaderCode::from(95), CoapHeaderCode::Continue);
+        assert_eq!(CoapHeaderCode::from(157), CoapHeaderCode::TooManyRequests);
+        assert_eq!(
+            CoapHeaderCode::from(225),
+            CoapHeaderCode::Other(CoapOtherCode(225))
+        );
+        for code in 0..=u8::MAX {
+            // Every code has one value, Other only holds codes without a named variant
+            let value = CoapHeaderCode::from(code);
+            assert_eq!(
+                value == CoapHeaderCode::Other(CoapOtherCode(code)),
+                matches!(value, CoapHeaderCode::Other(_))
+            );
+        }
+    }
+
+    #[test]
+    fn type_conversion() {
+        for t in 0..4 {
+            assert_eq!(u8::from(CoapHeaderType::try_from(t).unwrap()), t);
+        }
+        assert_eq!(
+            CoapHeaderType::try_from(4),
+            Err(CoapError::InvalidMessageType)
+        );
+    }
+
+    #[test]
+    fn code_class_detail() {
+        let code = CoapHeaderCode::from_class_detail(4, 8).unwrap();
+        assert_eq!(code, CoapHeaderCode::RequestEntityIncomplete);
+        assert_eq!(code.class(), 4);
+        assert_eq!(code.detail(), 8);
+        assert!(CoapHeaderCode::from_class_detail(8, 0).is_err());
+        assert!(CoapHeaderCode::from_class_detail(2, 32).is_err());
+
+        assert!(CoapHeaderCode::EMPTY.is_empty());
+        assert!(!CoapHeaderCode::EMPTY.is_request());
+        assert!(CoapHeaderCode::GET.is_request());
+        assert!(CoapHeaderCode::IPATCH.is_request());
+        assert!(CoapHeaderCode::Continue.is_success());
+        assert!(CoapHeaderCode::Conflict.is_client_error());
+        assert!(CoapHeaderCode::from(0x9f).is_client_error());
+        assert!(CoapHeaderCode::HopLimitReached.is_server_error());
+        assert!(!CoapHeaderCode::from(0xe1).is_response());
+    }
+
+    #[test]
+    fn code_display_parse() {
+        let mut buf: heapless::String<8> = heapless::String::new();
+        core::fmt::write(&mut buf, format_args!("{}", CoapHeaderCode::Content)).unwrap();
+        assert_eq!(buf, "2.05");
+        let mut buf: heapless::String<8> = heapless::String::new();
+        core::fmt::write(&mut buf, format_args!("{}", CoapHeaderCode::from(0xe1))).unwrap();
+        assert_eq!(buf, "7.01");
+
+        assert_eq!(
+            "2.05".parse::<CoapHeaderCode>().unwrap(),
+            CoapHeaderCode::Content
+        );
+        assert_eq!(
+            "0.00".parse::<CoapHeaderCode>().unwrap(),
+            CoapHeaderCode::EMPTY
+        );
+        assert_eq!(
+            "4.29".parse::<CoapHeaderCode>().unwrap(),
+            CoapHeaderCode::TooManyRequests
+        );
+        assert_eq!(
+            "7.03".parse::<CoapHeaderCode>().unwrap(),
+            CoapHeaderCode::Other(CoapOtherCode(0xe3))
+        );
+        assert!("2.5".parse::<CoapHeaderCode>().is_err());
+        assert!("2.32".parse::<CoapHeaderCode>().is_err());
+        assert!("8.00".parse::<CoapHeaderCode>().is_err());
+        assert!("2x05".parse::<CoapHeaderCode>().is_err());
+    }
 }
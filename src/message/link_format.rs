@@ -0,0 +1,496 @@
+//! CoRE Link Format, RFC 6690.
+//!
+//! Used by the server to describe its resources at `/.well-known/core`,
+//! see [`CoapLinkAttributes`]. Clients read such documents with [`parse`],
+//! which borrows the links from the payload.
+
+use super::CoapMediaType;
+use crate::CoapError;
+use core::fmt::{self, Write};
+use heapless::String;
+
+/// The target attributes of a resource, RFC 6690 §3.
+/// Listed with the resource at `/.well-known/core`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CoapLinkAttributes<'a> {
+    rt: Option<&'a str>,
+    interface: Option<&'a str>,
+    ct: Option<CoapMediaType>,
+    sz: Option<u32>,
+    title: Option<&'a str>,
+    obs: bool,
+}
+
+impl<'a> CoapLinkAttributes<'a> {
+    /// Creates attributes without any attribute set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the resource type, space separated if there are several
+    pub fn rt(mut self, rt: &'a str) -> Self {
+        self.rt = Some(rt);
+        self
+    }
+
+    /// Sets the interface description, the `if` attribute, space separated if there are several
+    pub fn interface(mut self, interface: &'a str) -> Self {
+        self.interface = Some(interface);
+        self
+    }
+
+    /// Sets the Content-Format of the resource representation
+    pub fn ct(mut self, ct: CoapMediaType) -> Self {
+        self.ct = Some(ct);
+        self
+    }
+
+    /// Sets the estimated size of the resource representation in bytes
+    pub fn sz(mut self, sz: u32) -> Self {
+        self.sz = Some(sz);
+        self
+    }
+
+    /// Sets the human readable title
+    pub fn title(mut self, title: &'a str) -> Self {
+        self.title = Some(title);
+        self
+    }
+
+    /// Marks the resource as observable, RFC 7641 §6
+    pub fn obs(mut self) -> Self {
+        self.obs = true;
+        self
+    }
+
+    /// Returns the resource type
+    pub fn get_rt(&self) -> Option<&'a str> {
+        self.rt
+    }
+
+    /// Returns the interface description
+    pub fn get_interface(&self) -> Option<&'a str> {
+        self.interface
+    }
+
+    /// Returns the Content-Format
+    pub fn get_ct(&self) -> Option<CoapMediaType> {
+        self.ct
+    }
+
+    /// Returns the estimated size
+    pub fn get_sz(&self) -> Option<u32> {
+        self.sz
+    }
+
+    /// Returns the title
+    pub fn get_title(&self) -> Option<&'a str> {
+        self.title
+    }
+
+    /// Returns true if the resource is observable
+    pub fn is_obs(&self) -> bool {
+        self.obs
+    }
+
+    /// Checks a `/.well-known/core` query filter against the link, RFC 6690 §4.1.
+    /// A value ending with `*` matches every value starting with the rest of it,
+    /// rt and if match if any of their space separated values does
+    pub(crate) fn matches_filter(&self, path: &str, key: &str, value: &str) -> bool {
+        let (value, prefix) = match value.strip_suffix('*') {
+            Some(value) => (value, true),
+            None => (value, false),
+        };
+        let matches = |candidate: &str| {
+            if prefix {
+                candidate.starts_with(value)
+            } else {
+                candidate == value
+            }
+        };
+        let matches_number = |number: Option<u32>| {
+            let mut buf: String<10> = String::new();
+            match number {
+                Some(number) => write!(buf, "{}", number).is_ok() && matches(&buf),
+                None => false,
+            }
+        };
+        match key {
+            // The path is kept without its leading `/`
+            "href" => match value.strip_prefix('/') {
+                Some(value) if prefix => path.starts_with(value),
+                Some(value) => path == value,
+                None => prefix && value.is_empty(),
+            },
+            "rt" => self.rt.is_some_and(|rt| rt.split(' ').any(matches)),
+            "if" => self.interface.is_some_and(|i| i.split(' ').any(matches)),
+            "ct" => matches_number(self.ct.map(|ct| u16::from(ct) as u32)),
+            "sz" => matches_number(self.sz),
+            "title" => self.title.is_some_and(matches),
+            "obs" => self.obs,
+            _ => false,
+        }
+    }
+
+    /// Writes the link to the resource path with the attributes, for example
+    /// `</sensors/temp>;rt="temperature";ct=0;obs`
+    pub(crate) fn write_link<W: Write>(&self, w: &mut W, path: &str) -> fmt::Result {
+        write!(w, "</{}>", path)?;
+        if let Some(rt) = self.rt {
+            write!(w, ";rt=\"{}\"", rt)?;
+        }
+        if let Some(interface) = self.interface {
+            write!(w, ";if=\"{}\"", interface)?;
+        }
+        if let Some(ct) = self.ct {
+            write!(w, ";ct={}", u16::from(ct))?;
+        }
+        if let Some(sz) = self.sz {
+            write!(w, ";sz={}", sz)?;
+        }
+        if let Some(title) = self.title {
+            write!(w, ";title=\"{}\"", title)?;
+        }
+        if self.obs {
+            w.write_str(";obs")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a link format document, for example the payload of a response to
+/// GET `/.well-known/core`. Fails if the payload is not UTF-8,
+/// syntax errors are returned by the iterator
+pub fn parse(payload: &[u8]) -> Result<CoapLinks<'_>, CoapError> {
+    let document = core::str::from_utf8(payload).map_err(|e| CoapError::InvalidLinkFormat {
+        offset: e.valid_up_to(),
+    })?;
+    Ok(CoapLinks {
+        document,
+        position: 0,
+    })
+}
+
+/// Iterator over the links of a link format document, see [`parse`].
+/// Stops after the first syntax error
+#[derive(Debug, Clone)]
+pub struct CoapLinks<'a> {
+    document: &'a str,
+    position: usize,
+}
+
+impl<'a> CoapLinks<'a> {
+    /// Parses the link starting at the current position, up to the `,` ending it
+    fn parse_link(&mut self) -> Result<CoapLink<'a>, CoapError> {
+        let bytes = self.document.as_bytes();
+        let error = |offset| CoapError::InvalidLinkFormat { offset };
+        let start = self.position;
+        if bytes[start] != b'<' {
+            return Err(error(start));
+        }
+        let end = bytes[start..]
+            .iter()
+            .position(|&b| b == b'>')
+            .map(|end| start + end)
+            .ok_or(error(start))?;
+        let target = &self.document[start + 1..end];
+        let mut position = end + 1;
+        while position < bytes.len() && bytes[position] == b';' {
+            position += 1;
+            let name_end = scan(bytes, position, |b| matches!(b, b'=' | b';' | b','));
+            if name_end == position {
+                return Err(error(position));
+            }
+            position = name_end;
+            if position < bytes.len() && bytes[position] == b'=' {
+                position += 1;
+                position = match bytes.get(position) {
+                    Some(b'"') => quoted_end(bytes, position).ok_or(error(position))?,
+                    _ => scan(bytes, position, |b| matches!(b, b';' | b',')),
+                };
+            }
+        }
+        let params = &self.document[end + 1..position];
+        match bytes.get(position) {
+            None => {}
+            Some(b',') => position += 1,
+            Some(_) => return Err(error(position)),
+        }
+        self.position = position;
+        Ok(CoapLink { target, params })
+    }
+}
+
+impl<'a> Iterator for CoapLinks<'a> {
+    type Item = Result<CoapLink<'a>, CoapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Line breaks between links are tolerated
+        let bytes = self.document.as_bytes();
+        while self.position < bytes.len() && bytes[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+        if self.position >= bytes.len() {
+            return None;
+        }
+        let link = self.parse_link();
+        if link.is_err() {
+            self.position = bytes.len();
+        }
+        Some(link)
+    }
+}
+
+/// A link of a link format document, borrowed from the payload
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoapLink<'a> {
+    target: &'a str,
+    params: &'a str,
+}
+
+impl<'a> CoapLink<'a> {
+    /// Returns the target URI, for example `/sensors/temp`
+    pub fn get_target(&self) -> &'a str {
+        self.target
+    }
+
+    /// Returns an iterator over the attributes as `(name, value)` pairs.
+    /// Quoted values are returned without the quotes, escapes are kept as they are.
+    /// The value is `None` for attributes without `=`, for example `obs`
+    pub fn attributes(&self) -> CoapLinkAttributeIter<'a> {
+        CoapLinkAttributeIter {
+            params: self.params,
+        }
+    }
+
+    /// Returns the value of the first attribute with the name,
+    /// an empty string for attributes without value
+    pub fn get_attribute(&self, name: &str) -> Option<&'a str> {
+        self.attributes()
+            .find(|(attribute, _)| *attribute == name)
+            .map(|(_, value)| value.unwrap_or(""))
+    }
+
+    /// Returns the resource types
+    pub fn get_rt(&self) -> impl Iterator<Item = &'a str> {
+        self.values("rt")
+    }
+
+    /// Returns the interface descriptions, the `if` attribute
+    pub fn get_interface(&self) -> impl Iterator<Item = &'a str> {
+        self.values("if")
+    }
+
+    /// Returns the relation types
+    pub fn get_rel(&self) -> impl Iterator<Item = &'a str> {
+        self.values("rel")
+    }
+
+    /// Returns the Content-Formats, values that are not a number are skipped
+    pub fn get_ct(&self) -> impl Iterator<Item = CoapMediaType> + 'a {
+        self.values("ct")
+            .filter_map(|ct| ct.parse::<u16>().ok())
+            .map(CoapMediaType::from)
+    }
+
+    /// Returns the estimated size
+    pub fn get_sz(&self) -> Option<u32> {
+        self.get_attribute("sz")?.parse().ok()
+    }
+
+    /// Returns the anchor, the context URI of the link
+    pub fn get_anchor(&self) -> Option<&'a str> {
+        self.get_attribute("anchor")
+    }
+
+    /// Returns the title
+    pub fn get_title(&self) -> Option<&'a str> {
+        self.get_attribute("title")
+    }
+
+    /// Returns true if the target is observable
+    pub fn is_obs(&self) -> bool {
+        self.get_attribute("obs").is_some()
+    }
+
+    /// Returns the space separated values of the first attribute with the name
+    fn values(&self, name: &str) -> impl Iterator<Item = &'a str> {
+        self.get_attribute(name)
+            .unwrap_or("")
+            .split_ascii_whitespace()
+    }
+}
+
+/// Iterator over the attributes of a [`CoapLink`] as `(name, value)` pairs
+#[derive(Debug, Clone)]
+pub struct CoapLinkAttributeIter<'a> {
+    params: &'a str,
+}
+
+impl<'a> Iterator for CoapLinkAttributeIter<'a> {
+    type Item = (&'a str, Option<&'a str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The parameters were checked by the parser, they start with `;`
+        let bytes = self.params.as_bytes();
+        if bytes.is_empty() {
+            return None;
+        }
+        let name_end = scan(bytes, 1, |b| matches!(b, b'=' | b';'));
+        let name = &self.params[1..name_end];
+        let (value, end) = match bytes.get(name_end) {
+            Some(b'=') => match bytes.get(name_end + 1) {
+                Some(b'"') => {
+                    let end = quoted_end(bytes, name_end + 1).unwrap_or(bytes.len());
+                    (Some(&self.params[name_end + 2..end - 1]), end)
+                }
+                _ => {
+                    let end = scan(bytes, name_end + 1, |b| b == b';');
+                    (Some(&self.params[name_end + 1..end]), end)
+                }
+            },
+            _ => (None, name_end),
+        };
+        self.params = &self.params[end..];
+        Some((name, value))
+    }
+}
+
+/// Returns the position of the first byte from `start` on that ends a token
+fn scan(bytes: &[u8], start: usize, ends: impl Fn(u8) -> bool) -> usize {
+    bytes[start..]
+        .iter()
+        .position(|&b| ends(b))
+        .map_or(bytes.len(), |end| start + end)
+}
+
+/// Returns the position after the quoted string starting at `start`, skipping escapes
+fn quoted_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut position = start + 1;
+    while position < bytes.len() {
+        match bytes[position] {
+            b'\\' => position += 2,
+            b'"' => return Some(position + 1),
+            _ => position += 1,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::link_format::*;
+
+    #[test]
+    fn write_link() {
+        let mut buf: String<128> = String::new();
+        CoapLinkAttributes::new()
+            .rt("temperature-c")
+            .interface("sensor")
+            .ct(CoapMediaType::TextPlain)
+            .sz(12)
+            .title("Room temperature")
+            .obs()
+            .write_link(&mut buf, "sensors/temp")
+            .unwrap();
+        assert_eq!(
+            buf,
+            "</sensors/temp>;rt=\"temperature-c\";if=\"sensor\";ct=0;sz=12;title=\"Room temperature\";obs"
+        );
+
+        let mut buf: String<128> = String::new();
+        CoapLinkAttributes::new().write_link(&mut buf, "a").unwrap();
+        assert_eq!(buf, "</a>");
+    }
+
+    #[test]
+    fn parse_links() {
+        let document = b"</sensors/temp>;rt=\"temperature-c sensor\";if=sensor;ct=\"0 50\";sz=12;obs,\n\
+            <coap://[2001:db8::1]/lamp>;anchor=\"/a,b\";rel=\"describedby alternate\";title=\"x;\\\"y\\\"\",\
+            </empty>";
+        let mut links = parse(document).unwrap();
+
+        let link = links.next().unwrap().unwrap();
+        assert_eq!(link.get_target(), "/sensors/temp");
+        let mut rt = link.get_rt();
+        assert_eq!(rt.next(), Some("temperature-c"));
+        assert_eq!(rt.next(), Some("sensor"));
+        assert_eq!(rt.next(), None);
+        assert_eq!(link.get_interface().next(), Some("sensor"));
+        let mut ct = link.get_ct();
+        assert_eq!(ct.next(), Some(CoapMediaType::TextPlain));
+        assert_eq!(ct.next(), Some(CoapMediaType::ApplicationJson));
+        assert_eq!(ct.next(), None);
+        assert_eq!(link.get_sz(), Some(12));
+        assert!(link.is_obs());
+        assert_eq!(link.get_anchor(), None);
+        assert_eq!(link.get_rel().next(), None);
+
+        let link = links.next().unwrap().unwrap();
+        assert_eq!(link.get_target(), "coap://[2001:db8::1]/lamp");
+        assert_eq!(link.get_anchor(), Some("/a,b"));
+        assert_eq!(link.get_rel().nth(1), Some("alternate"));
+        assert_eq!(link.get_title(), Some("x;\\\"y\\\""));
+        assert!(!link.is_obs());
+        let mut attributes = link.attributes();
+        assert_eq!(attributes.next(), Some(("anchor", Some("/a,b"))));
+        assert_eq!(attributes.nth(1).map(|(name, _)| name), Some("title"));
+        assert_eq!(attributes.next(), None);
+
+        let link = links.next().unwrap().unwrap();
+        assert_eq!(link.get_target(), "/empty");
+        assert_eq!(link.attributes().next(), None);
+        assert_eq!(links.next(), None);
+
+        assert_eq!(parse(b"").unwrap().next(), None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            parse(&[b'<', 0xff, b'>']).err(),
+            Some(CoapError::InvalidLinkFormat { offset: 1 })
+        );
+        let errors: [(&[u8], usize); 5] = [
+            (b"/a", 0),
+            (b"</a", 0),
+            (b"</a>;", 5),
+            (b"</a>;title=\"x", 11),
+            (b"</a>x", 4),
+        ];
+        for (document, offset) in errors.iter() {
+            let mut links = parse(document).unwrap();
+            assert_eq!(
+                links.next(),
+                Some(Err(CoapError::InvalidLinkFormat { offset: *offset }))
+            );
+            assert_eq!(links.next(), None);
+        }
+        let mut links = parse(b"</a>,</b>;=1,</c>").unwrap();
+        assert!(links.next().unwrap().is_ok());
+        assert!(links.next().unwrap().is_err());
+        assert_eq!(links.next(), None);
+    }
+
+    #[test]
+    fn filter() {
+        let attributes = CoapLinkAttributes::new()
+            .rt("temperature-c light")
+            .ct(CoapMediaType::ApplicationJson)
+            .obs();
+        assert!(attributes.matches_filter("sensors/temp", "rt", "light"));
+        assert!(attributes.matches_filter("sensors/temp", "rt", "temp*"));
+        assert!(attributes.matches_filter("sensors/temp", "rt", "*"));
+        assert!(!attributes.matches_filter("sensors/temp", "rt", "temperature"));
+        assert!(attributes.matches_filter("sensors/temp", "ct", "50"));
+        assert!(attributes.matches_filter("sensors/temp", "ct", "5*"));
+        assert!(!attributes.matches_filter("sensors/temp", "ct", "0"));
+        assert!(attributes.matches_filter("sensors/temp", "href", "/sensors/temp"));
+        assert!(attributes.matches_filter("sensors/temp", "href", "/sensors*"));
+        assert!(!attributes.matches_filter("sensors/temp", "href", "/actuators*"));
+        assert!(attributes.matches_filter("sensors/temp", "obs", ""));
+        assert!(!attributes.matches_filter("sensors/temp", "if", "*"));
+        assert!(!attributes.matches_filter("sensors/temp", "sz", "*"));
+        assert!(!attributes.matches_filter("sensors/temp", "unknown", "x"));
+    }
+}
@@ -0,0 +1,152 @@
+//! CoAP Content-Formats, the IANA "CoAP Content-Formats" registry (RFC 7252 §12.3)
+
+/// Defines the media type enum together with its registry numbers and names
+macro_rules! media_types {
+    ($($number:literal => $variant:ident, $name:literal;)*) => {
+        /// CoAP Content-Formats, used by the Content-Format and Accept options.
+        /// Numbers without a named variant are kept as [`CoapMediaType::Other`]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum CoapMediaType {
+            $(
+                #[doc = $name]
+                $variant,
+            )*
+            /// Any other Content-Format number
+            Other(u16),
+        }
+
+        impl CoapMediaType {
+            /// Returns the media type with its parameters and content coding,
+            /// or `None` for [`CoapMediaType::Other`]
+            pub fn as_str(&self) -> Option<&'static str> {
+                match self {
+                    $(CoapMediaType::$variant => Some($name),)*
+                    CoapMediaType::Other(_) => None,
+                }
+            }
+        }
+
+        impl From<u16> for CoapMediaType {
+            fn from(item: u16) -> Self {
+                match item {
+                    $($number => CoapMediaType::$variant,)*
+                    other => CoapMediaType::Other(other),
+                }
+            }
+        }
+
+        impl From<CoapMediaType> for u16 {
+            fn from(item: CoapMediaType) -> Self {
+                match item {
+                    $(CoapMediaType::$variant => $number,)*
+                    CoapMediaType::Other(other) => other,
+                }
+            }
+        }
+    };
+}
+
+media_types! {
+    0 => TextPlain, "text/plain; charset=utf-8";
+    16 => ApplicationCoseEncrypt0, "application/cose; cose-type=\"cose-encrypt0\"";
+    17 => ApplicationCoseMac0, "application/cose; cose-type=\"cose-mac0\"";
+    18 => ApplicationCoseSign1, "application/cose; cose-type=\"cose-sign1\"";
+    19 => ApplicationAceCbor, "application/ace+cbor";
+    21 => ImageGif, "image/gif";
+    22 => ImageJpeg, "image/jpeg";
+    23 => ImagePng, "image/png";
+    40 => ApplicationLinkFormat, "application/link-format";
+    41 => ApplicationXml, "application/xml";
+    42 => ApplicationOctetStream, "application/octet-stream";
+    47 => ApplicationExi, "application/exi";
+    50 => ApplicationJson, "application/json";
+    51 => ApplicationJsonPatchJson, "application/json-patch+json";
+    52 => ApplicationMergePatchJson, "application/merge-patch+json";
+    60 => ApplicationCbor, "application/cbor";
+    61 => ApplicationCwt, "application/cwt";
+    62 => ApplicationMultipartCore, "application/multipart-core";
+    63 => ApplicationCborSeq, "application/cbor-seq";
+    96 => ApplicationCoseEncrypt, "application/cose; cose-type=\"cose-encrypt\"";
+    97 => ApplicationCoseMac, "application/cose; cose-type=\"cose-mac\"";
+    98 => ApplicationCoseSign, "application/cose; cose-type=\"cose-sign\"";
+    101 => ApplicationCoseKey, "application/cose-key";
+    102 => ApplicationCoseKeySet, "application/cose-key-set";
+    110 => ApplicationSenmlJson, "application/senml+json";
+    111 => ApplicationSensmlJson, "application/sensml+json";
+    112 => ApplicationSenmlCbor, "application/senml+cbor";
+    113 => ApplicationSensmlCbor, "application/sensml+cbor";
+    114 => ApplicationSenmlExi, "application/senml-exi";
+    115 => ApplicationSensmlExi, "application/sensml-exi";
+    140 => ApplicationYangDataCborSid, "application/yang-data+cbor; id=sid";
+    256 => ApplicationCoapGroupJson, "application/coap-group+json";
+    257 => ApplicationConciseProblemDetailsCbor, "application/concise-problem-details+cbor";
+    258 => ApplicationSwidCbor, "application/swid+cbor";
+    271 => ApplicationDotsCbor, "application/dots+cbor";
+    272 => ApplicationMissingBlocksCborSeq, "application/missing-blocks+cbor-seq";
+    280 => ApplicationPkcs7MimeServerGeneratedKey, "application/pkcs7-mime; smime-type=server-generated-key";
+    281 => ApplicationPkcs7MimeCertsOnly, "application/pkcs7-mime; smime-type=certs-only";
+    284 => ApplicationPkcs8, "application/pkcs8";
+    285 => ApplicationCsrattrs, "application/csrattrs";
+    286 => ApplicationPkcs10, "application/pkcs10";
+    287 => ApplicationPkixCert, "application/pkix-cert";
+    290 => ApplicationAifCbor, "application/aif+cbor";
+    291 => ApplicationAifJson, "application/aif+json";
+    310 => ApplicationSenmlXml, "application/senml+xml";
+    311 => ApplicationSensmlXml, "application/sensml+xml";
+    320 => ApplicationSenmlEtchJson, "application/senml-etch+json";
+    322 => ApplicationSenmlEtchCbor, "application/senml-etch+cbor";
+    340 => ApplicationYangDataCbor, "application/yang-data+cbor";
+    341 => ApplicationYangDataCborName, "application/yang-data+cbor; id=name";
+    432 => ApplicationTdJson, "application/td+json";
+    433 => ApplicationTmJson, "application/tm+json";
+    10000 => ApplicationVndOcfCbor, "application/vnd.ocf+cbor";
+    10001 => ApplicationOscore, "application/oscore";
+    10002 => ApplicationJavascript, "application/javascript";
+    11050 => ApplicationJsonDeflate, "application/json, deflate coded";
+    11060 => ApplicationCborDeflate, "application/cbor, deflate coded";
+    11542 => ApplicationVndOmaLwm2mTlv, "application/vnd.oma.lwm2m+tlv";
+    11543 => ApplicationVndOmaLwm2mJson, "application/vnd.oma.lwm2m+json";
+    11544 => ApplicationVndOmaLwm2mCbor, "application/vnd.oma.lwm2m+cbor";
+    20000 => TextCss, "text/css";
+    30000 => ImageSvgXml, "image/svg+xml";
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::media_type::*;
+
+    #[test]
+    fn conversion() {
+        assert_eq!(CoapMediaType::from(0), CoapMediaType::TextPlain);
+        assert_eq!(CoapMediaType::from(60), CoapMediaType::ApplicationCbor);
+        assert_eq!(
+            CoapMediaType::from(112),
+            CoapMediaType::ApplicationSenmlCbor
+        );
+        assert_eq!(CoapMediaType::from(10001), CoapMediaType::ApplicationOscore);
+        assert_eq!(CoapMediaType::from(65000), CoapMediaType::Other(65000));
+        assert_eq!(u16::from(CoapMediaType::ApplicationLinkFormat), 40);
+        assert_eq!(
+            u16::from(CoapMediaType::ApplicationConciseProblemDetailsCbor),
+            257
+        );
+        assert_eq!(u16::from(CoapMediaType::Other(65000)), 65000);
+        // Every named number converts back to itself
+        for number in 0..=u16::MAX {
+            assert_eq!(u16::from(CoapMediaType::from(number)), number);
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(
+            CoapMediaType::ApplicationJson.as_str(),
+            Some("application/json")
+        );
+        assert_eq!(
+            CoapMediaType::ApplicationCoseSign1.as_str(),
+            Some("application/cose; cose-type=\"cose-sign1\"")
+        );
+        assert_eq!(CoapMediaType::Other(1).as_str(), None);
+    }
+}
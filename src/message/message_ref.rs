@@ -0,0 +1,424 @@
+//! Borrowed, zero-copy view of a CoAP message
+
+use super::header::{CoapHeader, CoapHeaderCode};
+use super::option::{decode_option_header, decode_str, decode_uint, CoapOptionNumbers};
+use super::{response_header, CoapMediaType, CoapMessage};
+use crate::CoapError;
+use core::convert::TryFrom;
+use heapless::Vec;
+
+/// A CoAP message parsed in place.
+/// Token, options and payload all borrow from the receive buffer, nothing is copied
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoapMessageRef<'a> {
+    pub(crate) header: CoapHeader,
+    token: &'a [u8],
+    options: &'a [u8],
+    payload: &'a [u8],
+    unrecognized_critical: Option<u16>,
+}
+
+impl<'a> CoapMessageRef<'a> {
+    /// Parses a message from its wire format.
+    /// All options are validated once here, so iterating over them later can not fail.
+    /// Unknown options are kept, the first unrecognized critical option is reported by
+    /// [`CoapMessageRef::get_unrecognized_critical_option`].
+    /// Options with a value length outside of their definition are treated like
+    /// unrecognized options, RFC 7252 §5.4.3
+    pub fn decode(buf: &'a [u8]) -> Result<Self, CoapError> {
+        let header = CoapHeader::decode(buf)?;
+        // An empty message is nothing but the header, RFC 7252 §4.1
+        if header.get_code() == CoapHeaderCode::EMPTY && (header.get_tkl() > 0 || buf.len() > 4) {
+            return Err(CoapError::InvalidEmptyMessage);
+        }
+        let tkl = header.get_tkl() as usize;
+        if tkl > 8 {
+            return Err(CoapError::BadTokenLength { length: tkl });
+        }
+        if buf.len() < 4 + tkl {
+            return Err(CoapError::TruncatedToken { offset: 4 });
+        }
+        let token = &buf[4..4 + tkl];
+        let rest = &buf[4 + tkl..];
+
+        let mut iter = CoapOptionIter::new(rest);
+        let mut unrecognized_critical = None;
+        while let Some(opt) = iter.next_option().map_err(|e| e.at_offset(4 + tkl))? {
+            let number = opt.get_option_number();
+            let recognized = number.is_recognized() && opt.has_valid_length();
+            if unrecognized_critical.is_none() && !recognized && number.is_critical() {
+                unrecognized_critical = Some(opt.number);
+            }
+        }
+        let options = &rest[..rest.len() - iter.buf.len()];
+
+        // The option iterator only stops at the end of the buffer or at the payload marker
+        let payload = match iter.buf {
+            [0xff] => {
+                return Err(CoapError::PayloadMarkerWithoutPayload {
+                    offset: buf.len() - 1,
+                })
+            }
+            [0xff, payload @ ..] => payload,
+            _ => iter.buf,
+        };
+
+        Ok(CoapMessageRef {
+            header,
+            token,
+            options,
+            payload,
+            unrecognized_critical,
+        })
+    }
+
+    /// Creates a response to this request with the code.
+    /// Token and message id are copied, a confirmable request gets a piggybacked response
+    /// in the acknowledgement, anything else a non-confirmable response
+    pub fn response<
+        const MAX_OPTIONS: usize,
+        const OPTION_SIZE: usize,
+        const PAYLOAD_SIZE: usize,
+    >(
+        &self,
+        code: CoapHeaderCode,
+    ) -> Result<CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>, CoapError> {
+        let mut response = CoapMessage::from_header(response_header(&self.header, code)?, &[])?;
+        response.set_token(self.token)?;
+        Ok(response)
+    }
+
+    /// Copies the message in its wire format, it has to fit into `N` bytes
+    pub(crate) fn to_vec<const N: usize>(self) -> Result<Vec<u8, N>, CoapError> {
+        let full = CoapError::CapacityExceeded { capacity: N };
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&self.header.encode()?)
+            .map_err(|_| full)?;
+        buf.extend_from_slice(self.token).map_err(|_| full)?;
+        buf.extend_from_slice(self.options).map_err(|_| full)?;
+        if !self.payload.is_empty() {
+            buf.push(0xff).map_err(|_| full)?;
+            buf.extend_from_slice(self.payload).map_err(|_| full)?;
+        }
+        Ok(buf)
+    }
+
+    /// Returns the message header
+    pub fn get_header(&self) -> &CoapHeader {
+        &self.header
+    }
+
+    /// Returns the message token
+    pub fn get_token(&self) -> &'a [u8] {
+        self.token
+    }
+
+    /// Returns an iterator over the message options.
+    /// Elective options with a value length outside of their definition are skipped
+    pub fn get_options(&self) -> CoapOptionIter<'a> {
+        CoapOptionIter::new(self.options)
+    }
+
+    /// Returns the Content-Format of the payload, if the message carries one
+    pub fn content_format(&self) -> Option<CoapMediaType> {
+        self.media_type_option(CoapOptionNumbers::ContentFormat)
+    }
+
+    /// Returns the Content-Format the client accepts, if the message carries one
+    pub fn accept(&self) -> Option<CoapMediaType> {
+        self.media_type_option(CoapOptionNumbers::Accept)
+    }
+
+    fn media_type_option(&self, number: CoapOptionNumbers) -> Option<CoapMediaType> {
+        let value = self
+            .get_options()
+            .find(|o| o.get_option_number() == number)?
+            .get_uint()
+            .ok()?;
+        u16::try_from(value).ok().map(CoapMediaType::from)
+    }
+
+    /// Returns the message payload
+    pub fn get_payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    /// Returns the number of the first critical option that is not recognized, if any.
+    /// A confirmable request carrying one must be answered with 4.02 Bad Option, RFC 7252 §5.4.1
+    pub fn get_unrecognized_critical_option(&self) -> Option<u16> {
+        self.unrecognized_critical
+    }
+}
+
+/// A single option borrowed from a message buffer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoapOptionRef<'a> {
+    number: u16,
+    data: &'a [u8],
+}
+
+impl<'a> CoapOptionRef<'a> {
+    /// Returns the option number
+    pub fn get_option_number(&self) -> CoapOptionNumbers {
+        self.number.into()
+    }
+
+    /// Returns the option value
+    pub fn get_option_data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Returns the option value as an unsigned integer.
+    /// Fails if the value is longer than 4 bytes
+    pub fn get_uint(&self) -> Result<u32, CoapError> {
+        decode_uint(self.number, self.data)
+    }
+
+    /// Returns the option value as a string.
+    /// Fails if the value is not valid UTF-8
+    pub fn get_str(&self) -> Result<&'a str, CoapError> {
+        decode_str(self.number, self.data)
+    }
+
+    fn has_valid_length(&self) -> bool {
+        self.get_option_number()
+            .length_range()
+            .contains(&self.data.len())
+    }
+}
+
+/// Lazy iterator over the options of a [`CoapMessageRef`]
+#[derive(Debug, Clone)]
+pub struct CoapOptionIter<'a> {
+    buf: &'a [u8],
+    prev_option: u16,
+    offset: usize,
+}
+
+impl<'a> CoapOptionIter<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        CoapOptionIter {
+            buf,
+            prev_option: 0,
+            offset: 0,
+        }
+    }
+
+    /// Parses the next option, stops at the payload marker or the end of the buffer.
+    /// Error offsets are relative to the start of the options
+    fn next_option(&mut self) -> Result<Option<CoapOptionRef<'a>>, CoapError> {
+        let buf = self.buf;
+        if buf.is_empty() || buf[0] == 0xff {
+            return Ok(None);
+        }
+        let (delta, length, index) =
+            decode_option_header(buf).map_err(|e| e.at_offset(self.offset))?;
+        if buf.len() < index + length {
+            return Err(CoapError::BadOptionLength {
+                offset: self.offset,
+            });
+        }
+        let number = self.prev_option as u32 + delta;
+        if number > u16::MAX as u32 {
+            return Err(CoapError::BadOptionDelta {
+                offset: self.offset,
+            });
+        }
+
+        self.prev_option = number as u16;
+        self.buf = &buf[index + length..];
+        self.offset += index + length;
+        Ok(Some(CoapOptionRef {
+            number: number as u16,
+            data: &buf[index..index + length],
+        }))
+    }
+}
+
+impl<'a> Iterator for CoapOptionIter<'a> {
+    type Item = CoapOptionRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The options have been validated when the message was decoded
+        while let Some(opt) = self.next_option().ok().flatten() {
+            if opt.has_valid_length() {
+                return Some(opt);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::header::*;
+    use crate::message::message_ref::*;
+    use crate::message::option::*;
+    use crate::message::CoapMessage;
+
+    #[test]
+    fn decode_borrowed() {
+        let header =
+            CoapHeader::new(CoapHeaderType::Confirmable, 2, CoapHeaderCode::GET, 123).unwrap();
+        let mut msg = CoapMessage::new(header, &[1, 2, 3]);
+        msg.set_token(&[100, 101]).unwrap();
+        msg.add_option(CoapOption::new(CoapOptionNumbers::UriPath, b"test"))
+            .unwrap();
+        msg.add_option(CoapOption::new(CoapOptionNumbers::UriPath, b"level"))
+            .unwrap();
+        let en_msg = msg.encode().unwrap();
+        let buf = &en_msg.0[..en_msg.1];
+        let view = CoapMessageRef::decode(buf).unwrap();
+
+        assert_eq!(*view.get_header(), header);
+        assert_eq!(view.get_token(), &[100, 101]);
+        let mut options = view.get_options();
+        let opt = options.next().unwrap();
+        assert_eq!(opt.get_option_number(), CoapOptionNumbers::UriPath);
+        assert_eq!(opt.get_option_data(), b"test");
+        assert_eq!(options.next().unwrap().get_option_data(), b"level");
+        assert!(options.next().is_none());
+        assert_eq!(view.get_payload(), &[1, 2, 3]);
+        // The payload points into the receive buffer
+        assert_eq!(view.get_payload().as_ptr(), buf[buf.len() - 3..].as_ptr());
+
+        // Copied back to the wire format
+        let copy: heapless::Vec<u8, 32> = view.to_vec().unwrap();
+        assert_eq!(&copy[..], buf);
+        assert_eq!(
+            view.to_vec::<8>(),
+            Err(CoapError::CapacityExceeded { capacity: 8 })
+        );
+    }
+
+    #[test]
+    fn decode_no_payload() {
+        let view = CoapMessageRef::decode(&[0x40, 1, 0, 1, 0xb1, b'a']).unwrap();
+        assert_eq!(view.get_options().count(), 1);
+        assert!(view.get_payload().is_empty());
+    }
+
+    #[test]
+    fn decode_malformed() {
+        use crate::CoapError;
+
+        assert_eq!(
+            CoapMessageRef::decode(&[0x40, 1, 0]),
+            Err(CoapError::TruncatedHeader { length: 3 })
+        );
+        assert_eq!(
+            CoapMessageRef::decode(&[0x80, 1, 0, 1]),
+            Err(CoapError::WrongVersion(2))
+        );
+        assert_eq!(
+            CoapMessageRef::decode(&[0x49, 1, 0, 1]),
+            Err(CoapError::BadTokenLength { length: 9 })
+        );
+        assert_eq!(
+            CoapMessageRef::decode(&[0x40, 0, 0, 1, 0xff, 1]),
+            Err(CoapError::InvalidEmptyMessage)
+        );
+        assert_eq!(
+            CoapMessageRef::decode(&[0x41, 0, 0, 1, 7]),
+            Err(CoapError::InvalidEmptyMessage)
+        );
+        // Payload marker without payload
+        assert_eq!(
+            CoapMessageRef::decode(&[0x40, 1, 0, 1, 0xff]),
+            Err(CoapError::PayloadMarkerWithoutPayload { offset: 4 })
+        );
+        // Token longer than the message
+        assert_eq!(
+            CoapMessageRef::decode(&[0x44, 1, 0, 1, 1]),
+            Err(CoapError::TruncatedToken { offset: 4 })
+        );
+        // Option value longer than the message
+        assert_eq!(
+            CoapMessageRef::decode(&[0x41, 1, 0, 1, 7, 0xb1, b'a', 0x14, b'a']),
+            Err(CoapError::BadOptionLength { offset: 7 })
+        );
+        // Missing extended delta byte
+        assert_eq!(
+            CoapMessageRef::decode(&[0x40, 1, 0, 1, 0xd0]),
+            Err(CoapError::BadOptionDelta { offset: 4 })
+        );
+        // Reserved delta nibble
+        assert_eq!(
+            CoapMessageRef::decode(&[0x40, 1, 0, 1, 0xb1, b'a', 0xf0]),
+            Err(CoapError::BadOptionDelta { offset: 6 })
+        );
+        // Reserved length nibble
+        assert_eq!(
+            CoapMessageRef::decode(&[0x40, 1, 0, 1, 0x1f]),
+            Err(CoapError::BadOptionLength { offset: 4 })
+        );
+        // Option number larger than 65535
+        assert_eq!(
+            CoapMessageRef::decode(&[0x40, 1, 0, 1, 0xe0, 0xfe, 0x00, 0xe0, 0x01, 0x00]),
+            Err(CoapError::BadOptionDelta { offset: 7 })
+        );
+    }
+
+    #[test]
+    fn decode_unknown_options() {
+        // Elective unknown option 1000 followed by critical unknown option 1001
+        let buf = [0x40, 1, 0, 1, 0xe1, 0x02, 0xdb, b'a', 0x11, b'b'];
+        let view = CoapMessageRef::decode(&buf).unwrap();
+        let mut options = view.get_options();
+        let opt = options.next().unwrap();
+        assert_eq!(opt.get_option_number(), CoapOptionNumbers::Unknown(1000));
+        assert_eq!(opt.get_option_data(), b"a");
+        let opt = options.next().unwrap();
+        assert_eq!(opt.get_option_number(), CoapOptionNumbers::Unknown(1001));
+        assert_eq!(view.get_unrecognized_critical_option(), Some(1001));
+
+        // Elective only
+        let view = CoapMessageRef::decode(&buf[..8]).unwrap();
+        assert_eq!(view.get_unrecognized_critical_option(), None);
+    }
+
+    #[test]
+    fn decode_largest_option() {
+        // Unknown elective option 1000 with the largest encodable value length
+        let mut buf = [0u8; 4 + 5 + 65804];
+        buf[..4].copy_from_slice(&[0x40, 1, 0, 1]);
+        let index = 4 + encode_option_header(1000, 65804, &mut buf[4..]).unwrap();
+        assert_eq!(index, 9);
+        let view = CoapMessageRef::decode(&buf).unwrap();
+        let opt = view.get_options().next().unwrap();
+        assert_eq!(opt.get_option_number(), CoapOptionNumbers::Unknown(1000));
+        assert_eq!(opt.get_option_data().len(), 65804);
+        assert!(view.get_payload().is_empty());
+    }
+
+    #[test]
+    fn decode_option_length_out_of_range() {
+        // Content-Format with 3 bytes is ignored as it is elective, Uri-Path "a" is kept
+        let buf = [0x40, 1, 0, 1, 0xb1, b'a', 0x13, 0, 0, 1];
+        let view = CoapMessageRef::decode(&buf).unwrap();
+        assert_eq!(view.get_unrecognized_critical_option(), None);
+        let mut options = view.get_options();
+        assert_eq!(options.next().unwrap().get_str(), Ok("a"));
+        assert!(options.next().is_none());
+
+        // Uri-Host with an empty value is critical
+        let view = CoapMessageRef::decode(&[0x40, 1, 0, 1, 0x30]).unwrap();
+        assert_eq!(view.get_unrecognized_critical_option(), Some(3));
+    }
+
+    #[test]
+    fn option_ref_values() {
+        let buf = [
+            0x40, 1, 0, 1, 0xb2, 0xc3, 0xa9, 0x12, 0x01, 0x00, 0x31, 0xff,
+        ];
+        let view = CoapMessageRef::decode(&buf).unwrap();
+        let mut options = view.get_options();
+        assert_eq!(options.next().unwrap().get_str(), Ok("\u{e9}"));
+        assert_eq!(options.next().unwrap().get_uint(), Ok(256));
+        let query = options.next().unwrap();
+        assert_eq!(
+            query.get_str(),
+            Err(crate::CoapError::InvalidOptionValue(15))
+        );
+    }
+}
@@ -1,165 +1,402 @@
+//! CoAP message codec.
+//!
+//! A [`CoapMessage`] is made up of a [`header::CoapHeader`], an optional token,
+//! a set of [`option::CoapOptions`] and an optional payload.
+//! Messages can be built up by hand and encoded, or decoded from a received buffer.
+//! [`CoapMessageRef`] gives a borrowed view of a received message without copying it.
+
 use crate::CoapError;
-use heapless::consts::*;
+use core::convert::TryFrom;
 use heapless::Vec;
 
+pub mod block;
+pub mod builder;
 pub mod header;
+pub mod link_format;
+pub mod media_type;
+pub mod message_ref;
 pub mod option;
 
-#[derive(Debug, Clone)]
+pub use block::CoapBlock;
+pub use builder::CoapMessageBuilder;
+pub use link_format::{CoapLink, CoapLinkAttributes};
+pub use media_type::CoapMediaType;
+pub use message_ref::{CoapMessageRef, CoapOptionIter, CoapOptionRef};
+use option::{DEFAULT_MAX_OPTIONS, DEFAULT_OPTION_SIZE};
+
+/// Default maximum payload length of a message
+pub const DEFAULT_PAYLOAD_SIZE: usize = 255;
+
+/// CoAP request methods
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CoapMethod {
+    /// GET method
     GET,
+    /// POST method
     POST,
+    /// PUT method
     PUT,
+    /// DELETE method
     DELETE,
+    /// FETCH method, RFC 8132
+    FETCH,
+    /// PATCH method, RFC 8132
+    PATCH,
+    /// iPATCH method, RFC 8132
+    IPATCH,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum CoapMediaType {
-    TextPlain,
-    ApplicationLinkFormat,
-    ApplicationXml,
-    ApplicationOctetStream,
-    ApplicationExi,
-    ApplicationJson,
+impl CoapMethod {
+    /// Returns the method of a request code, or `None` for other codes
+    pub fn from_code(code: header::CoapHeaderCode) -> Option<Self> {
+        match code {
+            header::CoapHeaderCode::GET => Some(CoapMethod::GET),
+            header::CoapHeaderCode::POST => Some(CoapMethod::POST),
+            header::CoapHeaderCode::PUT => Some(CoapMethod::PUT),
+            header::CoapHeaderCode::DELETE => Some(CoapMethod::DELETE),
+            header::CoapHeaderCode::FETCH => Some(CoapMethod::FETCH),
+            header::CoapHeaderCode::PATCH => Some(CoapMethod::PATCH),
+            header::CoapHeaderCode::IPATCH => Some(CoapMethod::IPATCH),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// CoAP token, 0 to 8 bytes used to match responses to requests
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct CoapToken {
-    token: Vec<u8, U8>,
+    token: Vec<u8, 8>,
     length: usize,
 }
 
 impl CoapToken {
+    /// Creates a new empty token
     pub fn new() -> Self {
         CoapToken {
-            token: Vec::<u8, U8>::new(),
+            token: Vec::new(),
             length: 0,
         }
     }
+    /// Returns the length of the token in bytes
     pub fn len(&self) -> usize {
         self.length
     }
+    /// Returns true if the token is empty
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+    /// Returns the token bytes
+    pub fn as_slice(&self) -> &[u8] {
+        &self.token
+    }
+
+    /// Creates a token from its bytes, max 8
+    pub(crate) fn from_slice(token: &[u8]) -> Result<Self, CoapError> {
+        let length = token.len();
+        Ok(CoapToken {
+            token: Vec::from_slice(token).map_err(|_| CoapError::BadTokenLength { length })?,
+            length,
+        })
+    }
 }
 
+/// A complete CoAP message.
+/// Holds up to `MAX_OPTIONS` options of up to `OPTION_SIZE` bytes each
+/// and a payload of up to `PAYLOAD_SIZE` bytes
 #[derive(Debug, Clone, PartialEq)]
-pub struct CoapMessage {
-    pub header: header::CoapHeader,
+pub struct CoapMessage<
+    const MAX_OPTIONS: usize = DEFAULT_MAX_OPTIONS,
+    const OPTION_SIZE: usize = DEFAULT_OPTION_SIZE,
+    const PAYLOAD_SIZE: usize = DEFAULT_PAYLOAD_SIZE,
+> {
+    pub(crate) header: header::CoapHeader,
     token: CoapToken,
-    pub options: option::CoapOptions,
+    pub(crate) options: option::CoapOptions<MAX_OPTIONS, OPTION_SIZE>,
     payload_marker: u8,
-    payload: Vec<u8, U255>,
+    payload: Vec<u8, PAYLOAD_SIZE>,
     payload_length: usize,
 }
 
 impl CoapMessage {
+    /// Creates a new message with the default capacities from a header and a payload.
+    /// Token and options are added with [`CoapMessage::set_token`] and [`CoapMessage::add_option`]
+    ///
+    /// # Panics
+    /// Panics if the payload is longer than 255 bytes, see [`CoapMessage::from_header`]
     pub fn new(header: header::CoapHeader, payload: &[u8]) -> Self {
-        let payload_length = payload.len();
-        let payload = Vec::from_slice(payload).unwrap();
-        CoapMessage {
+        match Self::from_header(header, payload) {
+            Ok(message) => message,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Starts building a message with the code and the default capacities
+    pub fn builder(
+        code: header::CoapHeaderCode,
+    ) -> CoapMessageBuilder<DEFAULT_MAX_OPTIONS, DEFAULT_OPTION_SIZE, DEFAULT_PAYLOAD_SIZE> {
+        CoapMessageBuilder::new(code)
+    }
+
+    /// Starts building a GET request with the default capacities
+    pub fn get(
+    ) -> CoapMessageBuilder<DEFAULT_MAX_OPTIONS, DEFAULT_OPTION_SIZE, DEFAULT_PAYLOAD_SIZE> {
+        CoapMessageBuilder::new(header::CoapHeaderCode::GET)
+    }
+
+    /// Starts building a POST request with the default capacities
+    pub fn post(
+    ) -> CoapMessageBuilder<DEFAULT_MAX_OPTIONS, DEFAULT_OPTION_SIZE, DEFAULT_PAYLOAD_SIZE> {
+        CoapMessageBuilder::new(header::CoapHeaderCode::POST)
+    }
+
+    /// Starts building a PUT request with the default capacities
+    pub fn put(
+    ) -> CoapMessageBuilder<DEFAULT_MAX_OPTIONS, DEFAULT_OPTION_SIZE, DEFAULT_PAYLOAD_SIZE> {
+        CoapMessageBuilder::new(header::CoapHeaderCode::PUT)
+    }
+
+    /// Starts building a DELETE request with the default capacities
+    pub fn delete(
+    ) -> CoapMessageBuilder<DEFAULT_MAX_OPTIONS, DEFAULT_OPTION_SIZE, DEFAULT_PAYLOAD_SIZE> {
+        CoapMessageBuilder::new(header::CoapHeaderCode::DELETE)
+    }
+}
+
+impl<const MAX_OPTIONS: usize, const OPTION_SIZE: usize, const PAYLOAD_SIZE: usize>
+    CoapMessage<MAX_OPTIONS, OPTION_SIZE, PAYLOAD_SIZE>
+{
+    /// Creates a new message from a header and a payload.
+    /// Fails if the payload is longer than `PAYLOAD_SIZE` bytes
+    pub fn from_header(header: header::CoapHeader, payload: &[u8]) -> Result<Self, CoapError> {
+        let mut message = CoapMessage {
             header,
             token: CoapToken::new(),
-            options: option::CoapOptions::new(),
+            options: option::CoapOptions::default(),
             payload_marker: 0xff,
-            payload: payload,
-            payload_length,
-        }
+            payload: Vec::new(),
+            payload_length: 0,
+        };
+        message.set_payload(payload)?;
+        Ok(message)
     }
 
-    pub(crate) fn set_token(&mut self, token: &[u8]) -> Result<(), CoapError> {
-        if token.len() > 8 {
-            return Err(CoapError::MessageFormatError);
-        }
-        self.token.length = token.len();
-        self.token.token = Vec::from_slice(token).unwrap();
+    /// Creates a piggybacked or non-confirmable response to this request, see
+    /// [`CoapMessageRef::response`]
+    pub fn response(&self, code: header::CoapHeaderCode) -> Result<Self, CoapError> {
+        let mut response = Self::from_header(response_header(&self.header, code)?, &[])?;
+        response.token = self.token.clone();
+        Ok(response)
+    }
+
+    /// Returns the message header
+    pub fn get_header(&self) -> &header::CoapHeader {
+        &self.header
+    }
+
+    /// Returns the message token
+    pub fn get_token(&self) -> &[u8] {
+        self.token.as_slice()
+    }
+
+    /// Returns the message options
+    pub fn get_options(&self) -> &option::CoapOptions<MAX_OPTIONS, OPTION_SIZE> {
+        &self.options
+    }
+
+    /// Returns the message options for modification
+    pub fn get_options_mut(&mut self) -> &mut option::CoapOptions<MAX_OPTIONS, OPTION_SIZE> {
+        &mut self.options
+    }
+
+    /// Returns the Content-Format of the payload, if the message carries one
+    pub fn content_format(&self) -> Option<CoapMediaType> {
+        self.media_type_option(option::CoapOptionNumbers::ContentFormat)
+    }
+
+    /// Sets the Content-Format of the payload, replacing any previous one
+    pub fn set_content_format(&mut self, format: CoapMediaType) -> Result<(), CoapError> {
+        self.set_media_type_option(option::CoapOptionNumbers::ContentFormat, format)
+    }
+
+    /// Returns the Content-Format the client accepts, if the message carries one
+    pub fn accept(&self) -> Option<CoapMediaType> {
+        self.media_type_option(option::CoapOptionNumbers::Accept)
+    }
+
+    /// Sets the Content-Format the client accepts, replacing any previous one
+    pub fn set_accept(&mut self, format: CoapMediaType) -> Result<(), CoapError> {
+        self.set_media_type_option(option::CoapOptionNumbers::Accept, format)
+    }
+
+    fn media_type_option(&self, number: option::CoapOptionNumbers) -> Option<CoapMediaType> {
+        let value = self
+            .options
+            .iter()
+            .find(|o| o.get_option_number() == number)?
+            .get_uint()
+            .ok()?;
+        u16::try_from(value).ok().map(CoapMediaType::from)
+    }
+
+    fn set_media_type_option(
+        &mut self,
+        number: option::CoapOptionNumbers,
+        format: CoapMediaType,
+    ) -> Result<(), CoapError> {
+        let (bytes, start) = option::encode_uint(u16::from(format) as u32);
+        self.options.replace(number, &bytes[start..])
+    }
+
+    /// Returns the message payload
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload[..self.payload_length]
+    }
+
+    /// Sets the message token, max 8 bytes.
+    /// The token length in the header is updated to match
+    pub fn set_token(&mut self, token: &[u8]) -> Result<(), CoapError> {
+        self.token = CoapToken::from_slice(token)?;
+        self.header.set_tkl(self.token.len() as u8);
+
+        Ok(())
+    }
+
+    /// Sets the message payload, max `PAYLOAD_SIZE` bytes
+    pub fn set_payload(&mut self, payload: &[u8]) -> Result<(), CoapError> {
+        self.payload = Vec::from_slice(payload).map_err(|_| CoapError::CapacityExceeded {
+            capacity: self.payload.capacity(),
+        })?;
+        self.payload_length = payload.len();
 
         Ok(())
     }
 
-    pub(crate) fn add_option(&mut self, option: option::CoapOption) -> Result<(), CoapError> {
+    /// Fills the payload with up to `length` bytes written by the closure,
+    /// which returns the number of bytes it wrote
+    pub(crate) fn fill_payload<F>(&mut self, length: usize, fill: F) -> Result<usize, CoapError>
+    where
+        F: FnOnce(&mut [u8]) -> Result<usize, CoapError>,
+    {
+        self.payload
+            .resize(length, 0)
+            .map_err(|_| CoapError::CapacityExceeded {
+                capacity: self.payload.capacity(),
+            })?;
+        let written = fill(&mut self.payload).map(|written| written.min(length));
+        self.payload.truncate(*written.as_ref().unwrap_or(&0));
+        self.payload_length = self.payload.len();
+        written
+    }
+
+    /// Adds an option to the message.
+    /// Options are kept in option number order, see [`option::CoapOptions::push`]
+    pub fn add_option(&mut self, option: option::CoapOption<OPTION_SIZE>) -> Result<(), CoapError> {
         self.options.push(option)?;
         Ok(())
     }
 
-    pub(crate) fn encode(&mut self) -> Result<([u8; 255], usize), CoapError> {
-        let mut index = 0;
+    /// Encodes the message into its wire format.
+    /// Returns the encoded buffer together with the number of bytes used
+    pub fn encode(&self) -> Result<([u8; 255], usize), CoapError> {
         let mut msg: [u8; 255] = [0; 255];
-        let header = self.header.encode()?;
-        for i in &header {
-            msg[index] = *i;
-            index += 1;
+        let length = self.encode_into(&mut msg)?;
+        Ok((msg, length))
+    }
+
+    /// Returns the number of bytes needed to encode the message
+    pub fn encoded_len(&self) -> usize {
+        let mut length = 4 + self.token.len();
+        let mut prev_option = option::CoapOptionNumbers::Zero;
+        for opt in self.options.iter() {
+            length += opt.encoded_len(prev_option);
+            prev_option = opt.get_option_number();
         }
-        if self.token.len() != 0 {
-            for i in 0..self.token.len() {
-                msg[index] = self.token.token[i];
-                index += 1;
-            }
+        if self.payload_length != 0 {
+            length += 1 + self.payload_length;
         }
-        if self.options.len() != 0 {
-            let mut prev_option = option::CoapOptionNumbers::Zero;
-            for opt in self.options.options.iter() {
-                let o = opt.encode(prev_option)?;
-                for j in 0..o.1 {
-                    msg[index] = o.0[j];
-                    index += 1;
-                }
-                prev_option = opt.get_option_number();
-            }
+        length
+    }
+
+    /// Encodes the message directly into the buffer.
+    /// Returns the number of bytes written, or [`CoapError::BufferTooSmall`]
+    /// with the number of bytes needed if the message does not fit
+    pub fn encode_into(&self, buf: &mut [u8]) -> Result<usize, CoapError> {
+        let length = self.encoded_len();
+        if buf.len() < length {
+            return Err(CoapError::BufferTooSmall {
+                needed: length,
+                available: buf.len(),
+            });
+        }
+        // The token length always follows the token actually carried
+        let mut header = self.header;
+        header.set_tkl(self.token.len() as u8);
+        buf[..4].copy_from_slice(&header.encode()?);
+        let mut index = 4;
+        buf[index..index + self.token.len()].copy_from_slice(self.token.as_slice());
+        index += self.token.len();
+
+        let mut prev_option = option::CoapOptionNumbers::Zero;
+        for opt in self.options.iter() {
+            index += opt.encode_into(prev_option, &mut buf[index..])?;
+            prev_option = opt.get_option_number();
         }
         if self.payload_length != 0 {
-            msg[index] = self.payload_marker;
+            buf[index] = self.payload_marker;
             index += 1;
-            for i in 0..self.payload_length {
-                msg[index] = self.payload[i];
-                index += 1;
-            }
+            buf[index..index + self.payload_length].copy_from_slice(self.get_payload());
+            index += self.payload_length;
         }
 
-        Ok((msg, index))
+        Ok(index)
     }
 
-    pub(crate) fn decode(buf: &mut [u8]) -> Result<Self, CoapError> {
-        if buf.len() < 4 {
-            return Err(CoapError::MessageError);
+    /// Decodes a message from its wire format into owned buffers.
+    /// Unknown elective options are kept, an unrecognized critical option is reported as
+    /// [`CoapError::UnrecognizedCriticalOption`].
+    /// Use [`CoapMessageRef::decode`] to inspect a message without copying it
+    pub fn decode(buf: &[u8]) -> Result<Self, CoapError> {
+        let view = CoapMessageRef::decode(buf)?;
+        if let Some(number) = view.get_unrecognized_critical_option() {
+            return Err(CoapError::UnrecognizedCriticalOption(number));
         }
-        let (raw_header, mut rest) = buf.split_at_mut(4);
-        let header = header::CoapHeader::decode(raw_header)?;
-        let mut token: &[u8] = &[];
-        if header.get_tkl() > 8 {
-            return Err(CoapError::MessageFormatError);
-        }
-        if header.get_tkl() != 0 {
-            let tok = rest.split_at_mut(header.get_tkl() as usize);
-            token = tok.0;
-            rest = tok.1;
-        }
-        if rest.len() == 0 {
-            return Ok(CoapMessage::new(header, &[0]));
-        }
-        let (options, mut rest) = option::CoapOptions::decode(rest)?;
+        Self::from_ref(&view)
+    }
 
-        if rest.len() > 1 && rest[0] == 0xff {
-            rest = &rest[1..];
-        } else if rest.len() == 1 && rest[0] == 0xff {
-            return Err(CoapError::MessageError);
+    /// Copies a borrowed message into owned buffers
+    pub(crate) fn from_ref(view: &CoapMessageRef) -> Result<Self, CoapError> {
+        let mut message = Self::from_header(*view.get_header(), view.get_payload())?;
+        message.set_token(view.get_token())?;
+        for opt in view.get_options() {
+            message.add_option(option::CoapOption::try_new(
+                opt.get_option_number(),
+                opt.get_option_data(),
+            )?)?;
         }
-        let mut new_message: CoapMessage = CoapMessage::new(header, rest);
-        new_message.set_token(token)?;
-        new_message.options = options;
 
-        Ok(new_message)
+        Ok(message)
     }
 }
 
-impl From<u8> for CoapMethod {
-    fn from(item: u8) -> Self {
-        match item {
-            1 => CoapMethod::GET,
-            2 => CoapMethod::POST,
-            3 => CoapMethod::PUT,
-            4 => CoapMethod::DELETE,
-            _ => unreachable!(),
-        }
-    }
+/// Returns the token of an encoded message, empty if the message is too short
+pub(crate) fn encoded_token(message: &[u8]) -> &[u8] {
+    let tkl = message.first().map_or(0, |first| usize::from(first & 0xf));
+    message.get(4..4 + tkl).unwrap_or(&[])
+}
+
+/// Returns the header of a response to a request with the header.
+/// Confirmable requests are acknowledged, anything else gets a non-confirmable response
+pub(crate) fn response_header(
+    request: &header::CoapHeader,
+    code: header::CoapHeaderCode,
+) -> Result<header::CoapHeader, CoapError> {
+    let t = if request.get_type() == header::CoapHeaderType::Confirmable {
+        header::CoapHeaderType::Acknowledgement
+    } else {
+        header::CoapHeaderType::NonConfirmable
+    };
+    header::CoapHeader::new(t, request.get_tkl(), code, request.get_message_id())
 }
 
 impl From<CoapMethod> for u8 {
@@ -169,34 +406,16 @@ impl From<CoapMethod> for u8 {
             CoapMethod::POST => 2,
             CoapMethod::PUT => 3,
             CoapMethod::DELETE => 4,
+            CoapMethod::FETCH => 5,
+            CoapMethod::PATCH => 6,
+            CoapMethod::IPATCH => 7,
         }
     }
 }
 
-impl From<u8> for CoapMediaType {
-    fn from(item: u8) -> Self {
-        match item {
-            0 => CoapMediaType::TextPlain,
-            40 => CoapMediaType::ApplicationLinkFormat,
-            41 => CoapMediaType::ApplicationXml,
-            42 => CoapMediaType::ApplicationOctetStream,
-            47 => CoapMediaType::ApplicationExi,
-            50 => CoapMediaType::ApplicationJson,
-            _ => unreachable!(),
-        }
-    }
-}
-
-impl From<CoapMediaType> for u8 {
-    fn from(item: CoapMediaType) -> Self {
-        match item {
-            CoapMediaType::TextPlain => 0,
-            CoapMediaType::ApplicationLinkFormat => 40,
-            CoapMediaType::ApplicationXml => 41,
-            CoapMediaType::ApplicationOctetStream => 42,
-            CoapMediaType::ApplicationExi => 47,
-            CoapMediaType::ApplicationJson => 50,
-        }
+impl From<CoapMethod> for header::CoapHeaderCode {
+    fn from(item: CoapMethod) -> Self {
+        header::CoapHeaderCode::from(u8::from(item))
     }
 }
 
@@ -206,6 +425,28 @@ mod tests {
     use crate::message::header;
     use crate::message::option;
 
+    #[test]
+    fn method_from_code() {
+        // Codes built from their number get the named variant
+        let get = header::CoapHeaderCode::from_class_detail(0, 1).unwrap();
+        assert_eq!(
+            message::CoapMethod::from_code(get),
+            Some(message::CoapMethod::GET)
+        );
+        assert_eq!(
+            message::CoapMethod::from_code(header::CoapHeaderCode::from(7)),
+            Some(message::CoapMethod::IPATCH)
+        );
+        assert_eq!(
+            message::CoapMethod::from_code(header::CoapHeaderCode::from(8)),
+            None
+        );
+        assert_eq!(
+            message::CoapMethod::from_code(header::CoapHeaderCode::Content),
+            None
+        );
+    }
+
     #[test]
     fn encode_header_payload() {
         let data = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
@@ -216,7 +457,7 @@ mod tests {
             123,
         )
         .unwrap();
-        let mut msg = message::CoapMessage::new(header.clone(), &data);
+        let msg = message::CoapMessage::new(header, &data);
         let en_msg = msg.encode().unwrap();
 
         // Check payload marker and payload
@@ -240,9 +481,9 @@ mod tests {
             123,
         )
         .unwrap();
-        let mut msg = message::CoapMessage::new(header.clone(), &data);
-        let mut en_msg = msg.encode().unwrap();
-        let buf = &mut en_msg.0[..en_msg.1];
+        let msg = message::CoapMessage::new(header, &data);
+        let en_msg = msg.encode().unwrap();
+        let buf = &en_msg.0[..en_msg.1];
         let de_msg = message::CoapMessage::decode(buf).unwrap();
 
         assert_eq!(de_msg, msg);
@@ -257,10 +498,10 @@ mod tests {
             123,
         )
         .unwrap();
-        let mut msg = message::CoapMessage::new(header.clone(), &data);
+        let mut msg = message::CoapMessage::new(header, &data);
         msg.set_token(&[100, 111, 122]).unwrap();
-        let mut en_msg = msg.encode().unwrap();
-        let buf = &mut en_msg.0[..en_msg.1];
+        let en_msg = msg.encode().unwrap();
+        let buf = &en_msg.0[..en_msg.1];
         let de_msg = message::CoapMessage::decode(buf).unwrap();
 
         assert_eq!(de_msg, msg);
@@ -275,17 +516,14 @@ mod tests {
             123,
         )
         .unwrap();
-        let mut msg = message::CoapMessage::new(header.clone(), &data);
+        let mut msg = message::CoapMessage::new(header, &data);
         msg.set_token(&[100, 111, 122]).unwrap();
-        msg.add_option(option::CoapOption::new(
-            option::CoapOptionNumbers::Accept,
-            &[],
-        ))
-        .unwrap();
+        msg.add_option(option::CoapOption::uint(option::CoapOptionNumbers::Accept, 50).unwrap())
+            .unwrap();
 
         let ref_msg = msg.clone();
-        let mut en_msg = msg.encode().unwrap();
-        let buf = &mut en_msg.0[..en_msg.1];
+        let en_msg = msg.encode().unwrap();
+        let buf = &en_msg.0[..en_msg.1];
         let de_msg = message::CoapMessage::decode(buf).unwrap();
 
         assert_eq!(de_msg, ref_msg);
@@ -301,7 +539,7 @@ mod tests {
             123,
         )
         .unwrap();
-        let mut msg = message::CoapMessage::new(header.clone(), &data);
+        let mut msg = message::CoapMessage::new(header, &data);
         msg.set_token(&[100, 111, 122]).unwrap();
         msg.add_option(option::CoapOption::new(
             option::CoapOptionNumbers::IfNoneMatch,
@@ -327,10 +565,177 @@ mod tests {
         .unwrap(); // 35
 
         let ref_msg = msg.clone();
-        let mut en_msg = msg.encode().unwrap();
-        let buf = &mut en_msg.0[..en_msg.1];
+        let en_msg = msg.encode().unwrap();
+        let buf = &en_msg.0[..en_msg.1];
         let de_msg = message::CoapMessage::decode(buf).unwrap();
 
         assert_eq!(de_msg, ref_msg);
     }
+
+    #[test]
+    fn accessors() {
+        let header = header::CoapHeader::new(
+            header::CoapHeaderType::Confirmable,
+            2,
+            header::CoapHeaderCode::GET,
+            42,
+        )
+        .unwrap();
+        let mut msg = message::CoapMessage::new(header, b"hello");
+        msg.set_token(&[1, 2]).unwrap();
+        msg.add_option(option::CoapOption::new(
+            option::CoapOptionNumbers::UriPath,
+            b"test",
+        ))
+        .unwrap();
+        let en_msg = msg.encode().unwrap();
+        let de_msg: message::CoapMessage =
+            message::CoapMessage::decode(&en_msg.0[..en_msg.1]).unwrap();
+
+        assert_eq!(de_msg.get_header().get_message_id(), 42);
+        assert_eq!(de_msg.get_token(), &[1, 2]);
+        assert_eq!(de_msg.get_options().len(), 1);
+        let opt = de_msg.get_options().iter().next().unwrap();
+        assert_eq!(opt.get_option_number(), option::CoapOptionNumbers::UriPath);
+        assert_eq!(&opt.get_option_data()[..], b"test");
+        assert_eq!(de_msg.get_payload(), b"hello");
+    }
+
+    #[test]
+    fn encode_into() {
+        let header = header::CoapHeader::new(
+            header::CoapHeaderType::NonConfirmable,
+            0,
+            header::CoapHeaderCode::Content,
+            7,
+        )
+        .unwrap();
+        let mut msg = message::CoapMessage::new(header, &[0xaa; 200]);
+        msg.add_option(option::CoapOption::new(
+            option::CoapOptionNumbers::UriPath,
+            &[b'a'; 100],
+        ))
+        .unwrap();
+        // 4 header + 1 option header + 1 extended length + 100 option value + 1 marker + 200
+        assert_eq!(msg.encoded_len(), 307);
+
+        let mut buf = [0u8; 512];
+        let length = msg.encode_into(&mut buf).unwrap();
+        assert_eq!(length, 307);
+        assert_eq!(message::CoapMessage::decode(&buf[..length]).unwrap(), msg);
+
+        let mut small = [0u8; 306];
+        match msg.encode_into(&mut small) {
+            Err(crate::CoapError::BufferTooSmall { needed, available }) => {
+                assert_eq!(needed, 307);
+                assert_eq!(available, 306);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn custom_capacities() {
+        let header = header::CoapHeader::new(
+            header::CoapHeaderType::NonConfirmable,
+            0,
+            header::CoapHeaderCode::Content,
+            7,
+        )
+        .unwrap();
+        let mut msg: message::CoapMessage<1, 4, 8> =
+            message::CoapMessage::from_header(header, &[1; 8]).unwrap();
+        assert_eq!(
+            msg.set_payload(&[1; 9]),
+            Err(crate::CoapError::CapacityExceeded { capacity: 8 })
+        );
+        assert_eq!(
+            option::CoapOption::<4>::try_new(option::CoapOptionNumbers::UriPath, b"hello"),
+            Err(crate::CoapError::CapacityExceeded { capacity: 4 })
+        );
+        msg.add_option(
+            option::CoapOption::try_new(option::CoapOptionNumbers::UriPath, b"a").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            msg.add_option(
+                option::CoapOption::try_new(option::CoapOptionNumbers::UriPath, b"b").unwrap()
+            ),
+            Err(crate::CoapError::CapacityExceeded { capacity: 1 })
+        );
+
+        let en_msg = msg.encode().unwrap();
+        let de_msg = message::CoapMessage::<1, 4, 8>::decode(&en_msg.0[..en_msg.1]).unwrap();
+        assert_eq!(de_msg, msg);
+        // A message that does not fit the capacities is rejected
+        let big = message::CoapMessage::new(header, &[1; 9]).encode().unwrap();
+        assert_eq!(
+            message::CoapMessage::<1, 4, 8>::decode(&big.0[..big.1]),
+            Err(crate::CoapError::CapacityExceeded { capacity: 8 })
+        );
+    }
+
+    #[test]
+    fn options_out_of_order() {
+        let header = header::CoapHeader::new(
+            header::CoapHeaderType::Confirmable,
+            0,
+            header::CoapHeaderCode::GET,
+            1,
+        )
+        .unwrap();
+        let mut msg = message::CoapMessage::new(header, &[]);
+        msg.add_option(option::CoapOption::new(
+            option::CoapOptionNumbers::UriQuery,
+            b"x=1",
+        ))
+        .unwrap();
+        msg.add_option(option::CoapOption::new(
+            option::CoapOptionNumbers::UriPath,
+            b"a",
+        ))
+        .unwrap();
+        msg.add_option(option::CoapOption::new(
+            option::CoapOptionNumbers::UriPath,
+            b"b",
+        ))
+        .unwrap();
+        let en_msg = msg.encode().unwrap();
+        assert_eq!(
+            &en_msg.0[..en_msg.1],
+            &[0x40, 1, 0, 1, 0xb1, b'a', 0x01, b'b', 0x43, b'x', b'=', b'1']
+        );
+    }
+
+    #[test]
+    fn content_format_and_accept() {
+        let mut msg = message::CoapMessage::get().path("a").build().unwrap();
+        assert_eq!(msg.content_format(), None);
+        assert_eq!(msg.accept(), None);
+
+        msg.set_content_format(message::CoapMediaType::ApplicationSenmlCbor)
+            .unwrap();
+        msg.set_accept(message::CoapMediaType::Other(65000))
+            .unwrap();
+        msg.set_accept(message::CoapMediaType::TextPlain).unwrap();
+        assert_eq!(
+            msg.content_format(),
+            Some(message::CoapMediaType::ApplicationSenmlCbor)
+        );
+        assert_eq!(msg.accept(), Some(message::CoapMediaType::TextPlain));
+        assert_eq!(msg.get_options().len(), 3);
+
+        let en_msg = msg.encode().unwrap();
+        // Uri-Path "a", Content-Format 112 and an empty Accept for text/plain
+        assert_eq!(
+            &en_msg.0[..en_msg.1],
+            &[0x50, 1, 0, 0, 0xb1, b'a', 0x11, 112, 0x50]
+        );
+        let view = message::CoapMessageRef::decode(&en_msg.0[..en_msg.1]).unwrap();
+        assert_eq!(
+            view.content_format(),
+            Some(message::CoapMediaType::ApplicationSenmlCbor)
+        );
+        assert_eq!(view.accept(), Some(message::CoapMediaType::TextPlain));
+    }
 }
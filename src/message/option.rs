@@ -1,242 +1,546 @@
+//! CoAP options
+
 use crate::CoapError;
-use heapless::consts::*;
+use core::ops::RangeInclusive;
 use heapless::Vec;
 
+/// Default maximum number of options in a message
+pub const DEFAULT_MAX_OPTIONS: usize = 10;
+/// Default maximum length of an option value
+pub const DEFAULT_OPTION_SIZE: usize = 255;
+
+/// The set of options carried by a message.
+/// Holds up to `MAX_OPTIONS` options with values of up to `OPTION_SIZE` bytes
 #[derive(Clone, Debug, PartialEq)]
-pub struct CoapOptions {
-    pub options: Vec<CoapOption, U10>,
+pub struct CoapOptions<
+    const MAX_OPTIONS: usize = DEFAULT_MAX_OPTIONS,
+    const OPTION_SIZE: usize = DEFAULT_OPTION_SIZE,
+> {
+    pub(crate) options: Vec<CoapOption<OPTION_SIZE>, MAX_OPTIONS>,
     length: usize,
 }
 
 impl CoapOptions {
+    /// Creates a new empty set of options with the default capacities.
+    /// Use [`Default`] for other capacities
     pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<const MAX_OPTIONS: usize, const OPTION_SIZE: usize> Default
+    for CoapOptions<MAX_OPTIONS, OPTION_SIZE>
+{
+    fn default() -> Self {
         CoapOptions {
-            options: Vec::<CoapOption, U10>::new(),
+            options: Vec::new(),
             length: 0,
         }
     }
+}
+
+impl<const MAX_OPTIONS: usize, const OPTION_SIZE: usize> CoapOptions<MAX_OPTIONS, OPTION_SIZE> {
+    /// Returns the number of options
     pub fn len(&self) -> usize {
         self.length
     }
-    pub(crate) fn push(&mut self, option: CoapOption) -> Result<(), CoapError> {
-        match self.options.push(option) {
+    /// Returns true if there are no options
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+    /// Returns an iterator over the options
+    pub fn iter(&self) -> core::slice::Iter<'_, CoapOption<OPTION_SIZE>> {
+        self.options.iter()
+    }
+    /// Adds an option, keeping the set sorted by option number.
+    /// The option is placed after the options with the same number,
+    /// so repeated options like Uri-Path segments keep the order they were pushed in
+    pub fn push(&mut self, option: CoapOption<OPTION_SIZE>) -> Result<(), CoapError> {
+        let number = option.number();
+        let index = self
+            .options
+            .iter()
+            .position(|o| o.number() > number)
+            .unwrap_or(self.options.len());
+        self.insert_at(index, option)
+    }
+
+    /// Adds an option, keeping the set sorted by option number.
+    /// Unlike [`CoapOptions::push`] the option is placed in front of the options with the same number
+    pub fn insert(&mut self, option: CoapOption<OPTION_SIZE>) -> Result<(), CoapError> {
+        let number = option.number();
+        let index = self
+            .options
+            .iter()
+            .position(|o| o.number() >= number)
+            .unwrap_or(self.options.len());
+        self.insert_at(index, option)
+    }
+
+    /// Removes all options with the option number.
+    /// Returns the number of options removed
+    pub fn remove_all(&mut self, number: CoapOptionNumbers) -> usize {
+        let number = u16::from(number);
+        let before = self.options.len();
+        self.options.retain(|o| o.number() != number);
+        self.length = self.options.len();
+        before - self.length
+    }
+
+    /// Replaces all options with the option number by a single option with the value
+    pub fn replace(&mut self, number: CoapOptionNumbers, value: &[u8]) -> Result<(), CoapError> {
+        let option = CoapOption::try_new(number.clone(), value)?;
+        self.remove_all(number);
+        self.push(option)
+    }
+
+    fn insert_at(
+        &mut self,
+        index: usize,
+        option: CoapOption<OPTION_SIZE>,
+    ) -> Result<(), CoapError> {
+        match self.options.insert(index, option) {
             Ok(_) => {
                 self.length += 1;
                 Ok(())
             }
-            Err(_) => Err(CoapError::InternalServerError),
+            Err(_) => Err(CoapError::CapacityExceeded {
+                capacity: self.options.capacity(),
+            }),
         }
     }
 
-    pub(crate) fn decode(buf: &mut [u8]) -> Result<(Self, &[u8]), CoapError> {
-        let mut index: usize = 0;
-        let mut options: CoapOptions = CoapOptions::new();
-        let mut ret: &[u8] = buf;
-        let mut prev_option: u8 = 0;
-        if buf[index] != 0xff {
-            while index <= ret.len() && ret[0] != 0xff {
-                let d = ret[0] >> 4;
-                let l = ret[0] & 0xf;
-                let delta: (u16, usize) = match d {
-                    0..12 => (d as u16, 0),
-                    13 => (ret[1] as u16 + 13, 1),
-                    14 => (buf[index + 2] as u16 + 269, 2),
-                    _ => return Err(CoapError::MessageFormatError),
-                };
-                let length_bytes: u16 = match l {
-                    0..12 => l as u16,
-                    13 => (1 + ret[1 + delta.1] + 13) as u16,
-                    14 => {
-                        2 + (((ret[1 + delta.1] as u16) << 8u8) as u16 | ret[2 + delta.1] as u16)
-                            + 269
-                    }
-                    _ => return Err(CoapError::MessageFormatError),
-                };
-                let split_index = 1 + delta.1 + length_bytes as usize;
-                index += split_index;
-                let opt = ret.split_at(split_index);
-                let raw_option = opt.0;
-                ret = opt.1;
-                let option = CoapOption::decode(prev_option, raw_option)?;
-                prev_option = option.get_option_number().into();
-                options.push(option)?;
+    /// Decodes options from the buffer until the payload marker or the end of the buffer.
+    /// Returns the options together with the remaining bytes
+    pub fn decode(buf: &[u8]) -> Result<(Self, &[u8]), CoapError> {
+        let mut options = Self::default();
+        let mut rest: &[u8] = buf;
+        let mut prev_option: u16 = 0;
+        while !rest.is_empty() && rest[0] != 0xff {
+            let offset = buf.len() - rest.len();
+            let (_, length, header_length) =
+                decode_option_header(rest).map_err(|e| e.at_offset(offset))?;
+            let split_index = header_length + length;
+            if rest.len() < split_index {
+                return Err(CoapError::BadOptionLength { offset });
             }
-            Ok((options, ret))
-        } else {
-            Ok((options, buf))
+            let (raw_option, tail) = rest.split_at(split_index);
+            let option = CoapOption::<OPTION_SIZE>::decode(prev_option, raw_option)
+                .map_err(|e| e.at_offset(offset))?;
+            prev_option = option.get_option_number().into();
+            options.push(option)?;
+            rest = tail;
         }
+        Ok((options, rest))
     }
 }
 
+/// A single CoAP option, an option number together with its value of up to `OPTION_SIZE` bytes
 #[derive(Clone, Debug, PartialEq)]
-pub struct CoapOption {
+pub struct CoapOption<const OPTION_SIZE: usize = DEFAULT_OPTION_SIZE> {
     option: CoapOptionNumbers,
-    data: Vec<u8, U255>,
+    data: Vec<u8, OPTION_SIZE>,
 }
 
 impl CoapOption {
+    /// Creates a new option with the default value capacity from an option number and its raw value
+    ///
+    /// # Panics
+    /// Panics if the value is longer than the capacity, see [`CoapOption::try_new`]
     pub fn new(option: CoapOptionNumbers, data: &[u8]) -> Self {
-        let mut d: Vec<u8, U255> = Vec::new();
-        d.extend_from_slice(data).unwrap();
-        CoapOption { option, data: d }
+        match Self::try_new(option, data) {
+            Ok(option) => option,
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+impl<const OPTION_SIZE: usize> CoapOption<OPTION_SIZE> {
+    /// Creates a new option from an option number and its raw value.
+    /// Fails if the value is longer than `OPTION_SIZE` bytes
+    pub fn try_new(option: CoapOptionNumbers, data: &[u8]) -> Result<Self, CoapError> {
+        let d = Vec::from_slice(data).map_err(|_| CoapError::CapacityExceeded {
+            capacity: OPTION_SIZE,
+        })?;
+        Ok(CoapOption { option, data: d })
+    }
+
+    /// Creates an option with an empty value, for example If-None-Match
+    pub fn empty(option: CoapOptionNumbers) -> Result<Self, CoapError> {
+        Self::opaque(option, &[])
+    }
+
+    /// Creates an option with an unsigned integer value, encoded in as few bytes as possible
+    pub fn uint(option: CoapOptionNumbers, value: u32) -> Result<Self, CoapError> {
+        let (bytes, start) = encode_uint(value);
+        Self::opaque(option, &bytes[start..])
+    }
+
+    /// Creates an option with a string value
+    pub fn string(option: CoapOptionNumbers, value: &str) -> Result<Self, CoapError> {
+        Self::opaque(option, value.as_bytes())
     }
 
+    /// Creates an option with an opaque value.
+    /// Unlike [`CoapOption::try_new`] the length is checked against the option definition
+    pub fn opaque(option: CoapOptionNumbers, value: &[u8]) -> Result<Self, CoapError> {
+        check_length(&option, value.len())?;
+        Self::try_new(option, value)
+    }
+
+    /// Returns the option number
     pub fn get_option_number(&self) -> CoapOptionNumbers {
         self.option.clone()
     }
-    pub fn get_option_data(&self) -> Vec<u8, U255> {
+    /// Returns the option value as an unsigned integer.
+    /// Fails if the value is longer than 4 bytes
+    pub fn get_uint(&self) -> Result<u32, CoapError> {
+        decode_uint(self.number(), &self.data)
+    }
+    /// Returns the option value as a string.
+    /// Fails if the value is not valid UTF-8
+    pub fn get_str(&self) -> Result<&str, CoapError> {
+        decode_str(self.number(), &self.data)
+    }
+    /// Returns the raw option value
+    pub fn get_opaque(&self) -> &[u8] {
+        &self.data
+    }
+    /// Returns the option value
+    pub fn get_option_data(&self) -> Vec<u8, OPTION_SIZE> {
         self.data.clone()
     }
-    pub(crate) fn encode(
+
+    fn number(&self) -> u16 {
+        self.option.clone().into()
+    }
+    /// Encodes the option relative to the previous option number.
+    /// Returns the encoded buffer together with the number of bytes used
+    pub fn encode(&self, prev_option: CoapOptionNumbers) -> Result<([u8; 255], usize), CoapError> {
+        let mut v: [u8; 255] = [0; 255];
+        let length = self.encode_into(prev_option, &mut v)?;
+        Ok((v, length))
+    }
+
+    /// Returns the number of bytes needed to encode the option after the previous option number
+    pub fn encoded_len(&self, prev_option: CoapOptionNumbers) -> usize {
+        let o: u16 = self.option.clone().into();
+        let po: u16 = prev_option.into();
+        let option_delta = o.saturating_sub(po) as u32;
+        let option_length = self.data.len();
+        1 + extended_len(option_delta) + extended_len(option_length as u32) + option_length
+    }
+
+    /// Encodes the option relative to the previous option number directly into the buffer.
+    /// Returns the number of bytes written
+    pub fn encode_into(
         &self,
         prev_option: CoapOptionNumbers,
-    ) -> Result<([u8; 255], usize), CoapError> {
-        let mut v: [u8; 255] = [0; 255];
-        let o: u8 = self.option.clone().into();
-        let po: u8 = prev_option.into();
+        buf: &mut [u8],
+    ) -> Result<usize, CoapError> {
+        let o: u16 = self.option.clone().into();
+        let po: u16 = prev_option.clone().into();
         // Check so that we are encoding the options in order
         if po > o {
-            return Err(CoapError::BadOption);
+            return Err(CoapError::OptionsOutOfOrder);
         }
-        let option_delta = o - po;
-        let option_length = self.data.len() as u8;
-        // TODO: make the correct assumtion regarding available length, not just 254 bytes
-        if option_length > 254 {
-            return Err(CoapError::InternalServerError);
+        check_length(&self.option, self.data.len())?;
+        let length = self.encoded_len(prev_option);
+        if buf.len() < length {
+            return Err(CoapError::BufferTooSmall {
+                needed: length,
+                available: buf.len(),
+            });
         }
-        let mut byte_offset = 0;
-        match option_delta {
-            0..12 => v[0] = option_delta << 4,
-            13..255 => {
-                v[0] = 13 << 4;
-                v[1] = option_delta - 13;
-                byte_offset += 1;
-            }
-            _ => return Err(CoapError::BadOption),
+        let index = encode_option_header((o - po) as u32, self.data.len() as u32, buf)?;
+        buf[index..length].copy_from_slice(&self.data);
+        Ok(length)
+    }
+
+    /// Decodes a single option relative to the previous option number
+    pub fn decode(prev_option_number: u16, buf: &[u8]) -> Result<Self, CoapError> {
+        let (delta, length, index) = decode_option_header(buf)?;
+        let number = prev_option_number as u32 + delta;
+        if number > u16::MAX as u32 {
+            return Err(CoapError::BadOptionDelta { offset: 0 });
         }
+        let value = buf
+            .get(index..index + length)
+            .ok_or(CoapError::BadOptionLength { offset: 0 })?;
+        let number = CoapOptionNumbers::from(number as u16);
+        check_length(&number, length)?;
+        Self::try_new(number, value)
+    }
+}
 
-        match option_length {
-            0..12 => v[0] = v[0] | option_length,
-            13..255 => {
-                v[0] = v[0] | 13;
-                v[1] = option_length - 13;
-                byte_offset += 1;
-            }
-            _ => return Err(CoapError::BadOption),
+/// Largest option delta or length that can be encoded, 14 followed by two extended bytes
+pub(crate) const MAX_EXTENDED: u32 = 65804;
+
+/// Returns the number of extended bytes needed for an option delta or length, RFC 7252 §3.1
+fn extended_len(value: u32) -> usize {
+    match value {
+        0..=12 => 0,
+        13..=268 => 1,
+        _ => 2,
+    }
+}
+
+/// Writes the extended bytes of an option delta or length and returns its 4 bit nibble
+fn encode_extended(value: u32, buf: &mut [u8], index: &mut usize) -> u8 {
+    match value {
+        0..=12 => value as u8,
+        13..=268 => {
+            buf[*index] = (value - 13) as u8;
+            *index += 1;
+            13
         }
-        let mut index = 1 + byte_offset;
-        for i in &self.data {
-            v[index] = *i;
-            index = index + 1;
+        _ => {
+            let ext = (value - 269) as u16;
+            buf[*index..*index + 2].copy_from_slice(&ext.to_be_bytes());
+            *index += 2;
+            14
         }
-        let length = self.data.len() + 1 + byte_offset; // length of data + option length
-        Ok((v, length))
     }
+}
 
-    pub(crate) fn decode(prev_option_number: u8, buf: &[u8]) -> Result<CoapOption, CoapError> {
-        let d = buf[0] >> 4;
-        let mut byte_offset = 0;
-        let delta: u16 = match d {
-            0..12 => d as u16,
-            13 => {
-                byte_offset += 1;
-                (buf[1] + 13) as u16
-            }
-            14 => ((buf[1] as u16) << 8 | buf[2] as u16) + 269,
-            _ => return Err(CoapError::BadOption),
-        };
-        let option: CoapOptionNumbers = (prev_option_number as u16 + delta).into();
-        let l = buf[0] & 15;
-        let length: u16 = match l {
-            0..12 => l as u16,
-            13 => {
-                let len = buf[1 + byte_offset] as u16 + 13;
-                byte_offset += 1;
-                len
-            }
-            14 => {
-                let len = ((buf[1 + byte_offset] as u16) << 8 | buf[2 + byte_offset] as u16) + 269;
-                byte_offset += 2;
-                len
-            }
-            _ => return Err(CoapError::BadOption),
-        };
-
-        let mut data = Vec::<u8, U255>::new();
-        // Data
-        for i in (1 + byte_offset)..(length as usize + 1 + byte_offset) {
-            data.push(buf[i]).unwrap();
+/// Reads an option delta or length, including its extended bytes.
+/// The nibble 15 is reserved for the payload marker and is a format error,
+/// as are missing extended bytes
+fn decode_extended(nibble: u8, buf: &[u8], index: &mut usize) -> Option<u32> {
+    match nibble {
+        0..=12 => Some(nibble as u32),
+        13 => {
+            let ext = *buf.get(*index)?;
+            *index += 1;
+            Some(ext as u32 + 13)
+        }
+        14 => {
+            let ext = buf.get(*index..*index + 2)?;
+            *index += 2;
+            Some(((ext[0] as u32) << 8 | ext[1] as u32) + 269)
         }
-        Ok(CoapOption { option, data })
+        _ => None,
+    }
+}
+
+/// Writes an option header, the delta and length nibbles followed by their extended bytes.
+/// Returns the number of bytes written
+pub(crate) fn encode_option_header(
+    delta: u32,
+    length: u32,
+    buf: &mut [u8],
+) -> Result<usize, CoapError> {
+    if delta > MAX_EXTENDED {
+        return Err(CoapError::BadOptionDelta { offset: 0 });
     }
+    if length > MAX_EXTENDED {
+        return Err(CoapError::BadOptionLength { offset: 0 });
+    }
+    let header_length = 1 + extended_len(delta) + extended_len(length);
+    if buf.len() < header_length {
+        return Err(CoapError::BufferTooSmall {
+            needed: header_length,
+            available: buf.len(),
+        });
+    }
+    let mut index = 1;
+    let d = encode_extended(delta, buf, &mut index);
+    let l = encode_extended(length, buf, &mut index);
+    buf[0] = d << 4 | l;
+    Ok(index)
+}
+
+/// Reads the option header at the start of the buffer.
+/// Returns the option delta, the value length and the number of header bytes.
+/// Error offsets are 0, the start of the option
+pub(crate) fn decode_option_header(buf: &[u8]) -> Result<(u32, usize, usize), CoapError> {
+    let first = *buf.first().ok_or(CoapError::BadOptionDelta { offset: 0 })?;
+    let mut index = 1;
+    let delta = decode_extended(first >> 4, buf, &mut index)
+        .ok_or(CoapError::BadOptionDelta { offset: 0 })?;
+    let length = decode_extended(first & 0xf, buf, &mut index)
+        .ok_or(CoapError::BadOptionLength { offset: 0 })?;
+    Ok((delta, length as usize, index))
 }
 
+/// CoAP option numbers.
+/// Numbers without a named variant are kept as [`CoapOptionNumbers::Unknown`]
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum CoapOptionNumbers {
+    /// 0, reserved. Used as the starting point when delta encoding
     Zero,
+    /// 1 If-Match
     IfMatch,
+    /// 3 Uri-Host
     UriHost,
+    /// 4 ETag
     ETag,
+    /// 5 If-None-Match
     IfNoneMatch,
+    /// 6 Observe (RFC 7641)
+    Observe,
+    /// 7 Uri-Port
     UriPort,
+    /// 8 Location-Path
     LocationPath,
+    /// 9 OSCORE (RFC 8613)
+    Oscore,
+    /// 11 Uri-Path
     UriPath,
+    /// 12 Content-Format
     ContentFormat,
+    /// 14 Max-Age
     MaxAge,
+    /// 15 Uri-Query
     UriQuery,
+    /// 16 Hop-Limit (RFC 8768)
+    HopLimit,
+    /// 17 Accept
     Accept,
+    /// 20 Location-Query
     LocationQuery,
+    /// 23 Block2 (RFC 7959)
+    Block2,
+    /// 27 Block1 (RFC 7959)
+    Block1,
+    /// 28 Size2 (RFC 7959)
+    Size2,
+    /// 35 Proxy-Uri
     ProxyUri,
+    /// 39 Proxy-Scheme
     ProxyScheme,
+    /// 60 Size1
     Size1,
+    /// 252 Echo (RFC 9175)
+    Echo,
+    /// 258 No-Response (RFC 7967)
+    NoResponse,
+    /// 292 Request-Tag (RFC 9175)
+    RequestTag,
+    /// Any other option number.
+    /// Only used for numbers that do not have a named variant
+    Unknown(u16),
 }
 
-impl From<u8> for CoapOptionNumbers {
-    fn from(item: u8) -> Self {
-        match item {
-            0 => CoapOptionNumbers::Zero,
-            1 => CoapOptionNumbers::IfMatch,
-            3 => CoapOptionNumbers::UriHost,
-            4 => CoapOptionNumbers::ETag,
-            5 => CoapOptionNumbers::IfNoneMatch,
-            7 => CoapOptionNumbers::UriPort,
-            8 => CoapOptionNumbers::LocationPath,
-            11 => CoapOptionNumbers::UriPath,
-            12 => CoapOptionNumbers::ContentFormat,
-            14 => CoapOptionNumbers::MaxAge,
-            15 => CoapOptionNumbers::UriQuery,
-            17 => CoapOptionNumbers::Accept,
-            20 => CoapOptionNumbers::LocationQuery,
-            35 => CoapOptionNumbers::ProxyUri,
-            39 => CoapOptionNumbers::ProxyScheme,
-            60 => CoapOptionNumbers::Size1,
-            _ => unreachable!(), // TODO: Handle Reserved cases
+impl CoapOptionNumbers {
+    /// Returns true if the option is critical, RFC 7252 §5.4.1.
+    /// An unrecognized critical option must cause the message to be rejected
+    pub fn is_critical(&self) -> bool {
+        u16::from(self.clone()) & 0x01 != 0
+    }
+
+    /// Returns true if the option is elective, the opposite of critical
+    pub fn is_elective(&self) -> bool {
+        !self.is_critical()
+    }
+
+    /// Returns true if the option is unsafe to forward, RFC 7252 §5.4.2.
+    /// A proxy that does not understand it must not forward the message
+    pub fn is_unsafe(&self) -> bool {
+        u16::from(self.clone()) & 0x02 != 0
+    }
+
+    /// Returns true if the option is not part of the cache key, RFC 7252 §5.4.6.
+    /// Only meaningful for options that are safe to forward
+    pub fn is_no_cache_key(&self) -> bool {
+        u16::from(self.clone()) & 0x1e == 0x1c
+    }
+
+    /// Returns true if the option number is known to this crate
+    pub fn is_recognized(&self) -> bool {
+        !matches!(self, CoapOptionNumbers::Unknown(_))
+    }
+
+    /// Returns the value format of the option, RFC 7252 §3.2.
+    /// Unknown options are treated as opaque
+    pub fn format(&self) -> CoapOptionFormat {
+        use CoapOptionNumbers::*;
+        match self {
+            IfNoneMatch => CoapOptionFormat::Empty,
+            Observe | UriPort | ContentFormat | MaxAge | HopLimit | Accept | Block2 | Block1
+            | Size2 | Size1 | NoResponse => CoapOptionFormat::Uint,
+            UriHost | LocationPath | UriPath | UriQuery | LocationQuery | ProxyUri
+            | ProxyScheme => CoapOptionFormat::String,
+            Zero | IfMatch | ETag | Oscore | Echo | RequestTag | Unknown(_) => {
+                CoapOptionFormat::Opaque
+            }
         }
     }
-}
-impl From<CoapOptionNumbers> for u8 {
-    fn from(item: CoapOptionNumbers) -> Self {
-        match item {
-            CoapOptionNumbers::Zero => 0,
-            CoapOptionNumbers::IfMatch => 1,
-            CoapOptionNumbers::UriHost => 3,
-            CoapOptionNumbers::ETag => 4,
-            CoapOptionNumbers::IfNoneMatch => 5,
-            CoapOptionNumbers::UriPort => 7,
-            CoapOptionNumbers::LocationPath => 8,
-            CoapOptionNumbers::UriPath => 11,
-            CoapOptionNumbers::ContentFormat => 12,
-            CoapOptionNumbers::MaxAge => 14,
-            CoapOptionNumbers::UriQuery => 15,
-            CoapOptionNumbers::Accept => 17,
-            CoapOptionNumbers::LocationQuery => 20,
-            CoapOptionNumbers::ProxyUri => 35,
-            CoapOptionNumbers::ProxyScheme => 39,
-            CoapOptionNumbers::Size1 => 60,
+
+    /// Returns the minimum and maximum value length of the option, RFC 7252 §5.10.
+    /// Unknown options are not limited
+    pub fn length_range(&self) -> RangeInclusive<usize> {
+        use CoapOptionNumbers::*;
+        match self {
+            IfNoneMatch => 0..=0,
+            NoResponse => 0..=1,
+            HopLimit => 1..=1,
+            UriPort | ContentFormat | Accept => 0..=2,
+            Observe | Block2 | Block1 => 0..=3,
+            MaxAge | Size2 | Size1 => 0..=4,
+            IfMatch | RequestTag => 0..=8,
+            ETag => 1..=8,
+            Echo => 1..=40,
+            UriHost | ProxyScheme => 1..=255,
+            LocationPath | UriPath | UriQuery | LocationQuery | Oscore => 0..=255,
+            ProxyUri => 1..=1034,
+            Zero | Unknown(_) => 0..=MAX_EXTENDED as usize,
         }
     }
 }
 
+/// Option value formats, RFC 7252 §3.2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoapOptionFormat {
+    /// Zero-length value
+    Empty,
+    /// Opaque sequence of bytes
+    Opaque,
+    /// Non-negative integer in network byte order, using as few bytes as possible
+    Uint,
+    /// UTF-8 string
+    String,
+}
+
+/// Encodes a uint option value in as few bytes as possible.
+/// Returns the buffer together with the index the value starts at, 0 is encoded as no bytes
+pub(crate) fn encode_uint(value: u32) -> ([u8; 4], usize) {
+    let bytes = value.to_be_bytes();
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(4);
+    (bytes, start)
+}
+
+/// Decodes a uint option value, leading zero bytes are allowed
+pub(crate) fn decode_uint(number: u16, data: &[u8]) -> Result<u32, CoapError> {
+    if data.len() > 4 {
+        return Err(CoapError::InvalidOptionValue(number));
+    }
+    Ok(data.iter().fold(0, |value, b| value << 8 | *b as u32))
+}
+
+/// Decodes a string option value
+pub(crate) fn decode_str(number: u16, data: &[u8]) -> Result<&str, CoapError> {
+    core::str::from_utf8(data).map_err(|_| CoapError::InvalidOptionValue(number))
+}
+
+/// Checks the value length against the option definition
+pub(crate) fn check_length(number: &CoapOptionNumbers, length: usize) -> Result<(), CoapError> {
+    if number.length_range().contains(&length) {
+        Ok(())
+    } else {
+        Err(CoapError::OptionLengthOutOfRange {
+            number: number.clone().into(),
+            length,
+        })
+    }
+}
+
+impl From<u8> for CoapOptionNumbers {
+    fn from(item: u8) -> Self {
+        CoapOptionNumbers::from(item as u16)
+    }
+}
+
 impl From<u16> for CoapOptionNumbers {
     fn from(item: u16) -> Self {
         match item {
@@ -245,18 +549,27 @@ impl From<u16> for CoapOptionNumbers {
             3 => CoapOptionNumbers::UriHost,
             4 => CoapOptionNumbers::ETag,
             5 => CoapOptionNumbers::IfNoneMatch,
+            6 => CoapOptionNumbers::Observe,
             7 => CoapOptionNumbers::UriPort,
             8 => CoapOptionNumbers::LocationPath,
+            9 => CoapOptionNumbers::Oscore,
             11 => CoapOptionNumbers::UriPath,
             12 => CoapOptionNumbers::ContentFormat,
             14 => CoapOptionNumbers::MaxAge,
             15 => CoapOptionNumbers::UriQuery,
+            16 => CoapOptionNumbers::HopLimit,
             17 => CoapOptionNumbers::Accept,
             20 => CoapOptionNumbers::LocationQuery,
+            23 => CoapOptionNumbers::Block2,
+            27 => CoapOptionNumbers::Block1,
+            28 => CoapOptionNumbers::Size2,
             35 => CoapOptionNumbers::ProxyUri,
             39 => CoapOptionNumbers::ProxyScheme,
             60 => CoapOptionNumbers::Size1,
-            _ => unreachable!(), // TODO: Handle Reserved cases
+            252 => CoapOptionNumbers::Echo,
+            258 => CoapOptionNumbers::NoResponse,
+            292 => CoapOptionNumbers::RequestTag,
+            _ => CoapOptionNumbers::Unknown(item),
         }
     }
 }
@@ -268,17 +581,27 @@ impl From<CoapOptionNumbers> for u16 {
             CoapOptionNumbers::UriHost => 3,
             CoapOptionNumbers::ETag => 4,
             CoapOptionNumbers::IfNoneMatch => 5,
+            CoapOptionNumbers::Observe => 6,
             CoapOptionNumbers::UriPort => 7,
             CoapOptionNumbers::LocationPath => 8,
+            CoapOptionNumbers::Oscore => 9,
             CoapOptionNumbers::UriPath => 11,
             CoapOptionNumbers::ContentFormat => 12,
             CoapOptionNumbers::MaxAge => 14,
             CoapOptionNumbers::UriQuery => 15,
+            CoapOptionNumbers::HopLimit => 16,
             CoapOptionNumbers::Accept => 17,
             CoapOptionNumbers::LocationQuery => 20,
+            CoapOptionNumbers::Block2 => 23,
+            CoapOptionNumbers::Block1 => 27,
+            CoapOptionNumbers::Size2 => 28,
             CoapOptionNumbers::ProxyUri => 35,
             CoapOptionNumbers::ProxyScheme => 39,
             CoapOptionNumbers::Size1 => 60,
+            CoapOptionNumbers::Echo => 252,
+            CoapOptionNumbers::NoResponse => 258,
+            CoapOptionNumbers::RequestTag => 292,
+            CoapOptionNumbers::Unknown(number) => number,
         }
     }
 }
@@ -289,7 +612,8 @@ mod tests {
 
     #[test]
     fn decode() {
-        let option = CoapOption::decode(0, &[((3 << 4) | 5), 10, 11, 12, 13, 14]).unwrap();
+        let option: CoapOption =
+            CoapOption::decode(0, &[((3 << 4) | 5), 10, 11, 12, 13, 14]).unwrap();
         assert_eq!(option.option, CoapOptionNumbers::UriHost);
         assert_eq!(option.data[0], 10);
         assert_eq!(option.data[1], 11);
@@ -299,10 +623,10 @@ mod tests {
     }
     #[test]
     fn decode_previous_option() {
-        let option = CoapOption::decode(3, &[((2 << 4) | 2), 10, 11]).unwrap();
-        assert_eq!(option.option, CoapOptionNumbers::IfNoneMatch);
+        let option: CoapOption = CoapOption::decode(3, &[((4 << 4) | 2), 10, 11]).unwrap();
+        assert_eq!(option.option, CoapOptionNumbers::UriPort);
         assert_eq!(option.data[0], 10);
-        //assert_eq!(option.data[2], 11);
+        assert_eq!(option.data[1], 11);
     }
 
     #[test]
@@ -342,11 +666,11 @@ mod tests {
     #[test]
     fn encode_decode_option() {
         let data = [1, 2, 3, 4, 5];
-        let vec_data: Vec<u8, U5> = Vec::from_slice(&data).unwrap();
+        let vec_data: Vec<u8, 5> = Vec::from_slice(&data).unwrap();
         let en_option = CoapOption::new(CoapOptionNumbers::UriHost, &data)
             .encode(CoapOptionNumbers::Zero)
             .unwrap();
-        let de_option = CoapOption::decode(0, &en_option.0).unwrap();
+        let de_option: CoapOption = CoapOption::decode(0, &en_option.0).unwrap();
 
         assert_eq!(de_option.option, CoapOptionNumbers::UriHost);
         assert_eq!(de_option.data, vec_data);
@@ -354,12 +678,12 @@ mod tests {
     #[test]
     fn encode_decode_previous_option() {
         let data = [1, 2, 3, 4, 5];
-        let vec_data: Vec<u8, U5> = Vec::from_slice(&data).unwrap();
+        let vec_data: Vec<u8, 5> = Vec::from_slice(&data).unwrap();
         let en_option = CoapOption::new(CoapOptionNumbers::UriHost, &data)
             .encode(CoapOptionNumbers::IfMatch)
             .unwrap();
-        let de_option =
-            CoapOption::decode(u8::from(CoapOptionNumbers::IfMatch), &en_option.0).unwrap();
+        let de_option: CoapOption =
+            CoapOption::decode(u16::from(CoapOptionNumbers::IfMatch), &en_option.0).unwrap();
 
         assert_eq!(de_option.option, CoapOptionNumbers::UriHost);
         assert_eq!(de_option.data, vec_data);
@@ -368,14 +692,263 @@ mod tests {
     #[test]
     fn encode_decode_previous_option_uripath() {
         let data = "test".as_bytes();
-        let vec_data: Vec<u8, U5> = Vec::from_slice(&data).unwrap();
-        let en_option = CoapOption::new(CoapOptionNumbers::UriPath, &data)
+        let vec_data: Vec<u8, 5> = Vec::from_slice(data).unwrap();
+        let en_option = CoapOption::new(CoapOptionNumbers::UriPath, data)
             .encode(CoapOptionNumbers::IfMatch)
             .unwrap();
-        let de_option =
-            CoapOption::decode(u8::from(CoapOptionNumbers::IfMatch), &en_option.0).unwrap();
+        let de_option: CoapOption =
+            CoapOption::decode(u16::from(CoapOptionNumbers::IfMatch), &en_option.0).unwrap();
 
         assert_eq!(de_option.option, CoapOptionNumbers::UriPath);
         assert_eq!(de_option.data, vec_data);
     }
+
+    #[test]
+    fn option_number_classification() {
+        // RFC 7252 §5.10 table and RFC 7641/7959
+        assert!(CoapOptionNumbers::UriPath.is_critical());
+        assert!(CoapOptionNumbers::UriPath.is_unsafe());
+        assert!(CoapOptionNumbers::IfMatch.is_critical());
+        assert!(!CoapOptionNumbers::IfMatch.is_unsafe());
+        assert!(CoapOptionNumbers::ContentFormat.is_elective());
+        assert!(!CoapOptionNumbers::ContentFormat.is_no_cache_key());
+        assert!(CoapOptionNumbers::Size1.is_no_cache_key());
+        assert!(CoapOptionNumbers::Size2.is_no_cache_key());
+        assert!(CoapOptionNumbers::Observe.is_elective());
+        assert!(CoapOptionNumbers::Observe.is_unsafe());
+        assert!(CoapOptionNumbers::Unknown(1001).is_critical());
+        assert!(CoapOptionNumbers::Unknown(1000).is_elective());
+    }
+
+    #[test]
+    fn option_number_conversion() {
+        for number in 0..=u16::MAX {
+            assert_eq!(u16::from(CoapOptionNumbers::from(number)), number);
+        }
+        assert_eq!(
+            CoapOptionNumbers::from(258u16),
+            CoapOptionNumbers::NoResponse
+        );
+        assert_eq!(CoapOptionNumbers::from(2u16), CoapOptionNumbers::Unknown(2));
+        assert!(!CoapOptionNumbers::Unknown(2).is_recognized());
+        assert!(CoapOptionNumbers::UriPath.is_recognized());
+    }
+
+    fn round_trip(number: u16, prev: CoapOptionNumbers, data: &[u8]) {
+        let option = CoapOption::new(CoapOptionNumbers::from(number), data);
+        let mut buf = [0u8; 300];
+        let length = option.encode_into(prev.clone(), &mut buf).unwrap();
+        assert_eq!(length, option.encoded_len(prev.clone()));
+        let de_option: CoapOption = CoapOption::decode(u16::from(prev), &buf[..length]).unwrap();
+        assert_eq!(de_option, option);
+    }
+
+    #[test]
+    fn encode_decode_delta_boundaries() {
+        for number in [12, 13, 268, 269, 270, 1000, u16::MAX].iter() {
+            round_trip(*number, CoapOptionNumbers::Zero, b"x");
+        }
+        // Echo 252 after Uri-Path, No-Response 258 after Echo
+        round_trip(252, CoapOptionNumbers::UriPath, b"echo");
+        round_trip(258, CoapOptionNumbers::Echo, &[]);
+        round_trip(258, CoapOptionNumbers::Zero, &[2]);
+    }
+
+    #[test]
+    fn encode_decode_length_boundaries() {
+        let data = [0xa5; 255];
+        for length in [0, 12, 13, 14, 254, 255].iter() {
+            round_trip(11, CoapOptionNumbers::Zero, &data[..*length]);
+            round_trip(1000, CoapOptionNumbers::Zero, &data[..*length]);
+        }
+    }
+
+    #[test]
+    fn option_header_boundaries() {
+        let boundaries = [(0, 1), (12, 1), (13, 2), (268, 2), (269, 3), (65804, 3)];
+        for (delta, delta_length) in boundaries.iter() {
+            for (length, length_length) in boundaries.iter() {
+                let mut buf = [0u8; 5];
+                let header_length = encode_option_header(*delta, *length, &mut buf).unwrap();
+                assert_eq!(header_length, delta_length + length_length - 1);
+                let decoded = decode_option_header(&buf[..header_length]).unwrap();
+                assert_eq!(decoded, (*delta, *length as usize, header_length));
+            }
+        }
+        let mut buf = [0u8; 5];
+        assert!(encode_option_header(65805, 0, &mut buf).is_err());
+        assert!(encode_option_header(0, 65805, &mut buf).is_err());
+    }
+
+    #[test]
+    fn decode_reserved_nibble() {
+        assert!(decode_option_header(&[0xf0]).is_err());
+        assert!(decode_option_header(&[0x0f]).is_err());
+        assert!(decode_option_header(&[0xd0]).is_err());
+        assert!(decode_option_header(&[0x0e, 1]).is_err());
+        assert!(CoapOption::<255>::decode(0, &[0xf1, 0]).is_err());
+    }
+
+    #[test]
+    fn decode_options_extended() {
+        // Uri-Path "a", then 1000 with a 13 byte value, then 1001 empty, then the payload
+        let mut buf = [0u8; 32];
+        buf[..2].copy_from_slice(&[0xb1, b'a']);
+        buf[2..6].copy_from_slice(&[0xed, 0x02, 0xd0, 0]);
+        buf[6..19].copy_from_slice(&[7; 13]);
+        buf[19..22].copy_from_slice(&[0x10, 0xff, 1]);
+        let (options, rest): (CoapOptions, _) = CoapOptions::decode(&buf[..22]).unwrap();
+        let mut iter = options.iter();
+        assert_eq!(
+            iter.next().unwrap().get_option_number(),
+            CoapOptionNumbers::UriPath
+        );
+        let opt = iter.next().unwrap();
+        assert_eq!(opt.get_option_number(), CoapOptionNumbers::Unknown(1000));
+        assert_eq!(&opt.get_option_data()[..], &[7; 13]);
+        let opt = iter.next().unwrap();
+        assert_eq!(opt.get_option_number(), CoapOptionNumbers::Unknown(1001));
+        assert_eq!(rest, &[0xff, 1]);
+    }
+
+    fn numbers_and_data(options: &CoapOptions) -> Vec<(u16, u8), 10> {
+        options
+            .iter()
+            .map(|o| (u16::from(o.get_option_number()), o.get_option_data()[0]))
+            .collect()
+    }
+
+    #[test]
+    fn push_keeps_order() {
+        let mut options = CoapOptions::new();
+        options
+            .push(CoapOption::new(CoapOptionNumbers::UriQuery, b"q"))
+            .unwrap();
+        options
+            .push(CoapOption::new(CoapOptionNumbers::UriPath, b"a"))
+            .unwrap();
+        options
+            .push(CoapOption::new(CoapOptionNumbers::UriHost, b"h"))
+            .unwrap();
+        options
+            .push(CoapOption::new(CoapOptionNumbers::UriPath, b"b"))
+            .unwrap();
+        assert_eq!(
+            &numbers_and_data(&options)[..],
+            &[(3, b'h'), (11, b'a'), (11, b'b'), (15, b'q')]
+        );
+        assert_eq!(options.len(), 4);
+    }
+
+    #[test]
+    fn insert_remove_replace() {
+        let mut options = CoapOptions::new();
+        options
+            .push(CoapOption::new(CoapOptionNumbers::UriPath, b"b"))
+            .unwrap();
+        options
+            .push(CoapOption::new(CoapOptionNumbers::UriQuery, b"q"))
+            .unwrap();
+        options
+            .insert(CoapOption::new(CoapOptionNumbers::UriPath, b"a"))
+            .unwrap();
+        assert_eq!(
+            &numbers_and_data(&options)[..],
+            &[(11, b'a'), (11, b'b'), (15, b'q')]
+        );
+
+        options.replace(CoapOptionNumbers::UriPath, b"c").unwrap();
+        assert_eq!(&numbers_and_data(&options)[..], &[(11, b'c'), (15, b'q')]);
+
+        options
+            .replace(CoapOptionNumbers::ContentFormat, &[0])
+            .unwrap();
+        assert_eq!(
+            &numbers_and_data(&options)[..],
+            &[(11, b'c'), (12, 0), (15, b'q')]
+        );
+
+        assert_eq!(options.remove_all(CoapOptionNumbers::UriQuery), 1);
+        assert_eq!(options.remove_all(CoapOptionNumbers::UriQuery), 0);
+        assert_eq!(options.len(), 2);
+
+        // A value that does not fit leaves the options untouched
+        let mut small: CoapOptions<10, 1> = CoapOptions::default();
+        small
+            .push(CoapOption::try_new(CoapOptionNumbers::UriPath, b"a").unwrap())
+            .unwrap();
+        assert!(small.replace(CoapOptionNumbers::UriPath, b"ab").is_err());
+        assert_eq!(small.len(), 1);
+    }
+
+    #[test]
+    fn typed_values() {
+        let option: CoapOption = CoapOption::uint(CoapOptionNumbers::MaxAge, 0).unwrap();
+        assert_eq!(option.get_opaque(), &[]);
+        assert_eq!(option.get_uint(), Ok(0));
+        let option: CoapOption = CoapOption::uint(CoapOptionNumbers::MaxAge, 255).unwrap();
+        assert_eq!(option.get_opaque(), &[255]);
+        let option: CoapOption = CoapOption::uint(CoapOptionNumbers::MaxAge, 256).unwrap();
+        assert_eq!(option.get_opaque(), &[1, 0]);
+        let option: CoapOption = CoapOption::uint(CoapOptionNumbers::MaxAge, u32::MAX).unwrap();
+        assert_eq!(option.get_uint(), Ok(u32::MAX));
+        // Leading zeros are accepted
+        let option: CoapOption = CoapOption::new(CoapOptionNumbers::MaxAge, &[0, 0, 1]);
+        assert_eq!(option.get_uint(), Ok(1));
+        let option: CoapOption = CoapOption::new(CoapOptionNumbers::MaxAge, &[1; 5]);
+        assert_eq!(option.get_uint(), Err(CoapError::InvalidOptionValue(14)));
+
+        let option: CoapOption = CoapOption::string(CoapOptionNumbers::UriPath, "temp").unwrap();
+        assert_eq!(option.get_str(), Ok("temp"));
+        let option: CoapOption = CoapOption::new(CoapOptionNumbers::UriPath, &[0xff]);
+        assert_eq!(option.get_str(), Err(CoapError::InvalidOptionValue(11)));
+
+        let option: CoapOption = CoapOption::empty(CoapOptionNumbers::IfNoneMatch).unwrap();
+        assert!(option.get_opaque().is_empty());
+        assert_eq!(
+            CoapOptionNumbers::IfNoneMatch.format(),
+            CoapOptionFormat::Empty
+        );
+        assert_eq!(CoapOptionNumbers::Accept.format(), CoapOptionFormat::Uint);
+        assert_eq!(CoapOptionNumbers::ETag.format(), CoapOptionFormat::Opaque);
+        assert_eq!(
+            CoapOptionNumbers::ProxyUri.format(),
+            CoapOptionFormat::String
+        );
+    }
+
+    #[test]
+    fn option_length_limits() {
+        let out_of_range =
+            |number: u16, length| Err(CoapError::OptionLengthOutOfRange { number, length });
+        assert_eq!(
+            CoapOption::<255>::uint(CoapOptionNumbers::ContentFormat, 0x10000),
+            out_of_range(12, 3)
+        );
+        assert_eq!(
+            CoapOption::<255>::string(CoapOptionNumbers::UriHost, ""),
+            out_of_range(3, 0)
+        );
+        assert_eq!(
+            CoapOption::<255>::opaque(CoapOptionNumbers::ETag, &[0; 9]),
+            out_of_range(4, 9)
+        );
+        assert_eq!(
+            CoapOption::<255>::opaque(CoapOptionNumbers::IfNoneMatch, &[0]),
+            out_of_range(5, 1)
+        );
+        assert!(CoapOption::<255>::opaque(CoapOptionNumbers::Unknown(1000), &[0; 255]).is_ok());
+
+        // Raw options are checked when they are encoded
+        let option = CoapOption::new(CoapOptionNumbers::ETag, &[]);
+        assert_eq!(
+            option.encode(CoapOptionNumbers::Zero),
+            Err(CoapError::OptionLengthOutOfRange {
+                number: 4,
+                length: 0
+            })
+        );
+        // and when they are decoded
+        assert_eq!(CoapOption::<255>::decode(0, &[0x40]), out_of_range(4, 0));
+    }
 }
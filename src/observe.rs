@@ -0,0 +1,239 @@
+//! Observing resources, RFC 7641.
+//!
+//! A client registers as an observer of a resource with a GET or FETCH request carrying
+//! Observe 0 and deregisters with Observe 1. Only resources whose
+//! [`crate::CoapLinkAttributes`] are marked `obs` can be observed.
+//! The application calls [`crate::CoapServer::notify`] with the path of a resource when
+//! its state changes, [`crate::CoapServer::poll`] then runs the registration request of
+//! every observer of that path through the handler again and returns the notifications.
+//! Observers keep their encoded registration request, a request too long to keep
+//! is answered without registering its client.
+//! Observers that reset a notification or do not acknowledge a confirmable one are removed.
+//!
+//! Clients observe resources with a [`crate::CoapClient`].
+
+use crate::message::header::CoapHeaderType;
+use crate::message::{self, CoapMessageRef};
+use crate::router;
+use core::net::SocketAddr;
+use heapless::Vec;
+
+/// Default maximum number of observers of a server
+pub const DEFAULT_MAX_OBSERVERS: usize = 4;
+
+/// Default maximum number of observers of one resource
+pub const DEFAULT_MAX_RESOURCE_OBSERVERS: usize = 2;
+
+/// Observe sequence numbers are 24 bits long, RFC 7641 §4.4
+pub(crate) const SEQUENCE_MASK: u32 = 0xff_ffff;
+
+/// Time after which a notification is fresher than the one before it, whatever its
+/// sequence number, in milliseconds, RFC 7641 §3.4
+pub(crate) const FRESHNESS_WINDOW: u64 = 128_000;
+
+/// Returns true if the notification with the sequence number `v2` received at `t2`
+/// is newer than the one with `v1` received at `t1`, RFC 7641 §3.4
+pub(crate) fn is_fresh(v1: u32, t1: u64, v2: u32, t2: u64) -> bool {
+    (v1 < v2 && v2 - v1 < 1 << 23) || (v1 > v2 && v1 - v2 > 1 << 23) || t2 > t1 + FRESHNESS_WINDOW
+}
+
+/// A client observing a resource
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Observer<const SIZE: usize> {
+    pub(crate) peer: Option<SocketAddr>,
+    /// The type of the registration request, used for the notifications
+    pub(crate) t: CoapHeaderType,
+    /// The encoded registration request, passed to the handler for every notification
+    request: Vec<u8, SIZE>,
+    /// True if the resource changed since the last notification
+    pub(crate) pending: bool,
+    /// The message id of the last notification, to match a reset
+    pub(crate) message_id: Option<u16>,
+}
+
+impl<const SIZE: usize> Observer<SIZE> {
+    /// Creates an observer registered by the encoded request
+    pub(crate) fn new(peer: Option<SocketAddr>, t: CoapHeaderType, request: Vec<u8, SIZE>) -> Self {
+        Observer {
+            peer,
+            t,
+            request,
+            pending: false,
+            message_id: None,
+        }
+    }
+
+    /// Returns the encoded registration request
+    pub(crate) fn get_request(&self) -> &[u8] {
+        &self.request
+    }
+
+    /// Returns the token of the registration request
+    pub(crate) fn get_token(&self) -> &[u8] {
+        message::encoded_token(&self.request)
+    }
+
+    /// Returns true if the observer registered from the peer with the token
+    pub(crate) fn is(&self, peer: Option<SocketAddr>, token: &[u8]) -> bool {
+        self.peer == peer && self.get_token() == token
+    }
+
+    /// Returns true if the path of the registration request matches the path template
+    fn observes(&self, path: &str) -> bool {
+        CoapMessageRef::decode(&self.request)
+            .is_ok_and(|request| router::match_path(path, &request).is_some())
+    }
+}
+
+/// The observers of the resources of a server, up to `MAX_OBSERVERS` in all and
+/// up to `MAX_RESOURCE_OBSERVERS` of one resource
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Observations<
+    const SIZE: usize,
+    const MAX_OBSERVERS: usize,
+    const MAX_RESOURCE_OBSERVERS: usize,
+> {
+    observers: Vec<Observer<SIZE>, MAX_OBSERVERS>,
+}
+
+impl<const SIZE: usize, const MAX_OBSERVERS: usize, const MAX_RESOURCE_OBSERVERS: usize>
+    Observations<SIZE, MAX_OBSERVERS, MAX_RESOURCE_OBSERVERS>
+{
+    /// Adds an observer of the resource with the path template, replacing the registration
+    /// with the same peer and token. Returns false if there is no room for it
+    pub(crate) fn register(&mut self, observer: Observer<SIZE>, path: &str) -> bool {
+        self.remove(observer.peer, observer.get_token());
+        let observers = self.observers.iter().filter(|o| o.observes(path)).count();
+        observers < MAX_RESOURCE_OBSERVERS && self.observers.push(observer).is_ok()
+    }
+
+    /// Removes the observer registered from the peer with the token.
+    /// Returns true if there was one
+    pub(crate) fn remove(&mut self, peer: Option<SocketAddr>, token: &[u8]) -> bool {
+        let count = self.len();
+        self.observers.retain(|observer| !observer.is(peer, token));
+        self.len() < count
+    }
+
+    /// Removes the observer whose last notification is reset by the message
+    pub(crate) fn remove_reset(&mut self, peer: Option<SocketAddr>, message_id: u16) {
+        self.observers
+            .retain(|observer| observer.peer != peer || observer.message_id != Some(message_id));
+    }
+
+    /// Marks the observers whose request path matches the path template to be notified,
+    /// see [`router`]. Returns the number of observers
+    pub(crate) fn notify(&mut self, path: &str) -> usize {
+        let mut count = 0;
+        for observer in self.observers.iter_mut().filter(|o| o.observes(path)) {
+            observer.pending = true;
+            count += 1;
+        }
+        count
+    }
+
+    /// Returns the observer registered from the peer with the token
+    pub(crate) fn find_mut(
+        &mut self,
+        peer: Option<SocketAddr>,
+        token: &[u8],
+    ) -> Option<&mut Observer<SIZE>> {
+        self.observers
+            .iter_mut()
+            .find(|observer| observer.is(peer, token))
+    }
+
+    /// Returns the first observer to be notified that is ready for it
+    pub(crate) fn next_pending<F>(&mut self, ready: F) -> Option<&mut Observer<SIZE>>
+    where
+        F: Fn(&Observer<SIZE>) -> bool,
+    {
+        self.observers
+            .iter_mut()
+            .find(|observer| observer.pending && ready(observer))
+    }
+
+    /// Returns the number of observers
+    pub(crate) fn len(&self) -> usize {
+        self.observers.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::CoapMessage;
+    use crate::observe::*;
+
+    fn observer(path: &str, token: u8) -> Observer<32> {
+        let request: CoapMessage = CoapMessage::get()
+            .path(path)
+            .token(&[token])
+            .build()
+            .unwrap();
+        let mut buffer = [0; 32];
+        let length = request.encode_into(&mut buffer).unwrap();
+        let request = Vec::from_slice(&buffer[..length]).unwrap();
+        Observer::new(None, CoapHeaderType::NonConfirmable, request)
+    }
+
+    #[test]
+    fn freshness() {
+        assert!(is_fresh(1, 0, 2, 0));
+        assert!(!is_fresh(2, 0, 1, 0));
+        assert!(!is_fresh(2, 0, 2, 0));
+        // The 24 bit sequence number wraps around
+        assert!(is_fresh(SEQUENCE_MASK, 0, 0, 0));
+        assert!(!is_fresh(0, 0, 1 << 23, 0));
+        // Anything is fresh after 128 seconds
+        assert!(!is_fresh(2, 0, 1, FRESHNESS_WINDOW));
+        assert!(is_fresh(2, 0, 1, FRESHNESS_WINDOW + 1));
+    }
+
+    #[test]
+    fn observations() {
+        let mut observations: Observations<32, 3, 2> = Observations::default();
+        assert!(observations.register(observer("temp", 1), "temp"));
+        assert!(observations.register(observer("temp", 2), "temp"));
+        // A registration with the same token replaces the one before
+        assert!(observations.register(observer("temp", 2), "temp"));
+        // Room for two observers of a resource
+        assert!(!observations.register(observer("temp", 5), "temp"));
+        assert!(observations.register(observer("hum", 3), "hum"));
+        // Room for three observers
+        assert!(!observations.register(observer("light", 4), "light"));
+        assert_eq!(observations.len(), 3);
+
+        assert_eq!(observations.notify("temp"), 2);
+        assert_eq!(observations.notify("light"), 0);
+        let next = observations.next_pending(|o| o.get_token() != [1]);
+        assert_eq!(next.map(|o| o.get_token()[0]), Some(2));
+
+        observations.find_mut(None, &[3]).unwrap().message_id = Some(9);
+        observations.remove_reset(None, 8);
+        assert_eq!(observations.len(), 3);
+        observations.remove_reset(None, 9);
+        assert_eq!(observations.len(), 2);
+        assert!(observations.register(observer("light", 4), "light"));
+
+        assert!(observations.remove(None, &[1]));
+        assert!(!observations.remove(None, &[1]));
+        assert_eq!(observations.len(), 2);
+    }
+
+    #[test]
+    fn notify_paths() {
+        let mut observations: Observations<32, 4, 3> = Observations::default();
+        let template = "relays/{id}/state";
+        observations.register(observer("relays/1/state", 1), template);
+        observations.register(observer("relays/2/state", 2), template);
+        observations.register(observer("relays/2/state", 3), template);
+        // The observers of all paths of a template count against the one resource
+        assert!(!observations.register(observer("relays/3/state", 4), template));
+        assert!(observations.register(observer("relays/3/state", 4), "relays/3/state"));
+        // A concrete path notifies the observers of one resource, a template all of them
+        assert_eq!(observations.notify("relays/2/state"), 2);
+        assert_eq!(observations.notify("relays/1/state"), 1);
+        assert_eq!(observations.notify("relays/{id}/state"), 4);
+        assert_eq!(observations.notify("relays/4/state"), 0);
+    }
+}
@@ -0,0 +1,360 @@
+//! Matching of request paths against resource path templates.
+//!
+//! A template is a `/` separated path, its segments are matched against the
+//! Uri-Path options of a request. A segment is either
+//! - a literal, matching the same segment only
+//! - `{name}`, matching any segment and capturing it as the parameter `name`
+//! - `*`, matching any segment
+//! - `**`, the last segment only, matching the remaining segments, none included
+//!
+//! The empty template is the root path `/`, it matches requests without Uri-Path options.
+//! A template can end with a query, for example `lights?format=json&dim`.
+//! A `key=value` item requires a Uri-Query option `key=value` in the request,
+//! a `key` item requires a Uri-Query option with that key, whatever its value.
+//!
+//! When several templates match a request the longest match wins, the one matching
+//! the most segments without `**`, then the one with the most literal segments,
+//! then the one with the most query items.
+//! Templates that are equally specific are tried in the order they were added.
+
+use crate::message::option::CoapOptionNumbers;
+use crate::message::{CoapMessageRef, CoapOptionIter};
+use crate::CoapError;
+use heapless::Vec;
+
+/// Maximum number of `{name}` parameters in a path template
+pub const MAX_PATH_PARAMS: usize = 4;
+
+/// The parameters captured from a request path by the `{name}` segments of a template
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CoapPathParams<'a> {
+    params: Vec<(&'a str, &'a str), MAX_PATH_PARAMS>,
+    rest: Option<usize>,
+}
+
+impl<'a> CoapPathParams<'a> {
+    /// Returns the value of the parameter `name`
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.params
+            .iter()
+            .find(|(param, _)| *param == name)
+            .map(|(_, value)| *value)
+    }
+
+    /// Returns an iterator over the parameter names and values, in template order
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> + '_ {
+        self.params.iter().copied()
+    }
+
+    /// Returns the number of parameters
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    /// Returns true if the template has no parameters
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    /// Returns the index of the first request path segment matched by a trailing `**`,
+    /// `None` if the template does not end with `**`
+    pub fn get_rest_index(&self) -> Option<usize> {
+        self.rest
+    }
+}
+
+/// How well a template matches a request path, the greater the better
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Specificity {
+    segments: usize,
+    literals: usize,
+    queries: usize,
+}
+
+/// Checks that a path template is well formed, see the module documentation
+pub(crate) fn validate(template: &str) -> Result<(), CoapError> {
+    let (template, query) = split_query(template);
+    if let Some(query) = query {
+        if query.split('&').any(|item| split_item(item).0.is_empty()) {
+            return Err(CoapError::InvalidPathTemplate);
+        }
+    }
+    let mut params = 0;
+    let mut segments = template.split('/').peekable();
+    while let Some(segment) = segments.next() {
+        match Segment::parse(segment)? {
+            Segment::Rest if segments.peek().is_some() => {
+                return Err(CoapError::InvalidPathTemplate)
+            }
+            Segment::Param(_) => params += 1,
+            _ => {}
+        }
+    }
+    if params > MAX_PATH_PARAMS {
+        return Err(CoapError::CapacityExceeded {
+            capacity: MAX_PATH_PARAMS,
+        });
+    }
+    Ok(())
+}
+
+/// Returns true if the template is a plain path, without parameters, wildcards or query
+pub(crate) fn is_plain(template: &str) -> bool {
+    !template.contains('?')
+        && template
+            .split('/')
+            .all(|segment| matches!(Segment::parse(segment), Ok(Segment::Literal(_))))
+}
+
+/// Matches the Uri-Path options of a request against a validated template
+pub(crate) fn match_path<'a>(
+    template: &'a str,
+    msg: &CoapMessageRef<'a>,
+) -> Option<(Specificity, CoapPathParams<'a>)> {
+    let (template, query) = split_query(template);
+    let mut specificity = Specificity {
+        segments: 0,
+        literals: 0,
+        queries: 0,
+    };
+    if let Some(query) = query {
+        for item in query.split('&') {
+            let item = split_item(item);
+            if !CoapQueryIter::new(msg.get_options())
+                .any(|param| param == item || item.1.is_none() && param.0 == item.0)
+            {
+                return None;
+            }
+            specificity.queries += 1;
+        }
+    }
+    let mut params = CoapPathParams::default();
+    let mut path = path_segments(msg.get_options());
+    // The empty template is the root path, a request without Uri-Path options
+    let segments = (!template.is_empty()).then(|| template.split('/'));
+    for segment in segments.into_iter().flatten() {
+        let segment = Segment::parse(segment).ok()?;
+        if segment == Segment::Rest {
+            params.rest = Some(specificity.segments);
+            return Some((specificity, params));
+        }
+        let value = path.next()?;
+        match segment {
+            Segment::Literal(literal) if literal.as_bytes() == value => specificity.literals += 1,
+            Segment::Literal(_) => return None,
+            Segment::Param(name) => {
+                let value = core::str::from_utf8(value).ok()?;
+                params.params.push((name, value)).ok()?;
+            }
+            Segment::Any | Segment::Rest => {}
+        }
+        specificity.segments += 1;
+    }
+    match path.next() {
+        Some(_) => None,
+        None => Some((specificity, params)),
+    }
+}
+
+/// Returns an iterator over the Uri-Path segments of a request
+pub(crate) fn path_segments(options: CoapOptionIter<'_>) -> impl Iterator<Item = &'_ [u8]> {
+    options
+        .filter(|opt| opt.get_option_number() == CoapOptionNumbers::UriPath)
+        .map(|opt| opt.get_option_data())
+}
+
+/// Iterator over the Uri-Query options of a request as `(key, value)` pairs.
+/// The value is `None` for options without `=`, options that are not UTF-8 are skipped
+#[derive(Debug, Clone)]
+pub struct CoapQueryIter<'a> {
+    options: CoapOptionIter<'a>,
+}
+
+impl<'a> CoapQueryIter<'a> {
+    pub(crate) fn new(options: CoapOptionIter<'a>) -> Self {
+        CoapQueryIter { options }
+    }
+}
+
+impl<'a> Iterator for CoapQueryIter<'a> {
+    type Item = (&'a str, Option<&'a str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.options
+            .by_ref()
+            .filter(|opt| opt.get_option_number() == CoapOptionNumbers::UriQuery)
+            .find_map(|opt| opt.get_str().ok())
+            .map(split_item)
+    }
+}
+
+/// Splits a template into its path and query
+fn split_query(template: &str) -> (&str, Option<&str>) {
+    match template.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (template, None),
+    }
+}
+
+/// Splits a query item into its key and value
+fn split_item(item: &str) -> (&str, Option<&str>) {
+    match item.split_once('=') {
+        Some((key, value)) => (key, Some(value)),
+        None => (item, None),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Segment<'a> {
+    Literal(&'a str),
+    Param(&'a str),
+    Any,
+    Rest,
+}
+
+impl<'a> Segment<'a> {
+    fn parse(segment: &'a str) -> Result<Self, CoapError> {
+        match segment {
+            "*" => Ok(Segment::Any),
+            "**" => Ok(Segment::Rest),
+            _ if segment.starts_with('{') || segment.ends_with('}') => {
+                match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                    Some(name) if !name.is_empty() && !name.contains(['{', '}']) => {
+                        Ok(Segment::Param(name))
+                    }
+                    _ => Err(CoapError::InvalidPathTemplate),
+                }
+            }
+            _ => Ok(Segment::Literal(segment)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::header::CoapHeaderCode;
+    use crate::message::option::CoapOption;
+    use crate::message::CoapMessage;
+    use crate::router::*;
+
+    fn matches(template: &str, path: &str) -> Option<(Specificity, usize)> {
+        let (path, query) = split_query(path);
+        let mut builder = CoapMessage::builder(CoapHeaderCode::GET).path(path);
+        for item in query.into_iter().flat_map(|query| query.split('&')) {
+            builder = builder.query(item);
+        }
+        let msg = builder.build().unwrap().encode().unwrap();
+        let msg = CoapMessageRef::decode(&msg.0[..msg.1]).unwrap();
+        match_path(template, &msg).map(|(specificity, params)| (specificity, params.len()))
+    }
+
+    #[test]
+    fn validate_templates() {
+        assert_eq!(validate("actuators/{id}/state"), Ok(()));
+        assert_eq!(validate("files/*/**"), Ok(()));
+        assert_eq!(validate("**"), Ok(()));
+        assert_eq!(validate("a/**/b"), Err(CoapError::InvalidPathTemplate));
+        assert_eq!(validate("a/{}"), Err(CoapError::InvalidPathTemplate));
+        assert_eq!(validate("a/{id"), Err(CoapError::InvalidPathTemplate));
+        assert_eq!(validate("a/x{id}"), Err(CoapError::InvalidPathTemplate));
+        assert_eq!(validate("a?b=1&c"), Ok(()));
+        assert_eq!(validate("a?b=1&"), Err(CoapError::InvalidPathTemplate));
+        assert_eq!(validate("a?=1"), Err(CoapError::InvalidPathTemplate));
+        assert!(is_plain("a/b"));
+        assert!(!is_plain("a/{b}"));
+        assert!(!is_plain("a/**"));
+        assert!(!is_plain("a?b"));
+        assert_eq!(
+            validate("{a}/{b}/{c}/{d}/{e}"),
+            Err(CoapError::CapacityExceeded {
+                capacity: MAX_PATH_PARAMS
+            })
+        );
+    }
+
+    #[test]
+    fn params() {
+        let msg = CoapMessage::get()
+            .path("actuators/12/state")
+            .build()
+            .unwrap()
+            .encode()
+            .unwrap();
+        let msg = CoapMessageRef::decode(&msg.0[..msg.1]).unwrap();
+        let (_, params) = match_path("{kind}/{id}/state", &msg).unwrap();
+        assert_eq!(params.get("kind"), Some("actuators"));
+        assert_eq!(params.get("id"), Some("12"));
+        assert_eq!(params.get("state"), None);
+        let mut iter = params.iter();
+        assert_eq!(iter.next(), Some(("kind", "actuators")));
+        assert_eq!(iter.next(), Some(("id", "12")));
+        assert_eq!(iter.next(), None);
+        assert_eq!(params.get_rest_index(), None);
+
+        let (_, params) = match_path("actuators/**", &msg).unwrap();
+        assert!(params.is_empty());
+        assert_eq!(params.get_rest_index(), Some(1));
+    }
+
+    #[test]
+    fn matching() {
+        assert!(matches("a/b", "a/b").is_some());
+        assert!(matches("a/b", "a").is_none());
+        assert!(matches("a/b", "a/b/c").is_none());
+        assert!(matches("a/*", "a/b").is_some());
+        assert!(matches("a/*", "a").is_none());
+        assert!(matches("a/*", "a/b/c").is_none());
+        assert!(matches("a/**", "a").is_some());
+        assert!(matches("a/**", "a/b/c").is_some());
+        assert!(matches("a/**", "b/c").is_none());
+        assert!(matches("**", "").is_some());
+        assert!(matches("", "").is_some());
+        assert!(matches("", "a").is_none());
+        assert!(matches("?x", "?x").is_some());
+        assert!(matches("a?x=1", "a?y=2&x=1").is_some());
+        assert!(matches("a?x=1", "a?x=2").is_none());
+        assert!(matches("a?x=1", "a").is_none());
+        assert!(matches("a?x", "a?x=2").is_some());
+        assert!(matches("a?x", "a?x").is_some());
+        assert!(matches("a?x=", "a?x").is_none());
+        assert!(matches("a?x&y", "a?x").is_none());
+        assert!(matches("a", "a?x").is_some());
+    }
+
+    #[test]
+    fn query_iter() {
+        let msg = CoapMessage::get()
+            .path("a")
+            .query("unit=c")
+            .query("verbose")
+            .query("expr=a=b")
+            .option(CoapOption::opaque(CoapOptionNumbers::UriQuery, &[0xff]).unwrap())
+            .query("x=")
+            .build()
+            .unwrap()
+            .encode()
+            .unwrap();
+        let msg = CoapMessageRef::decode(&msg.0[..msg.1]).unwrap();
+        let mut query = CoapQueryIter::new(msg.get_options());
+        assert_eq!(query.next(), Some(("unit", Some("c"))));
+        assert_eq!(query.next(), Some(("verbose", None)));
+        assert_eq!(query.next(), Some(("expr", Some("a=b"))));
+        assert_eq!(query.next(), Some(("x", Some(""))));
+        assert_eq!(query.next(), None);
+    }
+
+    #[test]
+    fn longest_match() {
+        let literal = matches("a/b/c", "a/b/c").unwrap().0;
+        let param = matches("a/{x}/c", "a/b/c").unwrap().0;
+        let any = matches("a/*/c", "a/b/c").unwrap().0;
+        let rest = matches("a/b/**", "a/b/c").unwrap().0;
+        let short_rest = matches("a/**", "a/b/c").unwrap().0;
+        assert!(literal > param);
+        assert_eq!(param, any);
+        assert!(param > rest);
+        assert!(rest > short_rest);
+        let query = matches("a/b/c?x", "a/b/c?x").unwrap().0;
+        assert!(query > literal);
+    }
+}
@@ -0,0 +1,553 @@
+//! CoAP URIs, RFC 7252 §6 and RFC 8323 §8.
+//!
+//! [`CoapUri::parse`] splits a URI into its components without copying,
+//! [`CoapUri::to_options`] decomposes it into request options (RFC 7252 §6.4)
+//! and [`compose`] turns the options of a request back into a URI (RFC 7252 §6.5).
+
+use crate::message::option::{CoapOption, CoapOptionNumbers, CoapOptions};
+use crate::CoapError;
+use core::fmt::Write;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use core::str::FromStr;
+use heapless::{String, Vec};
+
+/// URI schemes for CoAP over UDP, DTLS, TCP and WebSockets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoapScheme {
+    /// coap, CoAP over UDP
+    Coap,
+    /// coaps, CoAP over DTLS
+    Coaps,
+    /// coap+tcp, CoAP over TCP
+    CoapTcp,
+    /// coap+ws, CoAP over WebSockets
+    CoapWs,
+}
+
+impl CoapScheme {
+    /// Returns the port used when a URI does not name one
+    pub fn default_port(&self) -> u16 {
+        match self {
+            CoapScheme::Coap | CoapScheme::CoapTcp => 5683,
+            CoapScheme::Coaps => 5684,
+            CoapScheme::CoapWs => 80,
+        }
+    }
+
+    /// Returns the scheme name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CoapScheme::Coap => "coap",
+            CoapScheme::Coaps => "coaps",
+            CoapScheme::CoapTcp => "coap+tcp",
+            CoapScheme::CoapWs => "coap+ws",
+        }
+    }
+
+    /// Schemes are case insensitive, RFC 3986 §3.1
+    fn parse(scheme: &str) -> Option<Self> {
+        [
+            CoapScheme::Coap,
+            CoapScheme::Coaps,
+            CoapScheme::CoapTcp,
+            CoapScheme::CoapWs,
+        ]
+        .iter()
+        .copied()
+        .find(|s| s.as_str().eq_ignore_ascii_case(scheme))
+    }
+}
+
+/// The host of a URI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoapHost<'a> {
+    /// A registered name, still percent-encoded
+    Name(&'a str),
+    /// An IPv4 address or an IPv6 literal
+    Ip(IpAddr),
+}
+
+/// A parsed CoAP URI, borrowing from the URI string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoapUri<'a> {
+    scheme: CoapScheme,
+    host: CoapHost<'a>,
+    port: Option<u16>,
+    path: &'a str,
+    query: Option<&'a str>,
+}
+
+impl<'a> CoapUri<'a> {
+    /// Parses an absolute CoAP URI, for example `coap://[fe80::1]:5683/a/b?x=1`.
+    /// URIs with another scheme, a fragment or user information are rejected
+    pub fn parse(uri: &'a str) -> Result<Self, CoapError> {
+        let colon = uri.find(':').ok_or(CoapError::InvalidUri)?;
+        let scheme = CoapScheme::parse(&uri[..colon]).ok_or(CoapError::InvalidUri)?;
+        let rest = uri[colon + 1..]
+            .strip_prefix("//")
+            .ok_or(CoapError::InvalidUri)?;
+        if rest.contains('#') {
+            return Err(CoapError::InvalidUri);
+        }
+        let (rest, query) = match rest.find('?') {
+            Some(index) => (&rest[..index], Some(&rest[index + 1..])),
+            None => (rest, None),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        let (host, port) = parse_authority(authority)?;
+        check_encoded(path, |c| is_pchar(c) || c == b'/')?;
+        if let Some(query) = query {
+            check_encoded(query, |c| is_pchar(c) || c == b'/' || c == b'?')?;
+        }
+
+        Ok(CoapUri {
+            scheme,
+            host,
+            port,
+            path,
+            query,
+        })
+    }
+
+    /// Returns the scheme
+    pub fn get_scheme(&self) -> CoapScheme {
+        self.scheme
+    }
+
+    /// Returns the host
+    pub fn get_host(&self) -> CoapHost<'a> {
+        self.host
+    }
+
+    /// Returns the port, or the default port of the scheme if the URI does not name one
+    pub fn get_port(&self) -> u16 {
+        self.port.unwrap_or_else(|| self.scheme.default_port())
+    }
+
+    /// Returns the percent-encoded path, empty or starting with `/`
+    pub fn get_path(&self) -> &'a str {
+        self.path
+    }
+
+    /// Returns the percent-encoded query without the `?`, if any
+    pub fn get_query(&self) -> Option<&'a str> {
+        self.query
+    }
+
+    /// Adds the Uri-Host, Uri-Port, Uri-Path and Uri-Query options of a request to the URI
+    /// sent to the destination port, following RFC 7252 §6.4.
+    /// The request is expected to be sent to the host of the URI, so an IP address host is
+    /// not repeated in a Uri-Host option. Uri-Port is only added if the port of the URI is
+    /// not the destination port
+    pub fn to_options<const MAX_OPTIONS: usize, const OPTION_SIZE: usize>(
+        &self,
+        destination_port: u16,
+        options: &mut CoapOptions<MAX_OPTIONS, OPTION_SIZE>,
+    ) -> Result<(), CoapError> {
+        if let CoapHost::Name(host) = self.host {
+            push_decoded(options, CoapOptionNumbers::UriHost, host, true)?;
+        }
+        if self.get_port() != destination_port {
+            options.push(CoapOption::uint(
+                CoapOptionNumbers::UriPort,
+                self.get_port() as u32,
+            )?)?;
+        }
+        if !self.path.is_empty() && self.path != "/" {
+            for segment in self.path[1..].split('/') {
+                push_decoded(options, CoapOptionNumbers::UriPath, segment, false)?;
+            }
+        }
+        match self.query {
+            Some(query) if !query.is_empty() => {
+                for argument in query.split('&') {
+                    push_decoded(options, CoapOptionNumbers::UriQuery, argument, false)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Composes the URI of a request from its options, following RFC 7252 §6.5.
+/// The destination address and port of the request are used when it carries
+/// no Uri-Host or Uri-Port option, the default port of the scheme is left out
+pub fn compose<const N: usize, const MAX_OPTIONS: usize, const OPTION_SIZE: usize>(
+    scheme: CoapScheme,
+    destination: IpAddr,
+    destination_port: u16,
+    options: &CoapOptions<MAX_OPTIONS, OPTION_SIZE>,
+    uri: &mut String<N>,
+) -> Result<(), CoapError> {
+    let full = CoapError::CapacityExceeded { capacity: N };
+    let find = |number: CoapOptionNumbers| options.iter().find(|o| o.get_option_number() == number);
+
+    uri.push_str(scheme.as_str()).map_err(|_| full)?;
+    uri.push_str("://").map_err(|_| full)?;
+    match find(CoapOptionNumbers::UriHost) {
+        Some(host) => {
+            let host = host.get_opaque();
+            match core::str::from_utf8(host).ok().map(Ipv6Addr::from_str) {
+                Some(Ok(ip)) => write_ip(uri, IpAddr::V6(ip))?,
+                _ => push_encoded(uri, host, is_reg_name)?,
+            }
+        }
+        None => write_ip(uri, destination)?,
+    }
+
+    let port = match find(CoapOptionNumbers::UriPort) {
+        Some(port) => port.get_uint()?,
+        None => destination_port as u32,
+    };
+    if port != scheme.default_port() as u32 {
+        write!(uri, ":{}", port).map_err(|_| full)?;
+    }
+
+    let mut path = options
+        .iter()
+        .filter(|o| o.get_option_number() == CoapOptionNumbers::UriPath)
+        .peekable();
+    if path.peek().is_none() {
+        uri.push('/').map_err(|_| full)?;
+    }
+    for segment in path {
+        uri.push('/').map_err(|_| full)?;
+        push_encoded(uri, segment.get_opaque(), is_pchar)?;
+    }
+
+    let query = options
+        .iter()
+        .filter(|o| o.get_option_number() == CoapOptionNumbers::UriQuery);
+    for (index, argument) in query.enumerate() {
+        uri.push(if index == 0 { '?' } else { '&' })
+            .map_err(|_| full)?;
+        push_encoded(uri, argument.get_opaque(), |c| {
+            (is_pchar(c) || c == b'/' || c == b'?') && c != b'&'
+        })?;
+    }
+    Ok(())
+}
+
+/// Splits the authority into the host and the port, `host [ ":" port ]`
+fn parse_authority(authority: &str) -> Result<(CoapHost<'_>, Option<u16>), CoapError> {
+    if authority.contains('@') {
+        return Err(CoapError::InvalidUri);
+    }
+    let (host, port) = if let Some(literal) = authority.strip_prefix('[') {
+        let end = literal.find(']').ok_or(CoapError::InvalidUri)?;
+        let ip = Ipv6Addr::from_str(&literal[..end]).map_err(|_| CoapError::InvalidUri)?;
+        (CoapHost::Ip(IpAddr::V6(ip)), &literal[end + 1..])
+    } else {
+        let (host, port) = match authority.rfind(':') {
+            Some(index) => authority.split_at(index),
+            None => (authority, ""),
+        };
+        let host = match Ipv4Addr::from_str(host) {
+            Ok(ip) => CoapHost::Ip(IpAddr::V4(ip)),
+            Err(_) if !host.is_empty() => {
+                check_encoded(host, is_reg_name)?;
+                CoapHost::Name(host)
+            }
+            Err(_) => return Err(CoapError::InvalidUri),
+        };
+        (host, port)
+    };
+
+    let port = match port {
+        "" | ":" => None,
+        _ => {
+            let digits = port.strip_prefix(':').ok_or(CoapError::InvalidUri)?;
+            if !digits.bytes().all(|c| c.is_ascii_digit()) {
+                return Err(CoapError::InvalidUri);
+            }
+            Some(u16::from_str(digits).map_err(|_| CoapError::InvalidUri)?)
+        }
+    };
+    Ok((host, port))
+}
+
+/// unreserved, RFC 3986 §2.3
+fn is_unreserved(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, b'-' | b'.' | b'_' | b'~')
+}
+
+/// sub-delims, RFC 3986 §2.2
+fn is_sub_delim(c: u8) -> bool {
+    matches!(
+        c,
+        b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'='
+    )
+}
+
+/// reg-name characters other than percent-encodings, RFC 3986 §3.2.2
+fn is_reg_name(c: u8) -> bool {
+    is_unreserved(c) || is_sub_delim(c)
+}
+
+/// pchar characters other than percent-encodings, RFC 3986 §3.3
+fn is_pchar(c: u8) -> bool {
+    is_unreserved(c) || is_sub_delim(c) || c == b':' || c == b'@'
+}
+
+/// Checks that the string only has allowed characters and valid percent-encodings
+fn check_encoded(s: &str, allowed: impl Fn(u8) -> bool) -> Result<(), CoapError> {
+    let bytes = s.as_bytes();
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            hex_byte(bytes.get(index + 1..index + 3))?;
+            index += 3;
+        } else if allowed(bytes[index]) {
+            index += 1;
+        } else {
+            return Err(CoapError::InvalidUri);
+        }
+    }
+    Ok(())
+}
+
+fn hex_byte(digits: Option<&[u8]>) -> Result<u8, CoapError> {
+    let digits = digits.ok_or(CoapError::InvalidUri)?;
+    let digits = core::str::from_utf8(digits).map_err(|_| CoapError::InvalidUri)?;
+    if !digits.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(CoapError::InvalidUri);
+    }
+    u8::from_str_radix(digits, 16).map_err(|_| CoapError::InvalidUri)
+}
+
+/// Percent-decodes a URI component into an option and adds it to the options.
+/// Hosts are converted to lowercase first
+fn push_decoded<const MAX_OPTIONS: usize, const OPTION_SIZE: usize>(
+    options: &mut CoapOptions<MAX_OPTIONS, OPTION_SIZE>,
+    number: CoapOptionNumbers,
+    component: &str,
+    lowercase: bool,
+) -> Result<(), CoapError> {
+    let full = CoapError::CapacityExceeded {
+        capacity: OPTION_SIZE,
+    };
+    let bytes = component.as_bytes();
+    let mut value: Vec<u8, OPTION_SIZE> = Vec::new();
+    let mut index = 0;
+    while index < bytes.len() {
+        let c = if bytes[index] == b'%' {
+            index += 3;
+            hex_byte(bytes.get(index - 2..index))?
+        } else {
+            index += 1;
+            bytes[index - 1]
+        };
+        let c = if lowercase { c.to_ascii_lowercase() } else { c };
+        value.push(c).map_err(|_| full)?;
+    }
+    let value = core::str::from_utf8(&value).map_err(|_| CoapError::InvalidUri)?;
+    options.push(CoapOption::string(number, value)?)
+}
+
+/// Appends the value, percent-encoding every byte that is not allowed
+fn push_encoded<const N: usize>(
+    uri: &mut String<N>,
+    value: &[u8],
+    allowed: impl Fn(u8) -> bool,
+) -> Result<(), CoapError> {
+    let full = CoapError::CapacityExceeded { capacity: N };
+    for c in value.iter().copied() {
+        if allowed(c) {
+            uri.push(c as char).map_err(|_| full)?;
+        } else {
+            write!(uri, "%{:02X}", c).map_err(|_| full)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_ip<const N: usize>(uri: &mut String<N>, ip: IpAddr) -> Result<(), CoapError> {
+    match ip {
+        IpAddr::V4(ip) => write!(uri, "{}", ip),
+        IpAddr::V6(ip) => write!(uri, "[{}]", ip),
+    }
+    .map_err(|_| CoapError::CapacityExceeded { capacity: N })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::uri::*;
+
+    fn options(uri: &str) -> CoapOptions {
+        let mut options = CoapOptions::new();
+        CoapUri::parse(uri)
+            .unwrap()
+            .to_options(5683, &mut options)
+            .unwrap();
+        options
+    }
+
+    fn uri(options: &CoapOptions, destination: IpAddr, port: u16) -> String<64> {
+        let mut uri = String::new();
+        compose(CoapScheme::Coap, destination, port, options, &mut uri).unwrap();
+        uri
+    }
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+
+    #[test]
+    fn parse() {
+        let uri = CoapUri::parse("coap://[fe80::1]:5683/a/b?x=1").unwrap();
+        assert_eq!(uri.get_scheme(), CoapScheme::Coap);
+        assert_eq!(
+            uri.get_host(),
+            CoapHost::Ip(IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1)))
+        );
+        assert_eq!(uri.get_port(), 5683);
+        assert_eq!(uri.get_path(), "/a/b");
+        assert_eq!(uri.get_query(), Some("x=1"));
+
+        let uri = CoapUri::parse("COAPS://192.168.0.1").unwrap();
+        assert_eq!(uri.get_scheme(), CoapScheme::Coaps);
+        assert_eq!(
+            uri.get_host(),
+            CoapHost::Ip(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)))
+        );
+        assert_eq!(uri.get_port(), 5684);
+        assert_eq!(uri.get_path(), "");
+        assert_eq!(uri.get_query(), None);
+
+        let uri = CoapUri::parse("coap+tcp://example.com:1234/").unwrap();
+        assert_eq!(uri.get_scheme(), CoapScheme::CoapTcp);
+        assert_eq!(uri.get_host(), CoapHost::Name("example.com"));
+        assert_eq!(uri.get_port(), 1234);
+        assert_eq!(CoapUri::parse("coap+ws://h").unwrap().get_port(), 80);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for uri in [
+            "http://example.com/",
+            "coap:example.com",
+            "/a/b",
+            "coap://example.com/a#frag",
+            "coap://user@example.com/",
+            "coap://example.com:65536/",
+            "coap://example.com:+1/",
+            "coap://example.com/%zz",
+            "coap://example.com/%4",
+            "coap://example.com/a b",
+            "coap://[fe80::1/",
+            "coap://[example]/",
+            "coap://:5683/",
+        ] {
+            assert_eq!(CoapUri::parse(uri), Err(CoapError::InvalidUri), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn decompose() {
+        let options = options("coap://example.com:5683/~sensors/temp.xml?a=1&b=%262");
+        let mut iter = options.iter();
+        let mut next = |number| {
+            let option = iter.next().unwrap();
+            assert_eq!(option.get_option_number(), number);
+            option.get_str().unwrap()
+        };
+        assert_eq!(next(CoapOptionNumbers::UriHost), "example.com");
+        assert_eq!(next(CoapOptionNumbers::UriPath), "~sensors");
+        assert_eq!(next(CoapOptionNumbers::UriPath), "temp.xml");
+        assert_eq!(next(CoapOptionNumbers::UriQuery), "a=1");
+        assert_eq!(next(CoapOptionNumbers::UriQuery), "b=&2");
+
+        // Equivalent URIs from RFC 7252 §6.3
+        assert_eq!(
+            options,
+            crate::uri::tests::options("coap://EXAMPLE.com/%7Esensors/temp.xml?a=1&b=%262")
+        );
+        assert_eq!(
+            options,
+            crate::uri::tests::options("coap://EXAMPLE.com:/%7esensors/temp.xml?a=1&b=%262")
+        );
+    }
+
+    #[test]
+    fn decompose_path() {
+        // No path and the root path have no Uri-Path options
+        assert!(options("coap://[::1]").is_empty());
+        assert!(options("coap://[::1]/").is_empty());
+        assert!(options("coap://[::1]/?").is_empty());
+        // Empty segments are kept
+        let options = options("coap://[::1]/a//");
+        let segments: Vec<&str, 4> = options.iter().map(|o| o.get_str().unwrap()).collect();
+        assert_eq!(&segments[..], &["a", "", ""]);
+        // Decoded values have to be UTF-8
+        let mut options = CoapOptions::new();
+        let uri = CoapUri::parse("coap://[::1]/%ff").unwrap();
+        assert_eq!(
+            uri.to_options(5683, &mut options),
+            Err(CoapError::InvalidUri)
+        );
+    }
+
+    #[test]
+    fn decompose_port() {
+        // Uri-Port is added when the URI port is not the destination port
+        let options = options("coap://example.com:1234/a");
+        let numbers: Vec<CoapOptionNumbers, 4> =
+            options.iter().map(|o| o.get_option_number()).collect();
+        assert_eq!(
+            &numbers[..],
+            &[
+                CoapOptionNumbers::UriHost,
+                CoapOptionNumbers::UriPort,
+                CoapOptionNumbers::UriPath
+            ]
+        );
+        assert_eq!(options.iter().nth(1).unwrap().get_uint(), Ok(1234));
+
+        let mut options = CoapOptions::new();
+        let uri = CoapUri::parse("coap://[::1]/a").unwrap();
+        uri.to_options(1234, &mut options).unwrap();
+        assert_eq!(options.iter().next().unwrap().get_uint(), Ok(5683));
+        // The default port of the scheme is not added when sent to it
+        let mut options = CoapOptions::new();
+        uri.to_options(5683, &mut options).unwrap();
+        assert_eq!(options.len(), 1);
+    }
+
+    #[test]
+    fn compose_uri() {
+        let options = options("coap://example.com/~sensors/temp%20now?a=1&b=%262");
+        assert_eq!(
+            uri(&options, LOCALHOST, 5683),
+            "coap://example.com/~sensors/temp%20now?a=1&b=%262"
+        );
+        // The destination port is only added if it is not the default port
+        assert_eq!(
+            uri(&options, LOCALHOST, 1234),
+            "coap://example.com:1234/~sensors/temp%20now?a=1&b=%262"
+        );
+
+        // Without Uri-Host the destination address is used
+        let destination = IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1));
+        let options = crate::uri::tests::options("coap://[fe80::1]");
+        assert_eq!(uri(&options, destination, 5683), "coap://[fe80::1]/");
+        assert_eq!(uri(&options, LOCALHOST, 5683), "coap://127.0.0.1/");
+
+        // Uri-Port overrides the destination port
+        let mut options = CoapOptions::new();
+        options
+            .push(CoapOption::uint(CoapOptionNumbers::UriPort, 5683).unwrap())
+            .unwrap();
+        options
+            .push(CoapOption::string(CoapOptionNumbers::UriHost, "fe80::2").unwrap())
+            .unwrap();
+        assert_eq!(uri(&options, LOCALHOST, 1234), "coap://[fe80::2]/");
+
+        let mut small: String<8> = String::new();
+        assert_eq!(
+            compose(CoapScheme::Coap, LOCALHOST, 5683, &options, &mut small),
+            Err(CoapError::CapacityExceeded { capacity: 8 })
+        );
+    }
+}